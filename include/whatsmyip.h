@@ -0,0 +1,57 @@
+/*
+ * C bindings for the whatsmyip crate, built with the ffi feature:
+ *
+ *   cargo rustc --release --lib --features ffi --crate-type cdylib,staticlib
+ *
+ * and linked with target/release/libwhatsmyip.so or libwhatsmyip.a.
+ * Strings returned by the library are freed with
+ * whatsmyip_string_free().
+ */
+
+#ifndef WHATSMYIP_H
+#define WHATSMYIP_H
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Address families for whatsmyip_find() */
+#define WHATSMYIP_BOTH 0
+#define WHATSMYIP_V4 4
+#define WHATSMYIP_V6 6
+
+/* The addresses found by whatsmyip_find() */
+typedef struct whatsmyip_result whatsmyip_result;
+
+/*
+ * Find the external addresses of family with the default options,
+ * NULL on error, see whatsmyip_last_error(). Free the result with
+ * whatsmyip_result_free(). Blocks until the sources answer.
+ */
+whatsmyip_result *whatsmyip_find(int family);
+
+/* The number of addresses in res, 0 for NULL */
+size_t whatsmyip_result_len(const whatsmyip_result *res);
+
+/*
+ * The address at index as a string, e.g. "192.0.2.1", NULL if index
+ * is out of range
+ */
+char *whatsmyip_result_get(const whatsmyip_result *res, size_t index);
+
+/* Free a result from whatsmyip_find(), NULL is ignored */
+void whatsmyip_result_free(whatsmyip_result *res);
+
+/* The message of the last error in this thread, NULL if none */
+char *whatsmyip_last_error(void);
+
+/* Free a string from this library, NULL is ignored */
+void whatsmyip_string_free(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
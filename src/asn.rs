@@ -0,0 +1,229 @@
+//!
+//! The AS announcing an address, from the Team Cymru DNS interface,
+//! e.g. to tell if traffic leaves through the expected ISP or a VPN
+//!
+//! ```no_run
+//! use whatsmyip::{whatsmyip, Cymru};
+//! let ip = whatsmyip().unwrap();
+//! let asn = Cymru::new().lookup(&ip).unwrap();
+//! println!("AS{} {:?}", asn.asn, asn.name);
+//! ```
+//!
+
+use std::time::Duration;
+use {MyIp, Error, GeoBackend, GeoInfo};
+use dns;
+
+/// Origin zone for IPv4 addresses
+const ORIGIN_ZONE: &'static str = "origin.asn.cymru.com";
+/// Origin zone for IPv6 addresses
+const ORIGIN6_ZONE: &'static str = "origin6.asn.cymru.com";
+/// Zone with the AS holders
+const ASN_ZONE: &'static str = "asn.cymru.com";
+
+/// Well known cloud, hosting and VPN networks, see
+/// `is_vpn_or_datacenter()`
+pub const HOSTING_ASNS: &'static [(u32, &'static str)] = &[
+    (8075, "Microsoft"),
+    (9009, "M247"),
+    (12876, "Scaleway"),
+    (13335, "Cloudflare"),
+    (14061, "DigitalOcean"),
+    (14618, "Amazon"),
+    (15169, "Google"),
+    (16276, "OVH"),
+    (16509, "Amazon"),
+    (20473, "Vultr"),
+    (22612, "Namecheap"),
+    (24940, "Hetzner"),
+    (31898, "Oracle"),
+    (36352, "ColoCrossing"),
+    (39351, "31173 Services"),
+    (45102, "Alibaba"),
+    (51167, "Contabo"),
+    (53667, "FranTech"),
+    (60068, "Datacamp"),
+    (62240, "Clouvider"),
+    (63949, "Linode"),
+    (136787, "TEFINCOM"),
+    (209103, "Proton"),
+    (212238, "Datacamp"),
+    (396982, "Google Cloud"),
+];
+
+/// Words in an AS holder name that give away a hosting or VPN network
+const HOSTING_WORDS: &'static [&'static str] = &[
+    "HOSTING", "VPN", "VPS", "DATACENTER", "DATA CENTER", "CLOUD", "SERVER",
+];
+
+/// True if `asn` is in `HOSTING_ASNS`
+pub fn is_hosting_asn(asn: u32) -> bool {
+    HOSTING_ASNS.iter().any(|&(n, _)| n == asn)
+}
+
+/// The announcing AS of an address
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AsnInfo {
+    pub asn: u32,
+    /// The announced prefix holding the address
+    pub prefix: String,
+    /// Country code of the allocation
+    pub country: String,
+    /// Regional registry, e.g. ripencc
+    pub registry: String,
+    /// The AS holder, e.g. `GOOGLE - Google LLC, US`
+    pub name: Option<String>,
+}
+
+/// Looks up addresses with the Team Cymru IP to ASN service, over
+/// plain DNS so no API key or HTTP is needed
+pub struct Cymru {
+    server: String,
+    timeout: Duration,
+}
+
+/// The query name for `ip`, octets or nibbles reversed
+fn origin_name(ip: &MyIp) -> String {
+    let zone = if ip.is_ipv4() { ORIGIN_ZONE } else { ORIGIN6_ZONE };
+    format!("{}.{}", dns::reverse_labels(ip), zone)
+}
+
+/// The `|` separated fields of an answer
+fn fields(txt: &str) -> Vec<&str> {
+    txt.split('|').map(str::trim).collect()
+}
+
+/// Parse an origin answer, e.g. `15169 | 8.8.8.0/24 | US | arin | 2014-03-14`.
+/// Prefixes announced by several ASes list them all, we keep the first.
+fn parse_origin(txt: &str) -> Result<AsnInfo, Error> {
+    let fields = fields(txt);
+    let asn = fields.first()
+                .and_then(|asns| asns.split_whitespace().next())
+                .and_then(|asn| asn.parse().ok());
+    match (asn, fields.get(1), fields.get(2), fields.get(3)) {
+        (Some(asn), Some(prefix), Some(country), Some(registry)) => Ok(AsnInfo {
+            asn: asn,
+            prefix: prefix.to_string(),
+            country: country.to_string(),
+            registry: registry.to_string(),
+            name: None,
+        }),
+        _ => Err(Error::Parse(format!("Invalid Team Cymru answer: {}", txt))),
+    }
+}
+
+impl AsnInfo {
+    /// A guess at whether the address belongs to a hosting provider
+    /// or a VPN, from `HOSTING_ASNS` and the holder name. Residential
+    /// and mobile ISPs should say false, a VPN exit should say true.
+    pub fn is_vpn_or_datacenter(&self) -> bool {
+        if is_hosting_asn(self.asn) {
+            return true;
+        }
+        let name = self.name.as_ref().map(|name| name.to_uppercase()).unwrap_or_default();
+        HOSTING_WORDS.iter().any(|word| name.contains(word))
+    }
+}
+
+/// Look up the AS of `ip` with `Cymru`, see
+/// `AsnInfo::is_vpn_or_datacenter()`
+pub fn is_vpn_or_datacenter(ip: &MyIp) -> Result<bool, Error> {
+    Cymru::new().lookup(ip).map(|info| info.is_vpn_or_datacenter())
+}
+
+/// Length of a `a.b.c.d/len` prefix, the most specific one wins
+fn prefix_len(info: &AsnInfo) -> u8 {
+    info.prefix.rsplit('/').next().and_then(|len| len.parse().ok()).unwrap_or(0)
+}
+
+impl Cymru {
+    pub fn new() -> Cymru {
+        Cymru {
+            server: dns::DEFAULT_RESOLVER.to_owned(),
+            timeout: Duration::from_secs(3),
+        }
+    }
+
+    /// The resolver to ask, as host:port
+    /// (defaults to **OpenDNS**)
+    pub fn server(mut self, server: &str) -> Cymru {
+        self.server = server.to_owned();
+        self
+    }
+
+    /// (defaults to **3 seconds**)
+    pub fn timeout(mut self, timeout: Duration) -> Cymru {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The AS announcing `ip` and its holder
+    pub fn lookup(&self, ip: &MyIp) -> Result<AsnInfo, Error> {
+        let answers = try!(dns::lookup_txt(&self.server, &origin_name(ip), Some(self.timeout)));
+        let mut best: Option<AsnInfo> = None;
+        for txt in &answers {
+            let info = try!(parse_origin(txt));
+            if best.as_ref().map_or(true, |best| prefix_len(&info) > prefix_len(best)) {
+                best = Some(info);
+            }
+        }
+        let mut info = match best {
+            Some(info) => info,
+            None => return Err(Error::Parse(format!("No AS announces {}", ip))),
+        };
+        // The holder is a second query, not worth failing for
+        let name = format!("AS{}.{}", info.asn, ASN_ZONE);
+        match dns::lookup_txt(&self.server, &name, Some(self.timeout)) {
+            Ok(answers) => {
+                info.name = answers.first()
+                                .and_then(|txt| fields(txt).get(4).map(|name| name.to_string()));
+            },
+            Err(err) => info!("{} => {}", name, err),
+        }
+        debug!("{} => {:?}", ip, info);
+        Ok(info)
+    }
+}
+
+/// Fills in the country, ASN and ISP only
+impl GeoBackend for Cymru {
+    fn name(&self) -> &str {
+        "cymru"
+    }
+
+    fn lookup(&self, ip: &MyIp) -> Result<GeoInfo, Error> {
+        let info = try!(Cymru::lookup(self, ip));
+        Ok(GeoInfo {
+            country: Some(info.country),
+            asn: Some(info.asn),
+            isp: info.name,
+            ..GeoInfo::default()
+        })
+    }
+}
+
+#[test]
+fn test_cymru() {
+    use std::str::FromStr;
+    assert_eq!(origin_name(&MyIp::from_str("192.0.2.1").unwrap()),
+               "1.2.0.192.origin.asn.cymru.com");
+    assert!(origin_name(&MyIp::from_str("2001:db8::1").unwrap())
+                .starts_with("1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.origin6"));
+    let info = parse_origin("15169 23456 | 8.8.8.0/24 | US | arin | 2014-03-14").unwrap();
+    assert_eq!(info.asn, 15169);
+    assert_eq!(prefix_len(&info), 24);
+    assert!(parse_origin("garbage").is_err());
+}
+
+#[test]
+fn test_is_vpn_or_datacenter() {
+    let mut info = parse_origin("3320 | 192.0.2.0/24 | DE | ripencc | 2001-01-01").unwrap();
+    info.name = Some("DTAG Internet service provider operations, DE".to_owned());
+    assert!(!info.is_vpn_or_datacenter());
+    info.name = Some("EXAMPLE-VPN Example Privacy Ltd, CH".to_owned());
+    assert!(info.is_vpn_or_datacenter());
+    info.asn = 24940;
+    info.name = None;
+    assert!(info.is_vpn_or_datacenter());
+}
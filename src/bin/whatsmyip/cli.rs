@@ -0,0 +1,164 @@
+// The command line definition, also used for the shell completions
+// and the man page, see the hidden completions and man subcommands
+
+use clap::{App, AppSettings, Arg, Shell, SubCommand};
+
+pub fn app() -> App<'static, 'static> {
+    App::new("whatsmyip")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Find out your external IP address")
+        .arg(Arg::with_name("no-igd")
+                .long("no-igd")
+                .help("Do not ask the Internet Gateway Device"))
+        .arg(Arg::with_name("fast")
+                .long("fast")
+                .help("Stop at the first address found"))
+        .arg(Arg::with_name("timeout")
+                .long("timeout")
+                .value_name("SECS")
+                .help("Give up after SECS seconds, with the addresses found so far"))
+        .arg(Arg::with_name("http-limit")
+                .long("http-limit")
+                .value_name("N")
+                .default_value("1")
+                .help("Query at most N HTTP services, 0 for no limit"))
+        .arg(Arg::with_name("ipv4")
+                .short("4")
+                .conflicts_with("ipv6")
+                .help("Only look for IPv4 addresses, printed without a label"))
+        .arg(Arg::with_name("ipv6")
+                .short("6")
+                .help("Only look for IPv6 addresses, printed without a label"))
+        .arg(Arg::with_name("provider")
+                .long("provider")
+                .value_name("NAME")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Only use this provider and the --url services, can be repeated"))
+        .arg(Arg::with_name("url")
+                .long("url")
+                .value_name("URL")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Also ask this service that answers with the address as plain text, \
+                       https:// unless the URL has a scheme, tried before the others, \
+                       can be repeated"))
+        .arg(Arg::with_name("only-urls")
+                .long("only-urls")
+                .requires("url")
+                .help("Only ask the --url services, no IGD, STUN, DNS or built in providers"))
+        .arg(Arg::with_name("verbose")
+                .short("v")
+                .long("verbose")
+                .multiple(true)
+                .help("Log what is going on, -vv for debug and -vvv for trace messages"))
+        .arg(Arg::with_name("quiet")
+                .short("q")
+                .long("quiet")
+                .conflicts_with_all(&["verbose", "json", "format"])
+                .help("Print the addresses alone, no labels, logs or errors, \
+                       the exit status tells if it failed"))
+        .arg(Arg::with_name("json")
+                .long("json")
+                .help("Print a JSON object per answer, with its source and latency"))
+        .arg(Arg::with_name("format")
+                .long("format")
+                .value_name("TEMPLATE")
+                .conflicts_with("json")
+                .help("Print each answer as TEMPLATE, e.g. \"{ip} via {source} in {latency_ms}ms\", \
+                       the fields are address (or ip), family, scope, source, latency_ms, timestamp \
+                       and hostname (with --rdns)"))
+        .arg(Arg::with_name("rdns")
+                .long("rdns")
+                .help("Look up the host name of the addresses"))
+        .arg(Arg::with_name("geo")
+                .long("geo")
+                .conflicts_with_all(&["json", "format", "quiet"])
+                .help("Look up the country, city, ISP and ASN of the addresses"))
+        .arg(Arg::with_name("dnsbl")
+                .long("dnsbl")
+                .conflicts_with_all(&["json", "format", "quiet"])
+                .help("Check the IPv4 addresses against DNS blocklists"))
+        .arg(Arg::with_name("state")
+                .long("state")
+                .alias("state-file")
+                .value_name("FILE")
+                .help("Save the addresses to FILE and report if they changed"))
+        .arg(Arg::with_name("changed")
+                .long("changed")
+                .requires("state")
+                .help("Exit with 2 if the addresses differ from the ones in the state file, \
+                       or 0 if they are the same (3 if the state file cannot be read \
+                       or written)"))
+        .subcommand(SubCommand::with_name("daemon")
+                .about("Check the address periodically and run commands when it changes")
+                .arg(Arg::with_name("on-change")
+                        .long("on-change")
+                        .value_name("CMD")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("Run CMD with the shell on every change, {old} and {new} \
+                               are replaced by the addresses"))
+                .arg(Arg::with_name("interval")
+                        .long("interval")
+                        .value_name("SECS")
+                        .default_value("300")
+                        .help("Time between checks"))
+                .arg(Arg::with_name("pid-file")
+                        .long("pid-file")
+                        .value_name("FILE")
+                        .help("Write the process id to FILE"))
+                .arg(Arg::with_name("detach")
+                        .long("detach")
+                        .help("Run in the background, prints the process id")))
+        .subcommand(SubCommand::with_name("ddns")
+                .about("Push the address to a dynamic DNS service")
+                .arg(Arg::with_name("backend")
+                        .long("backend")
+                        .value_name("NAME")
+                        .possible_values(&["duckdns", "cloudflare", "dyndns2"])
+                        .required(true)
+                        .help("The dynamic DNS service"))
+                .arg(Arg::with_name("domain")
+                        .long("domain")
+                        .value_name("NAME")
+                        .required(true)
+                        .help("The name to update, DuckDNS takes the subdomain alone"))
+                .arg(Arg::with_name("token")
+                        .long("token")
+                        .value_name("TOKEN")
+                        .help("API token, for duckdns and cloudflare"))
+                .arg(Arg::with_name("zone")
+                        .long("zone")
+                        .value_name("ZONE")
+                        .help("Cloudflare zone (defaults to the domain without its first label)"))
+                .arg(Arg::with_name("url")
+                        .long("url")
+                        .value_name("URL")
+                        .help("dyndns2 update URL (defaults to Dyn)"))
+                .arg(Arg::with_name("username")
+                        .long("username")
+                        .value_name("USER")
+                        .help("dyndns2 user name"))
+                .arg(Arg::with_name("password")
+                        .long("password")
+                        .value_name("PASSWORD")
+                        .help("dyndns2 password"))
+                .arg(Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .help("Find the address and show the update without sending it")))
+        .subcommand(SubCommand::with_name("providers")
+                .about("List the providers")
+                .arg(Arg::with_name("test")
+                        .long("test")
+                        .help("Query each provider, with its status and latency")))
+        .subcommand(SubCommand::with_name("completions")
+                .setting(AppSettings::Hidden)
+                .about("Print the shell completion script")
+                .arg(Arg::with_name("shell")
+                        .possible_values(&Shell::variants())
+                        .required(true)))
+        .subcommand(SubCommand::with_name("man")
+                .setting(AppSettings::Hidden)
+                .about("Print the man page"))
+}
@@ -0,0 +1,448 @@
+// Keep the 2015 idioms (try!, ref patterns, explicit field names)
+// that newer lints would rewrite
+#![allow(deprecated, ellipsis_inclusive_range_patterns)]
+#![allow(clippy::redundant_field_names, clippy::redundant_static_lifetimes,
+         clippy::needless_borrowed_reference, clippy::match_ref_pats,
+         clippy::new_without_default, clippy::question_mark,
+         clippy::unnecessary_map_or, clippy::io_other_error)]
+
+extern crate whatsmyip;
+extern crate env_logger;
+#[macro_use] extern crate clap;
+extern crate log;
+extern crate rustc_serialize;
+
+mod cli;
+mod man;
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io::{self, Write};
+use std::process::{self, Command, Stdio};
+use std::time::{Duration, UNIX_EPOCH};
+use clap::{ArgMatches, Shell};
+use env_logger::LogBuilder;
+use log::LogLevelFilter;
+use rustc_serialize::json::Json;
+use whatsmyip::{Cloudflare, DnsblCheck, DuckDns, Dyndns2, GeoLookup, IpFamily, IpMonitor, IpReport, MyIp, Updater,
+                WhatsMyIp, State, Error};
+use whatsmyip::asn::is_hosting_asn;
+use whatsmyip::ddns::DYNDNS_URL;
+
+/// The fields of a report, for `--json` and `--format`
+fn report_fields(report: &IpReport) -> Vec<(&'static str, Json)> {
+    let latency = report.latency.as_secs() * 1000 + report.latency.subsec_millis() as u64;
+    let secs = report.timestamp.duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+    vec![
+        ("address", Json::String(report.ip.to_string())),
+        ("family", Json::String(if report.ip.is_ipv4() { "ipv4" } else { "ipv6" }.to_owned())),
+        ("scope", Json::String(format!("{:?}", report.scope))),
+        ("source", Json::String(format!("{:?}", report.source))),
+        ("latency_ms", Json::U64(latency)),
+        ("timestamp", Json::U64(secs)),
+        ("hostname", report.hostname.clone().map_or(Json::Null, Json::String)),
+    ]
+}
+
+/// One line of `--json` output
+fn report_json(report: &IpReport) -> String {
+    let obj: BTreeMap<String, Json> = report_fields(report).into_iter()
+                                        .map(|(name, val)| (name.to_owned(), val))
+                                        .collect();
+    Json::Object(obj).to_string()
+}
+
+/// `template` with each `{field}` replaced, `{ip}` is short for
+/// `{address}`
+fn report_format(template: &str, report: &IpReport) -> String {
+    let mut out = template.replace("{ip}", "{address}");
+    for (name, val) in report_fields(report) {
+        let val = match val {
+            Json::String(s) => s,
+            Json::Null => String::new(),
+            val => val.to_string(),
+        };
+        out = out.replace(&format!("{{{}}}", name), &val);
+    }
+    out
+}
+
+/// `--geo`, where `ip` appears to be
+fn print_geo(ip: &MyIp) {
+    let geo = match GeoLookup::new().lookup(ip) {
+        Ok(geo) => geo,
+        Err(err) => {
+            eprintln!("Unable to locate {}: {}", ip, err);
+            return;
+        },
+    };
+    println!("{}", ip);
+    let asn = geo.asn.map(|asn| format!("AS{}", asn));
+    for &(name, val) in &[("Country", &geo.country), ("Region", &geo.region),
+                          ("City", &geo.city), ("ISP", &geo.isp), ("ASN", &asn)] {
+        if let Some(ref val) = *val {
+            println!("  {}: {}", name, val);
+        }
+    }
+    if geo.asn.map_or(false, is_hosting_asn) {
+        println!("  Network: hosting or VPN");
+    }
+}
+
+/// `--dnsbl`, the blocklists listing `ip`
+fn print_dnsbl(ip: &MyIp) {
+    let results = match DnsblCheck::new().check(ip) {
+        Ok(results) => results,
+        Err(err) => {
+            eprintln!("Unable to check {}: {}", ip, err);
+            return;
+        },
+    };
+    let mut listed = false;
+    for res in &results {
+        match res.codes {
+            Ok(_) if res.is_listed() => {
+                listed = true;
+                println!("{} is listed in {}: {}", ip, res.list,
+                         res.reason.as_ref().map_or("no reason given", |r| &r[..]));
+            },
+            Ok(_) => (),
+            Err(ref err) => eprintln!("Unable to check {} in {}: {}", ip, res.list, err),
+        }
+    }
+    if !listed {
+        println!("{} is not listed in {} blocklists", ip, results.len());
+    }
+}
+
+/// The shell and the `--on-change` command with `{old}` and `{new}`
+/// replaced by the variables `ExecHook` sets
+#[cfg(unix)]
+fn on_change_command(cmd: &str) -> (&'static str, Vec<String>) {
+    let cmd = cmd.replace("{old}", "\"$WHATSMYIP_OLD_IP\"")
+                 .replace("{new}", "\"$WHATSMYIP_NEW_IP\"");
+    ("sh", vec!["-c".to_owned(), cmd])
+}
+
+#[cfg(windows)]
+fn on_change_command(cmd: &str) -> (&'static str, Vec<String>) {
+    let cmd = cmd.replace("{old}", "%WHATSMYIP_OLD_IP%")
+                 .replace("{new}", "%WHATSMYIP_NEW_IP%");
+    ("cmd", vec!["/C".to_owned(), cmd])
+}
+
+/// `whatsmyip daemon`, checks the address until killed
+fn daemon(opts: WhatsMyIp, state: Option<&str>, matches: &ArgMatches) {
+    if matches.is_present("detach") {
+        // Start again without --detach, with no terminal I/O, the
+        // daemon outlives us so nobody waits for it
+        let args = env::args_os().skip(1).filter(|arg| arg != "--detach");
+        #[allow(clippy::zombie_processes)]
+        let child = Command::new(env::current_exe().unwrap())
+                        .args(args)
+                        .stdin(Stdio::null())
+                        .stdout(Stdio::null())
+                        .stderr(Stdio::null())
+                        .spawn()
+                        .unwrap_or_else(|err| {
+                            eprintln!("Unable to start the daemon: {}", err);
+                            process::exit(1);
+                        });
+        println!("{}", child.id());
+        return;
+    }
+    if let Some(path) = matches.value_of("pid-file") {
+        if let Err(err) = File::create(path).and_then(|mut f| writeln!(f, "{}", process::id())) {
+            eprintln!("Unable to write {}: {}", path, err);
+            process::exit(1);
+        }
+    }
+
+    let mut monitor = IpMonitor::new(opts);
+    let interval = value_t!(matches, "interval", u64).unwrap_or_else(|err| err.exit());
+    monitor.interval(Duration::from_secs(interval));
+    if let Some(path) = state {
+        monitor.state_file(path);
+    }
+    for cmd in matches.values_of("on-change").into_iter().flatten() {
+        let (shell, args) = on_change_command(cmd);
+        let args: Vec<&str> = args.iter().map(|arg| &arg[..]).collect();
+        monitor.exec(shell, &args);
+    }
+    monitor.run(|ips| {
+        let ips: Vec<String> = ips.iter().map(|ip| ip.to_string()).collect();
+        println!("{}", ips.join(" "));
+    });
+}
+
+/// The `whatsmyip ddns` backend, from its arguments
+fn ddns_updater(matches: &ArgMatches) -> Box<dyn Updater> {
+    let domain = matches.value_of("domain").unwrap();
+    let required = |name: &str| -> &str {
+        matches.value_of(name).unwrap_or_else(|| {
+            eprintln!("--{} is required with --backend {}", name, matches.value_of("backend").unwrap());
+            process::exit(1);
+        })
+    };
+    match matches.value_of("backend").unwrap() {
+        "duckdns" => Box::new(DuckDns::new(domain, required("token"))),
+        "cloudflare" => {
+            // home.example.com is in the example.com zone
+            let zone = matches.value_of("zone")
+                        .unwrap_or_else(|| domain.split_once('.').map_or(domain, |(_, zone)| zone));
+            Box::new(Cloudflare::new(required("token"), zone, domain))
+        },
+        _ => Box::new(Dyndns2::new(matches.value_of("url").unwrap_or(DYNDNS_URL),
+                                   domain, required("username"), required("password"))),
+    }
+}
+
+/// `whatsmyip ddns`, finds the address and pushes it once
+fn ddns(opts: WhatsMyIp, matches: &ArgMatches) {
+    let updater = ddns_updater(matches);
+    let ips = match opts.find() {
+        Ok(ips) => ips,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        },
+    };
+    let names: Vec<String> = ips.iter().map(|ip| ip.to_string()).collect();
+    if matches.is_present("dry-run") {
+        println!("Would update {} to {}", updater.name(), names.join(" "));
+        return;
+    }
+    if let Err(err) = updater.update_all(&ips) {
+        eprintln!("Unable to update {}: {}", updater.name(), err);
+        process::exit(1);
+    }
+    println!("Updated {} to {}", updater.name(), names.join(" "));
+}
+
+/// `whatsmyip providers`, lists the providers and with `--test`
+/// queries each one
+fn providers(opts: WhatsMyIp, only: Option<Vec<String>>, matches: &ArgMatches) {
+    if !matches.is_present("test") {
+        for name in opts.provider_names() {
+            println!("{}", name);
+        }
+        return;
+    }
+    let results = opts.benchmark();
+    for res in &results {
+        let ms = res.latency.as_secs() * 1000 + res.latency.subsec_millis() as u64;
+        match res.result {
+            Ok(ref ip) => println!("{:<30} ok      {:>6}ms  {}", res.name, ms, ip),
+            Err(ref err) => println!("{:<30} failed  {:>6}ms  {}", res.name, ms, err),
+        }
+    }
+    // DNS providers are not part of the benchmark
+    for name in opts.provider_names() {
+        let selected = only.as_ref().map_or(true, |only| only.iter().any(|n| n == name));
+        if selected && !results.iter().any(|res| res.name == name) {
+            println!("{:<30} untested", name);
+        }
+    }
+}
+
+/// The name `add_https_provider()` gives the provider for `url`
+fn url_name(url: &str) -> String {
+    if url.contains("://") {
+        url.to_owned()
+    } else {
+        format!("https://{}", url)
+    }
+}
+
+/// The providers to keep with `--provider`, the `--url` services are
+/// named after their URL and are always kept
+fn only_providers(matches: &ArgMatches) -> Option<Vec<String>> {
+    matches.values_of("provider").map(|names| {
+        names.map(str::to_owned)
+            .chain(matches.values_of("url").into_iter().flatten().map(url_name))
+            .collect()
+    })
+}
+
+/// The state file cannot be read or written, exit with 3 (1 is for
+/// no address and 2 for a change)
+fn state_error(path: &str, err: Error, quiet: bool) -> ! {
+    if !quiet {
+        eprintln!("State file {}: {}", path, err);
+    }
+    process::exit(3);
+}
+
+fn main() {
+    let matches = cli::app().get_matches();
+    if let Some(sub) = matches.subcommand_matches("completions") {
+        let shell = value_t!(sub, "shell", Shell).unwrap_or_else(|err| err.exit());
+        cli::app().gen_completions_to("whatsmyip", shell, &mut io::stdout());
+        return;
+    }
+    if matches.subcommand_matches("man").is_some() {
+        print!("{}", man::man_page());
+        return;
+    }
+
+    let mut logger = LogBuilder::new();
+    if let Ok(filters) = env::var("RUST_LOG") {
+        logger.parse(&filters);
+    }
+    let quiet = matches.is_present("quiet");
+    match matches.occurrences_of("verbose") {
+        0 if quiet => { logger.filter(None, LogLevelFilter::Off); },
+        0 => (),
+        1 => { logger.filter(Some("whatsmyip"), LogLevelFilter::Info); },
+        2 => { logger.filter(Some("whatsmyip"), LogLevelFilter::Debug); },
+        _ => { logger.filter(Some("whatsmyip"), LogLevelFilter::Trace); },
+    }
+    logger.init().unwrap();
+
+    let mut opts = WhatsMyIp::new();
+    opts.igd(!matches.is_present("no-igd"))
+        .fast(matches.is_present("fast"))
+        .rdns(matches.is_present("rdns"));
+    match value_t!(matches, "http-limit", usize).unwrap_or_else(|err| err.exit()) {
+        0 => opts.http_limit(None),
+        n => opts.http_limit(Some(n)),
+    };
+    if matches.is_present("timeout") {
+        let secs = value_t!(matches, "timeout", f64).unwrap_or_else(|err| err.exit());
+        opts.deadline(Some(Duration::from_millis((secs * 1000.0) as u64)));
+    }
+    if matches.is_present("ipv4") {
+        opts.family(IpFamily::V4);
+    } else if matches.is_present("ipv6") {
+        opts.family(IpFamily::V6);
+    }
+    let only = only_providers(&matches);
+    if let Some(ref names) = only {
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        opts.only_providers(&names);
+    }
+    if matches.is_present("only-urls") {
+        opts.igd(false)
+            .stun(false)
+            .dns(false)
+            .dns_providers(&[])
+            .clear_providers();
+    }
+    for url in matches.values_of("url").into_iter().flatten() {
+        opts.add_https_provider(url);
+    }
+
+    if let Some(sub) = matches.subcommand_matches("daemon") {
+        daemon(opts, matches.value_of("state"), sub);
+        return;
+    }
+    if let Some(sub) = matches.subcommand_matches("ddns") {
+        ddns(opts, sub);
+        return;
+    }
+    if let Some(sub) = matches.subcommand_matches("providers") {
+        providers(opts, only, sub);
+        return;
+    }
+
+    let reports = match opts.find_detailed() {
+        Ok(reports) => reports,
+        Err(err) => {
+            if !quiet {
+                eprintln!("{}", err);
+            }
+            process::exit(1);
+        },
+    };
+    let mut addrs = Vec::new();
+    for report in &reports {
+        if !addrs.contains(&report.ip) {
+            addrs.push(report.ip);
+        }
+    }
+    if matches.is_present("json") {
+        for report in &reports {
+            println!("{}", report_json(report));
+        }
+    } else if let Some(template) = matches.value_of("format") {
+        for report in &reports {
+            println!("{}", report_format(template, report));
+        }
+    } else if quiet {
+        for addr in &addrs {
+            println!("{}", addr);
+        }
+    } else {
+        // ` (name)` with --rdns
+        let hostname = |addr: &MyIp| {
+            reports.iter()
+                .find(|r| r.ip == *addr)
+                .and_then(|r| r.hostname.as_ref())
+                .map_or(String::new(), |name| format!(" ({})", name))
+        };
+        if matches.is_present("ipv4") || matches.is_present("ipv6") {
+            for addr in &addrs {
+                println!("{}{}", addr, hostname(addr));
+            }
+        } else {
+            // Dual stack, IPv4 first
+            for addr in addrs.iter().filter(|addr| addr.is_ipv4()) {
+                println!("IPv4: {}{}", addr, hostname(addr));
+            }
+            for addr in addrs.iter().filter(|addr| addr.is_ipv6()) {
+                println!("IPv6: {}{}", addr, hostname(addr));
+            }
+        }
+    }
+    if matches.is_present("geo") {
+        for addr in &addrs {
+            print_geo(addr);
+        }
+    }
+    if matches.is_present("dnsbl") {
+        for addr in addrs.iter().filter(|addr| addr.is_ipv4()) {
+            print_dnsbl(addr);
+        }
+    }
+
+    if let Some(path) = matches.value_of("state") {
+        let mut sorted = addrs.clone();
+        sorted.sort();
+        let saved = State::load(path).unwrap_or_else(|err| state_error(path, err, quiet));
+        let changed = match saved {
+            Some(ref state) if state.ips == sorted => false,
+            Some(_) => {
+                if !quiet {
+                    eprintln!("Address changed");
+                }
+                true
+            },
+            // Nothing saved yet
+            None => true,
+        };
+        if let Err(err) = State::new(sorted).save(path) {
+            state_error(path, err, quiet);
+        }
+        if changed && matches.is_present("changed") {
+            process::exit(2);
+        }
+    }
+}
+
+#[test]
+fn test_url_with_provider() {
+    let matches = cli::app().get_matches_from(vec!["whatsmyip", "--provider", "ipify",
+                                                   "--url", "example.com/ip",
+                                                   "--url", "http://192.0.2.1/"]);
+    assert_eq!(only_providers(&matches).unwrap(),
+               vec!["ipify", "https://example.com/ip", "http://192.0.2.1/"]);
+    let mut opts = WhatsMyIp::with_providers(vec![]);
+    opts.add_https_provider("example.com/ip");
+    assert!(opts.provider_names().contains(&url_name("example.com/ip").as_str()));
+
+    let matches = cli::app().get_matches_from(vec!["whatsmyip", "--url", "example.com/ip"]);
+    assert_eq!(only_providers(&matches), None);
+}
@@ -0,0 +1,152 @@
+// The man page, written from the help of the command and of each
+// subcommand, see the hidden man subcommand
+
+use clap::ErrorKind;
+use cli;
+
+/// The parts of a `--help` text
+struct Help {
+    about: String,
+    usage: String,
+    /// The FLAGS, OPTIONS and SUBCOMMANDS entries, the name (e.g.
+    /// `-q, --quiet`) and its help
+    sections: Vec<(String, Vec<(String, String)>)>,
+}
+
+/// The help of the command, or of the subcommand in `args`, with no
+/// line wrapping
+fn help_text(args: &[&str]) -> String {
+    let mut argv = vec!["whatsmyip"];
+    argv.extend_from_slice(args);
+    argv.push("--help");
+    match cli::app().set_term_width(0).get_matches_from_safe(argv) {
+        Err(ref err) if err.kind == ErrorKind::HelpDisplayed => err.message.clone(),
+        _ => String::new(),
+    }
+}
+
+fn parse_help(text: &str) -> Help {
+    let mut help = Help {
+        about: String::new(),
+        usage: String::new(),
+        sections: Vec::new(),
+    };
+    let mut section: Option<String> = None;
+    // The first line is the name and version
+    for line in text.lines().skip(1) {
+        if line.ends_with(':') && !line.starts_with(' ') {
+            section = Some(line.trim_end_matches(':').to_owned());
+            if section.as_ref().map_or(false, |s| s != "USAGE") {
+                help.sections.push((line.trim_end_matches(':').to_owned(), Vec::new()));
+            }
+            continue;
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        match section.as_deref() {
+            None => help.about = line.to_owned(),
+            Some("USAGE") => help.usage = line.to_owned(),
+            Some(_) => {
+                let entry = match line.find("  ") {
+                    Some(pos) => (line[..pos].to_owned(), line[pos..].trim().to_owned()),
+                    None => (line.to_owned(), String::new()),
+                };
+                if let Some(&mut (_, ref mut entries)) = help.sections.last_mut() {
+                    entries.push(entry);
+                }
+            },
+        }
+    }
+    help
+}
+
+/// Escape backslashes and dashes, and protect a `.` or `'` at the
+/// start of a line
+fn escape(text: &str) -> String {
+    let text = text.replace('\\', "\\e").replace('-', "\\-");
+    if text.starts_with('.') || text.starts_with('\'') {
+        format!("\\&{}", text)
+    } else {
+        text
+    }
+}
+
+/// Flags in bold and values (e.g. `<FILE>`) in italics
+fn format_name(name: &str) -> String {
+    name.split(' ')
+        .map(|word| {
+            if word.starts_with('<') || word.starts_with('[') {
+                format!("\\fI{}\\fR", escape(word))
+            } else {
+                format!("\\fB{}\\fR", escape(word))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The flags and options of `help` as tagged paragraphs, except for
+/// --help and --version
+fn write_options(page: &mut String, help: &Help) {
+    for &(ref section, ref entries) in &help.sections {
+        if section != "FLAGS" && section != "OPTIONS" {
+            continue;
+        }
+        for &(ref name, ref text) in entries {
+            if name == "-h, --help" || name == "-V, --version" {
+                continue;
+            }
+            page.push_str(&format!(".TP\n{}\n{}\n", format_name(name), escape(text)));
+        }
+    }
+}
+
+/// The man page, in roff
+pub fn man_page() -> String {
+    let help = parse_help(&help_text(&[]));
+    let mut page = format!(".TH WHATSMYIP 1 \"\" \"whatsmyip {}\"\n", env!("CARGO_PKG_VERSION"));
+    page.push_str(&format!(".SH NAME\nwhatsmyip \\- {}\n", escape(&help.about)));
+    page.push_str(&format!(".SH SYNOPSIS\n{}\n", format_name(&help.usage)));
+    page.push_str(".SH DESCRIPTION\n\
+                   Asks the Internet Gateway Device, STUN servers, DNS resolvers and \
+                   web services for the external addresses of this host and prints \
+                   them one per line, labelled with their family unless only one \
+                   family is asked for.\n");
+    page.push_str(".SH OPTIONS\n");
+    write_options(&mut page, &help);
+
+    let commands = help.sections.iter()
+                    .filter(|&&(ref section, _)| section == "SUBCOMMANDS")
+                    .flat_map(|&(_, ref entries)| entries)
+                    .filter(|&&(ref name, _)| name != "help");
+    page.push_str(".SH COMMANDS\n");
+    for &(ref name, _) in commands {
+        let sub = parse_help(&help_text(&[name]));
+        page.push_str(&format!(".SS {}\n{}\n.PP\n", format_name(&sub.usage), escape(&sub.about)));
+        write_options(&mut page, &sub);
+    }
+    page.push_str(".SH EXIT STATUS\n\
+                   .TP\n0\nAn address was found\n\
+                   .TP\n1\nNo source answered, or another error such as a failed update\n\
+                   .TP\n2\nWith \\fB\\-\\-changed\\fR, the addresses differ from the state file\n\
+                   .TP\n3\nThe state file cannot be read or written\n");
+    page
+}
+
+#[test]
+fn test_man_page() {
+    let page = man_page();
+    assert!(page.starts_with(".TH WHATSMYIP 1 "));
+    for section in &["NAME", "SYNOPSIS", "DESCRIPTION", "OPTIONS", "COMMANDS", "EXIT STATUS"] {
+        assert!(page.contains(&format!("\n.SH {}\n", section)), "{}", section);
+    }
+    assert!(page.contains(".SH NAME\nwhatsmyip \\- Find out your external IP address\n"));
+    assert!(page.contains(".TP\n\\fB\\-q,\\fR \\fB\\-\\-quiet\\fR\n"));
+    assert!(page.contains(".TP\n\\fB\\-\\-provider\\fR \\fI<NAME>...\\fR\n"));
+    assert!(page.contains(".SS \\fBwhatsmyip\\fR \\fBddns\\fR"));
+    // Hidden subcommands stay out, --help is not an option worth listing
+    assert!(!page.contains("completions"));
+    assert!(!page.contains("Prints help information"));
+}
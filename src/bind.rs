@@ -0,0 +1,134 @@
+//!
+//! Local addresses for outgoing connections, see
+//! `WhatsMyIp::bind_address()` and `bind_interface()`
+//!
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::time::Duration;
+use get_if_addrs::get_if_addrs;
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use {Error, IpFamily};
+
+/// Where outgoing sockets are bound
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Bind {
+    /// Let the OS pick
+    Any,
+    /// Bind to these addresses, a family without an address is
+    /// not used at all
+    Addrs(Option<Ipv4Addr>, Option<Ipv6Addr>),
+}
+
+impl Bind {
+    pub fn address(ip: IpAddr) -> Bind {
+        match ip {
+            IpAddr::V4(ip) => Bind::Addrs(Some(ip), None),
+            IpAddr::V6(ip) => Bind::Addrs(None, Some(ip)),
+        }
+    }
+
+    /// The addresses of interface `name`, link local IPv6
+    /// addresses are skipped
+    pub fn interface(name: &str) -> Result<Bind, Error> {
+        let ifaces = try!(get_if_addrs()
+                            .map_err(Error::from));
+        let mut v4 = None;
+        let mut v6 = None;
+        for iface in ifaces.iter().filter(|iface| iface.name == name) {
+            match iface.ip() {
+                IpAddr::V4(ip) => v4 = v4.or(Some(ip)),
+                IpAddr::V6(ip) if ip.segments()[0] & 0xffc0 != 0xfe80 => v6 = v6.or(Some(ip)),
+                IpAddr::V6(_) => (),
+            }
+        }
+        if v4.is_none() && v6.is_none() {
+            return Err(Error::Protocol(format!("No addresses on interface {}", name)));
+        }
+        Ok(Bind::Addrs(v4, v6))
+    }
+
+    /// Only connect over `family`
+    pub fn only(self, family: IpFamily) -> Bind {
+        let (v4, v6) = match self {
+            Bind::Any => (Some(Ipv4Addr::new(0, 0, 0, 0)), Some(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0))),
+            Bind::Addrs(v4, v6) => (v4, v6),
+        };
+        match family {
+            IpFamily::V4 => Bind::Addrs(v4, None),
+            IpFamily::V6 => Bind::Addrs(None, v6),
+            IpFamily::Both => self,
+        }
+    }
+
+    /// The local address for a socket talking to `remote`, None if
+    /// that family is not allowed
+    pub fn local_for(&self, remote: &SocketAddr) -> Option<SocketAddr> {
+        match (*self, remote) {
+            (Bind::Any, &SocketAddr::V4(_)) => Some(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 0)),
+            (Bind::Any, &SocketAddr::V6(_)) => Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0)), 0)),
+            (Bind::Addrs(v4, _), &SocketAddr::V4(_)) => v4.map(|ip| SocketAddr::new(IpAddr::V4(ip), 0)),
+            (Bind::Addrs(_, v6), &SocketAddr::V6(_)) => v6.map(|ip| SocketAddr::new(IpAddr::V6(ip), 0)),
+        }
+    }
+
+    /// The local IPv4 address, unspecified for `Any`
+    pub fn ipv4(&self) -> Option<Ipv4Addr> {
+        match *self {
+            Bind::Any => Some(Ipv4Addr::new(0, 0, 0, 0)),
+            Bind::Addrs(v4, _) => v4,
+        }
+    }
+
+    fn not_allowed(remote: &SocketAddr) -> io::Error {
+        io::Error::new(io::ErrorKind::AddrNotAvailable,
+                       format!("No local address to reach {}", remote))
+    }
+
+    /// Connect to the first of `addrs` that answers
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    pub fn connect(&self, addrs: &[SocketAddr], timeout: Option<Duration>) -> io::Result<TcpStream> {
+        let mut last_err = io::Error::new(io::ErrorKind::Other, "No address to connect to");
+        for &addr in addrs {
+            let local = match self.local_for(&addr) {
+                Some(local) => local,
+                None => {
+                    last_err = Bind::not_allowed(&addr);
+                    continue;
+                },
+            };
+            let domain = if addr.is_ipv4() { Domain::ipv4() } else { Domain::ipv6() };
+            let socket = try!(Socket::new(domain, Type::stream(), Some(Protocol::tcp())));
+            try!(socket.bind(&SockAddr::from(local)));
+            let res = match timeout {
+                Some(timeout) => socket.connect_timeout(&SockAddr::from(addr), timeout),
+                None => socket.connect(&SockAddr::from(addr)),
+            };
+            match res {
+                Ok(()) => return Ok(socket.into_tcp_stream()),
+                Err(err) => last_err = err,
+            }
+        }
+        Err(last_err)
+    }
+
+    /// A UDP socket to talk to `remote`
+    pub fn udp(&self, remote: &SocketAddr) -> io::Result<UdpSocket> {
+        match self.local_for(remote) {
+            Some(local) => UdpSocket::bind(local),
+            None => Err(Bind::not_allowed(remote)),
+        }
+    }
+}
+
+#[test]
+fn test_bind_local_for() {
+    use std::str::FromStr;
+    let v4 = SocketAddr::from_str("192.0.2.1:80").unwrap();
+    let v6 = SocketAddr::from_str("[2001:db8::1]:80").unwrap();
+    let bind = Bind::address(IpAddr::from_str("198.51.100.7").unwrap());
+    assert_eq!(bind.local_for(&v4), Some(SocketAddr::from_str("198.51.100.7:0").unwrap()));
+    assert_eq!(bind.local_for(&v6), None);
+    assert_eq!(Bind::Any.local_for(&v6), Some(SocketAddr::from_str("[::]:0").unwrap()));
+    assert_eq!(Bind::Any.only(IpFamily::V4).local_for(&v6), None);
+}
@@ -0,0 +1,305 @@
+//!
+//! Reuse HTTP connections between searches, see `WhatsMyIpClient`
+//!
+
+use std::io;
+use std::net::SocketAddr;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use hyper;
+use hyper::Client;
+use hyper::client::RedirectPolicy;
+use hyper::client::pool::{Pool, PooledStream};
+use hyper::net::{HttpStream, HttpsStream, NetworkConnector, Ssl};
+use bind::Bind;
+use http::shorter;
+use pin::{self, Pin};
+use resolver::Hosts;
+use {Proxy, WhatsMyIp};
+
+/// The TLS backend, see the `rustls-tls` feature
+#[cfg(feature = "rustls-tls")]
+type Tls = ::tls::Rustls;
+#[cfg(not(feature = "rustls-tls"))]
+type Tls = hyper::net::Openssl;
+
+/// Most connection pools we keep, each config needs its own
+const MAX_POOLS: usize = 8;
+
+/// How long pooled clients remember an address lookup
+const DNS_TTL_SECS: u64 = 60;
+
+/// Settings that belong to the hyper client rather than the request
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClientConfig {
+    pub connect_timeout: Option<Duration>,
+    pub read_timeout: Option<Duration>,
+    pub proxy: Option<Proxy>,
+    pub bind: Bind,
+    pub hosts: Hosts,
+    pub pins: Vec<Pin>,
+    pub follow_redirects: bool,
+}
+
+impl ClientConfig {
+    /// Same timeout to connect and to read
+    pub fn new(timeout: Option<Duration>) -> ClientConfig {
+        ClientConfig {
+            connect_timeout: timeout,
+            read_timeout: timeout,
+            proxy: None,
+            bind: Bind::Any,
+            hosts: Hosts::new(),
+            pins: Vec::new(),
+            follow_redirects: true,
+        }
+    }
+}
+
+/// Host, port, when it was looked up and the addresses
+type CacheEntry = (String, u16, Instant, Vec<SocketAddr>);
+
+/// Address lookups shared by the connections of a pool
+struct DnsCache {
+    entries: Mutex<Vec<CacheEntry>>,
+}
+
+impl DnsCache {
+    fn new() -> DnsCache {
+        DnsCache {
+            entries: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The addresses of `host` from `hosts`, or from an earlier
+    /// lookup less than `DNS_TTL_SECS` ago
+    fn lookup(&self, hosts: &Hosts, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        let ttl = Duration::from_secs(DNS_TTL_SECS);
+        {
+            let mut entries = self.entries.lock().unwrap();
+            entries.retain(|&(_, _, at, _)| at.elapsed() < ttl);
+            if let Some(&(_, _, _, ref addrs)) = entries.iter()
+                                                    .find(|e| e.0 == host && e.1 == port) {
+                return Ok(addrs.clone());
+            }
+        }
+        let addrs = try!(hosts.lookup(host, port));
+        self.entries.lock().unwrap().push((host.to_owned(), port, Instant::now(), addrs.clone()));
+        Ok(addrs)
+    }
+}
+
+/// Opens hyper connections from the bound address, through
+/// the proxy if any
+struct Connector {
+    config: ClientConfig,
+    ssl: Tls,
+    dns: Option<DnsCache>,
+}
+
+impl Connector {
+    fn new(config: &ClientConfig, dns: Option<DnsCache>) -> Connector {
+        Connector {
+            config: config.clone(),
+            ssl: Tls::default(),
+            dns: dns,
+        }
+    }
+
+    fn lookup(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        match self.dns {
+            Some(ref dns) => dns.lookup(&self.config.hosts, host, port),
+            None => self.config.hosts.lookup(host, port),
+        }
+    }
+}
+
+impl NetworkConnector for Connector {
+    type Stream = HttpsStream<<Tls as Ssl>::Stream>;
+
+    fn connect(&self, host: &str, port: u16, scheme: &str) -> hyper::Result<Self::Stream> {
+        let timeout = self.config.connect_timeout;
+        let stream = match self.config.proxy {
+            Some(ref proxy) => {
+                let addrs = try!(self.lookup(proxy.host(), proxy.port()));
+                let mut stream = try!(self.config.bind.connect(&addrs, timeout));
+                try!(stream.set_read_timeout(self.config.read_timeout));
+                try!(stream.set_write_timeout(self.config.read_timeout));
+                try!(proxy.handshake(&mut stream, host, port));
+                stream
+            },
+            None => {
+                let addrs = try!(self.lookup(host, port));
+                try!(self.config.bind.connect(&addrs, timeout))
+            },
+        };
+        let stream = HttpStream(stream);
+        if scheme == "https" {
+            let stream = try!(self.ssl.wrap_client(stream, host));
+            try!(pin::check(&self.config.pins, &stream, host));
+            Ok(HttpsStream::Https(stream))
+        } else {
+            Ok(HttpsStream::Http(stream))
+        }
+    }
+}
+
+/// A connection pool used by several clients
+struct SharedPool(Arc<Pool<Connector>>);
+
+impl NetworkConnector for SharedPool {
+    type Stream = PooledStream<<Connector as NetworkConnector>::Stream>;
+
+    fn connect(&self, host: &str, port: u16, scheme: &str) -> hyper::Result<Self::Stream> {
+        self.0.connect(host, port, scheme)
+    }
+}
+
+/// Connections kept between requests, by config. The timeouts are
+/// not part of the config, with a `deadline()` they change on every
+/// request.
+pub struct HttpPool {
+    connect_timeout: Option<Duration>,
+    pools: Mutex<Vec<(ClientConfig, Arc<Pool<Connector>>)>>,
+}
+
+impl HttpPool {
+    /// New connections wait up to `connect_timeout`, they are shared
+    /// by requests with different timeouts
+    pub fn new(connect_timeout: Option<Duration>) -> HttpPool {
+        HttpPool {
+            connect_timeout: connect_timeout,
+            pools: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// A client with `config`, its connections are kept alive
+    /// and reused by later clients with the same config
+    pub fn client(&self, config: &ClientConfig) -> Client {
+        let key = ClientConfig {
+            connect_timeout: self.connect_timeout,
+            read_timeout: None,
+            follow_redirects: true,
+            ..config.clone()
+        };
+        let mut pools = self.pools.lock().unwrap();
+        let pool = match pools.iter().position(|&(ref c, _)| *c == key) {
+            Some(pos) => pools[pos].1.clone(),
+            None => {
+                let connector = Connector::new(&key, Some(DnsCache::new()));
+                let pool = Arc::new(Pool::with_connector(Default::default(), connector));
+                if pools.len() >= MAX_POOLS {
+                    pools.remove(0);
+                }
+                pools.push((key, pool.clone()));
+                pool
+            },
+        };
+        configure(Client::with_connector(SharedPool(pool)), config)
+    }
+}
+
+/// Timeouts and redirects of `config`, they belong to the request
+/// rather than the connection
+fn configure(mut cli: Client, config: &ClientConfig) -> Client {
+    cli.set_read_timeout(config.read_timeout);
+    cli.set_write_timeout(config.read_timeout);
+    if !config.follow_redirects {
+        cli.set_redirect_policy(RedirectPolicy::FollowNone);
+    }
+    cli
+}
+
+/// A new client, its connections are kept alive until it is dropped
+pub fn new_client(config: &ClientConfig) -> Client {
+    // Only Client::new() pools connections by itself
+    let connector = Connector::new(config, None);
+    configure(Client::with_connector(Pool::with_connector(Default::default(), connector)), config)
+}
+
+/// A `WhatsMyIp` that keeps HTTP connections open between calls
+/// to `find()`, for long running processes that check often. Address
+/// lookups are cached for a minute. New connections wait up to
+/// `http_connect_timeout()`, capped by `http_timeout()`.
+///
+/// ```no_run
+/// use std::thread;
+/// use std::time::Duration;
+/// use whatsmyip::{WhatsMyIp, WhatsMyIpClient};
+///
+/// let mut opts = WhatsMyIp::new();
+/// opts.fast(true);
+/// let client = WhatsMyIpClient::new(opts);
+/// loop {
+///     println!("{:?}", client.find());
+///     thread::sleep(Duration::from_secs(60));
+/// }
+/// ```
+pub struct WhatsMyIpClient {
+    opts: WhatsMyIp,
+}
+
+impl WhatsMyIpClient {
+    pub fn new(mut opts: WhatsMyIp) -> WhatsMyIpClient {
+        let connect_timeout = shorter(opts.http_connect_timeout, opts.http_timeout);
+        opts.http_pool = Some(HttpPool::new(connect_timeout));
+        WhatsMyIpClient {
+            opts: opts,
+        }
+    }
+}
+
+impl Deref for WhatsMyIpClient {
+    type Target = WhatsMyIp;
+
+    fn deref(&self) -> &WhatsMyIp {
+        &self.opts
+    }
+}
+
+#[cfg(test)]
+use std::io::Read;
+#[cfg(test)]
+use fixture::Fixture;
+
+#[test]
+fn test_keep_alive() {
+    let fixture = Fixture::start();
+    let cli = new_client(&ClientConfig::new(Some(Duration::from_secs(1))));
+    for _ in 0..2 {
+        let mut body = String::new();
+        cli.get(&fixture.url("/text")).send().unwrap()
+            .read_to_string(&mut body).unwrap();
+        assert_eq!(body, "192.0.2.1\n");
+    }
+    assert_eq!(fixture.connections(), 1);
+}
+
+#[test]
+fn test_http_pool() {
+    use std::net::Ipv4Addr;
+    let pool = HttpPool::new(None);
+    // Timeouts are set per request, e.g. what is left of a deadline
+    pool.client(&ClientConfig::new(Some(Duration::from_secs(1))));
+    pool.client(&ClientConfig::new(Some(Duration::from_millis(999))));
+    pool.client(&ClientConfig::new(None));
+    assert_eq!(pool.pools.lock().unwrap().len(), 1);
+    let mut config = ClientConfig::new(None);
+    config.bind = Bind::Addrs(Some(Ipv4Addr::new(127, 0, 0, 1)), None);
+    pool.client(&config);
+    assert_eq!(pool.pools.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn test_dns_cache() {
+    use std::net::{IpAddr, Ipv4Addr};
+    let mut hosts = Hosts::new();
+    hosts.insert("ip.example", IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+    let dns = DnsCache::new();
+    let addrs = dns.lookup(&hosts, "ip.example", 443).unwrap();
+    assert_eq!(addrs, vec![SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), 443)]);
+    // Answered from the cache
+    assert_eq!(dns.lookup(&Hosts::new(), "ip.example", 443).unwrap(), addrs);
+    assert_eq!(dns.entries.lock().unwrap().len(), 1);
+}
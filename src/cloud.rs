@@ -0,0 +1,97 @@
+//!
+//! Cloud instance metadata services
+//!
+//! On cloud VMs the public address is available from the instance
+//! metadata endpoint, no need to ask anyone on the internet.
+//!
+
+use std::io::Read;
+use std::time::Duration;
+use hyper::Client;
+use hyper::client::RequestBuilder;
+use hyper::header::Headers;
+use {MyIp, Error, ip_from_str};
+use tcp;
+
+const AWS_TOKEN_URL: &'static str = "http://169.254.169.254/latest/api/token";
+const AWS_IP_URL: &'static str = "http://169.254.169.254/latest/meta-data/public-ipv4";
+const GCE_IP_URL: &'static str = "http://metadata.google.internal/computeMetadata/v1/instance/network-interfaces/0/access-configs/0/external-ip";
+const AZURE_IP_URL: &'static str = "http://169.254.169.254/metadata/instance/network/interface/0/ipv4/ipAddress/0/publicIpAddress?api-version=2021-02-01&format=text";
+
+/// Cloud providers with a metadata service
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Cloud {
+    /// Amazon EC2, uses the IMDSv2 token flow
+    Aws,
+    /// Google Compute Engine
+    Gce,
+    /// Microsoft Azure
+    Azure,
+}
+
+/// All supported clouds, in the order they are tried
+pub const CLOUDS: &'static [Cloud] = &[Cloud::Aws, Cloud::Gce, Cloud::Azure];
+
+/// The metadata address is unroutable outside the cloud and the
+/// connection would hang, so we always connect with a timeout
+fn client(timeout: Duration) -> Client {
+    let mut cli = Client::with_connector(move |host: &str, port: u16, _: &str| {
+        tcp::connect(host, port, timeout)
+    });
+    cli.set_read_timeout(Some(timeout));
+    cli.set_write_timeout(Some(timeout));
+    cli
+}
+
+fn send(req: RequestBuilder, headers: Headers) -> Result<String, Error> {
+    let mut res = try!(req.headers(headers)
+                    .send()
+                    .map_err(Error::from));
+    if !res.status.is_success() {
+        return Err(Error::Http { status: res.status.to_u16() });
+    }
+    let mut s = String::new();
+    try!(res.read_to_string(&mut s)
+        .map_err(Error::from));
+    Ok(s)
+}
+
+fn aws_ip(timeout: Duration) -> Result<MyIp, Error> {
+    let cli = client(timeout);
+    let mut headers = Headers::new();
+    headers.set_raw("X-aws-ec2-metadata-token-ttl-seconds", vec![b"60".to_vec()]);
+    let token = try!(send(cli.put(AWS_TOKEN_URL), headers));
+
+    let mut headers = Headers::new();
+    headers.set_raw("X-aws-ec2-metadata-token", vec![token.trim().as_bytes().to_vec()]);
+    let s = try!(send(cli.get(AWS_IP_URL), headers));
+    ip_from_str(&s)
+}
+
+fn gce_ip(timeout: Duration) -> Result<MyIp, Error> {
+    let mut headers = Headers::new();
+    headers.set_raw("Metadata-Flavor", vec![b"Google".to_vec()]);
+    let s = try!(send(client(timeout).get(GCE_IP_URL), headers));
+    ip_from_str(&s)
+}
+
+fn azure_ip(timeout: Duration) -> Result<MyIp, Error> {
+    let mut headers = Headers::new();
+    headers.set_raw("Metadata", vec![b"true".to_vec()]);
+    let s = try!(send(client(timeout).get(AZURE_IP_URL), headers));
+    ip_from_str(&s)
+}
+
+/// Ask the instance metadata service for the public address
+pub fn cloud_ip(cloud: Cloud, timeout: Duration) -> Result<MyIp, Error> {
+    let res = match cloud {
+        Cloud::Aws => aws_ip(timeout),
+        Cloud::Gce => gce_ip(timeout),
+        Cloud::Azure => azure_ip(timeout),
+    };
+    if let Ok(ref ip) = res {
+        debug!("{:?} metadata => {}", cloud, ip);
+    }
+    res
+}
@@ -0,0 +1,248 @@
+//!
+//! Load the provider list from a JSON file, see `WhatsMyIp::from_config()`
+//!
+
+use std::time::Duration;
+use rustc_serialize::json::{Json, Object};
+use {Error, WhatsMyIp, Provider, Strategy};
+#[cfg(any(feature = "dns", feature = "stun"))]
+use {MyIp, Context};
+#[cfg(feature = "http")]
+use {HttpProvider, Pin};
+#[cfg(feature = "dns")]
+use dns;
+#[cfg(feature = "stun")]
+use stun;
+
+/// A DNS query from the config file
+#[cfg(feature = "dns")]
+struct DnsQuery {
+    name: String,
+    server: String,
+    doh: Option<String>,
+    query: String,
+    qtype: u16,
+    qclass: u16,
+}
+
+#[cfg(feature = "dns")]
+impl Provider for DnsQuery {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn fetch(&self, ctx: &Context) -> Result<MyIp, Error> {
+        let bind = try!(ctx.opts.local_bind());
+        dns::lookup_ip_bound(&self.server, self.doh.as_ref().map(|s| &s[..]), &self.query,
+                             self.qtype, self.qclass, bind, ctx.timeout)
+    }
+}
+
+/// A STUN server from the config file
+#[cfg(feature = "stun")]
+struct StunServer {
+    server: String,
+}
+
+#[cfg(feature = "stun")]
+impl Provider for StunServer {
+    fn name(&self) -> &str {
+        &self.server
+    }
+
+    fn fetch(&self, ctx: &Context) -> Result<MyIp, Error> {
+        let bind = try!(ctx.opts.local_bind());
+        stun::stun_ip(&self.server, bind, ctx.timeout)
+    }
+}
+
+fn get_str<'a>(obj: &'a Object, key: &str) -> Result<&'a str, Error> {
+    obj.get(key).and_then(|val| val.as_string())
+        .ok_or(Error::Parse(format!("Missing string {} in provider", key)))
+}
+
+fn get_duration(val: &Json) -> Result<Duration, Error> {
+    match val.as_f64() {
+        Some(secs) if secs >= 0.0 => {
+            Ok(Duration::new(secs as u64, ((secs - secs.floor()) * 1e9) as u32))
+        },
+        _ => Err(Error::Parse(format!("Invalid timeout {}", val))),
+    }
+}
+
+fn get_bool(val: &Json, key: &str) -> Result<bool, Error> {
+    val.as_boolean().ok_or(Error::Parse(format!("{} must be true or false", key)))
+}
+
+#[cfg(feature = "dns")]
+fn dns_type(val: &str) -> Result<u16, Error> {
+    match val {
+        "A" => Ok(dns::TYPE_A),
+        "AAAA" => Ok(dns::TYPE_AAAA),
+        "TXT" => Ok(dns::TYPE_TXT),
+        _ => Err(Error::Parse(format!("Unsupported DNS type {}", val))),
+    }
+}
+
+#[cfg(feature = "dns")]
+fn dns_class(val: &str) -> Result<u16, Error> {
+    match val {
+        "IN" => Ok(dns::CLASS_IN),
+        "CH" => Ok(dns::CLASS_CH),
+        _ => Err(Error::Parse(format!("Unsupported DNS class {}", val))),
+    }
+}
+
+#[cfg(feature = "stun")]
+fn stun_provider(obj: &Object) -> Result<Box<dyn Provider>, Error> {
+    Ok(Box::new(StunServer {
+        server: try!(get_str(obj, "server")).to_owned(),
+    }))
+}
+
+#[cfg(feature = "dns")]
+fn dns_provider(obj: &Object) -> Result<Box<dyn Provider>, Error> {
+    let server = try!(get_str(obj, "server"));
+    Ok(Box::new(DnsQuery {
+        name: get_str(obj, "name").unwrap_or(server).to_owned(),
+        server: server.to_owned(),
+        doh: get_str(obj, "doh").ok().map(|s| s.to_owned()),
+        query: try!(get_str(obj, "query")).to_owned(),
+        qtype: try!(dns_type(get_str(obj, "qtype").unwrap_or("A"))),
+        qclass: try!(dns_class(get_str(obj, "qclass").unwrap_or("IN"))),
+    }))
+}
+
+#[cfg(feature = "http")]
+fn http_provider(kind: &str, obj: &Object) -> Result<Box<dyn Provider>, Error> {
+    let url = try!(get_str(obj, "url"));
+    let name = get_str(obj, "name").unwrap_or(url);
+    let provider = match kind {
+        "text" => HttpProvider::text(name, url),
+        "json" => HttpProvider::json(name, url, try!(get_str(obj, "field"))),
+        "header" => HttpProvider::header(name, url, try!(get_str(obj, "header"))),
+        "regex" => HttpProvider::regex(name, url, try!(get_str(obj, "pattern"))),
+        _ => return Err(Error::Parse(format!("Unknown provider type {}", kind))),
+    };
+    let provider = match get_str(obj, "pin") {
+        Ok(pin) if pin.starts_with("sha256//") => match Pin::public_key(pin) {
+            Some(pin) => provider.pin(pin),
+            None => return Err(Error::Parse(format!("Invalid pin {}", pin))),
+        },
+        Ok(pin) => match Pin::certificate(pin) {
+            Some(pin) => provider.pin(pin),
+            None => return Err(Error::Parse(format!("Invalid pin {}", pin))),
+        },
+        Err(_) => provider,
+    };
+    Ok(Box::new(provider))
+}
+
+/// A provider type that was not compiled in
+#[cfg(not(all(feature = "stun", feature = "dns", feature = "http")))]
+fn not_compiled(kind: &str, feature: &str) -> Result<Box<dyn Provider>, Error> {
+    Err(Error::Parse(format!("Provider type {} needs the {} feature", kind, feature)))
+}
+
+#[cfg(not(feature = "stun"))]
+fn stun_provider(_: &Object) -> Result<Box<dyn Provider>, Error> {
+    not_compiled("stun", "stun")
+}
+
+#[cfg(not(feature = "dns"))]
+fn dns_provider(_: &Object) -> Result<Box<dyn Provider>, Error> {
+    not_compiled("dns", "dns")
+}
+
+#[cfg(not(feature = "http"))]
+fn http_provider(kind: &str, _: &Object) -> Result<Box<dyn Provider>, Error> {
+    not_compiled(kind, "http")
+}
+
+fn provider(obj: &Object) -> Result<Box<dyn Provider>, Error> {
+    match try!(get_str(obj, "type")) {
+        "stun" => stun_provider(obj),
+        "dns" => dns_provider(obj),
+        kind => http_provider(kind, obj),
+    }
+}
+
+pub fn from_str(s: &str) -> Result<WhatsMyIp, Error> {
+    let json = try!(Json::from_str(s)
+                    .map_err(Error::from));
+    let config = try!(json.as_object()
+                        .ok_or(Error::Parse("Expected a JSON object".to_owned())));
+
+    let mut opts = WhatsMyIp::new();
+    for (key, val) in config {
+        match &key[..] {
+            "igd" => { opts.igd(try!(get_bool(val, key))); },
+            "stun" => { opts.stun(try!(get_bool(val, key))); },
+            "dns" => { opts.dns(try!(get_bool(val, key))); },
+            "fast" => { opts.fast(try!(get_bool(val, key))); },
+            "http_timeout" => { opts.http_timeout(Some(try!(get_duration(val)))); },
+            "http_connect_timeout" => { opts.http_connect_timeout(Some(try!(get_duration(val)))); },
+            "http_read_timeout" => { opts.http_read_timeout(Some(try!(get_duration(val)))); },
+            "http_limit" => match val.as_u64() {
+                Some(limit) => { opts.http_limit(Some(limit as usize)); },
+                None => return Err(Error::Parse(format!("Invalid http_limit {}", val))),
+            },
+            "max_redirects" => match val.as_u64() {
+                Some(n) => { opts.max_redirects(n as usize); },
+                None => return Err(Error::Parse(format!("Invalid max_redirects {}", val))),
+            },
+            "strategy" => {
+                let strategy = match val.as_string() {
+                    Some("random") => Strategy::RandomShuffle,
+                    Some("fixed") => Strategy::FixedOrder,
+                    Some("round-robin") => Strategy::RoundRobin,
+                    Some("latency") => Strategy::LowestLatencyFirst,
+                    _ => return Err(Error::Parse(format!("Unknown strategy {}", val))),
+                };
+                opts.strategy(strategy);
+            },
+            "providers" => {
+                let list = try!(val.as_array()
+                                .ok_or(Error::Parse("providers must be a list".to_owned())));
+                opts.clear_providers();
+                for entry in list {
+                    let obj = try!(entry.as_object()
+                                    .ok_or(Error::Parse("Expected a provider object".to_owned())));
+                    let p = try!(provider(obj));
+                    let name = p.name().to_owned();
+                    let priority = obj.get("priority").and_then(|v| v.as_u64()).unwrap_or(0);
+                    let weight = obj.get("weight").and_then(|v| v.as_u64()).unwrap_or(1);
+                    opts.weighted_provider(p, priority as u32, weight as u32);
+                    if let Some(t) = obj.get("timeout") {
+                        opts.provider_timeout(&name, Some(try!(get_duration(t))));
+                    }
+                }
+            },
+            _ => return Err(Error::Parse(format!("Unknown option {}", key))),
+        }
+    }
+    Ok(opts)
+}
+
+#[cfg(all(feature = "http", feature = "dns", feature = "stun"))]
+#[test]
+fn test_from_str() {
+    let opts = from_str(r#"{
+        "stun": false,
+        "http_timeout": 2.5,
+        "http_connect_timeout": 0.5,
+        "strategy": "fixed",
+        "providers": [
+            {"type": "text", "url": "https://ip.example.com/"},
+            {"type": "json", "name": "ipinfo", "url": "https://ipinfo.io/json", "field": "ip"},
+            {"type": "dns", "name": "opendns", "server": "208.67.222.222:53",
+             "query": "myip.opendns.com", "timeout": 1},
+            {"type": "stun", "server": "stun.l.google.com:19302"}
+        ]
+    }"#).unwrap();
+    let names = opts.provider_names();
+    assert_eq!(&names[..4], &["https://ip.example.com/", "ipinfo", "opendns",
+                              "stun.l.google.com:19302"]);
+    assert!(from_str(r#"{"providers": [{"type": "ftp", "url": "ftp://x"}]}"#).is_err());
+    assert!(from_str(r#"{"colour": "blue"}"#).is_err());
+}
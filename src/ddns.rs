@@ -0,0 +1,413 @@
+//!
+//! Push the address to dynamic DNS services
+//!
+//! ```no_run
+//! use whatsmyip::{whatsmyip, Dyndns2, Updater};
+//! let ip = whatsmyip().unwrap();
+//! Dyndns2::new(whatsmyip::ddns::NOIP_URL, "myhost.example.com", "user", "password")
+//!     .update(&ip).unwrap();
+//! ```
+//!
+
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::time::Duration;
+use hyper::header::{Authorization, Basic, Bearer, Headers, UserAgent};
+use hyper::method::Method;
+use rustc_serialize::json::Json;
+use {MyIp, Error};
+use client::{self, ClientConfig};
+
+/// dyndns2 update URL for Dyn
+pub const DYNDNS_URL: &'static str = "https://members.dyndns.org/nic/update";
+/// dyndns2 update URL for No-IP
+pub const NOIP_URL: &'static str = "https://dynupdate.no-ip.com/nic/update";
+/// dyndns2 update URL for Google Domains
+pub const GOOGLE_DOMAINS_URL: &'static str = "https://domains.google.com/nic/update";
+
+/// Something that publishes the address, e.g. a DNS record.
+/// Implement it to run your own updates from `IpMonitor::updater()`.
+pub trait Updater: Send + Sync {
+    /// Name used in logs
+    fn name(&self) -> &str;
+
+    /// Publish `ip`
+    fn update(&self, ip: &MyIp) -> Result<(), Error>;
+
+    /// Publish all the addresses, for backends that can take e.g.
+    /// both an IPv4 and an IPv6 address at once
+    /// (defaults to calling `update()` for each address)
+    fn update_all(&self, ips: &[MyIp]) -> Result<(), Error> {
+        for ip in ips {
+            try!(self.update(ip));
+        }
+        Ok(())
+    }
+}
+
+/// Escape a query string value
+fn percent_encode(s: &str) -> String {
+    let mut out = String::new();
+    for b in s.bytes() {
+        match b {
+            b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' | b'-' | b'.' | b'_' | b'~' | b',' | b':' =>
+                out.push(b as char),
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+/// Check the dyndns2 response, `good` and `nochg` are a success
+fn check_response(body: &str) -> Result<(), Error> {
+    // One line per hostname
+    for line in body.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let code = line.split_whitespace().next().unwrap_or("");
+        match code {
+            "good" | "nochg" => (),
+            _ => return Err(Error::Protocol(format!("dyndns2 update failed: {}", line))),
+        }
+    }
+    Ok(())
+}
+
+/// A client for the dyndns2 protocol, supported by DynDNS, No-IP,
+/// Google Domains and many others
+pub struct Dyndns2 {
+    url: String,
+    hostname: String,
+    username: String,
+    password: String,
+    timeout: Duration,
+}
+
+impl Dyndns2 {
+    /// `url` is the update endpoint (e.g. `NOIP_URL`), `hostname` can
+    /// be a comma separated list
+    pub fn new(url: &str, hostname: &str, username: &str, password: &str) -> Dyndns2 {
+        Dyndns2 {
+            url: url.to_owned(),
+            hostname: hostname.to_owned(),
+            username: username.to_owned(),
+            password: password.to_owned(),
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// (defaults to **10 seconds**)
+    pub fn timeout(mut self, timeout: Duration) -> Dyndns2 {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The update URL, addresses are sent comma separated in `myip`
+    fn update_url(&self, ips: &[MyIp]) -> String {
+        let ips: Vec<String> = ips.iter().map(|ip| ip.to_string()).collect();
+        let sep = if self.url.contains('?') { '&' } else { '?' };
+        format!("{}{}hostname={}&myip={}", self.url, sep,
+                percent_encode(&self.hostname), percent_encode(&ips.join(",")))
+    }
+
+}
+
+impl Updater for Dyndns2 {
+    fn name(&self) -> &str {
+        &self.hostname
+    }
+
+    fn update(&self, ip: &MyIp) -> Result<(), Error> {
+        self.update_all(&[*ip])
+    }
+
+    /// Point the hostname to `ips`
+    fn update_all(&self, ips: &[MyIp]) -> Result<(), Error> {
+        let url = self.update_url(ips);
+        let cli = client::new_client(&ClientConfig::new(Some(self.timeout)));
+        let mut res = try!(cli.get(&url)
+                            .header(Authorization(Basic {
+                                username: self.username.clone(),
+                                password: Some(self.password.clone()),
+                            }))
+                            // Services block clients without a user agent
+                            .header(UserAgent(format!("whatsmyip/{}", env!("CARGO_PKG_VERSION"))))
+                            .send()
+                            .map_err(Error::from));
+        if !res.status.is_success() {
+            return Err(Error::Http { status: res.status.to_u16() });
+        }
+        let mut s = String::new();
+        try!(res.read_to_string(&mut s)
+            .map_err(Error::from));
+        debug!("{} => {}", self.hostname, s.trim());
+        check_response(&s)
+    }
+}
+
+/// Cloudflare API endpoint
+pub const CLOUDFLARE_API: &'static str = "https://api.cloudflare.com/client/v4";
+
+/// Updates the A and AAAA records of a name through the Cloudflare
+/// API, the token needs the Zone.DNS edit permission
+pub struct Cloudflare {
+    token: String,
+    zone: String,
+    record: String,
+    api: String,
+    timeout: Duration,
+}
+
+impl Cloudflare {
+    /// `zone` is the domain (e.g. `example.com`) and `record` the
+    /// full name to update (e.g. `home.example.com`)
+    pub fn new(token: &str, zone: &str, record: &str) -> Cloudflare {
+        Cloudflare {
+            token: token.to_owned(),
+            zone: zone.to_owned(),
+            record: record.to_owned(),
+            api: CLOUDFLARE_API.to_owned(),
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// (defaults to **10 seconds**)
+    pub fn timeout(mut self, timeout: Duration) -> Cloudflare {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The API base URL
+    /// (defaults to **CLOUDFLARE_API**)
+    pub fn api(mut self, url: &str) -> Cloudflare {
+        self.api = url.trim_right_matches('/').to_owned();
+        self
+    }
+
+    /// Send an API request, returns the `result` field
+    fn request(&self, method: Method, path: &str, body: Option<String>) -> Result<Json, Error> {
+        let url = format!("{}{}", self.api, path);
+        let cli = client::new_client(&ClientConfig::new(Some(self.timeout)));
+
+        let mut headers = Headers::new();
+        headers.set(Authorization(Bearer { token: self.token.clone() }));
+        headers.set_raw("Content-Type", vec![b"application/json".to_vec()]);
+        debug!("{} {}", method, url);
+        let req = cli.request(method, &url)
+                    .headers(headers);
+        let mut res = match body {
+            Some(ref body) => try!(req.body(body).send().map_err(Error::from)),
+            None => try!(req.send().map_err(Error::from)),
+        };
+        let mut s = String::new();
+        try!(res.read_to_string(&mut s)
+            .map_err(Error::from));
+        debug!("{} => {} {}", url, res.status, s);
+        let json = try!(Json::from_str(&s)
+                        .map_err(|_| Error::Http { status: res.status.to_u16() }));
+        cloudflare_result(json)
+    }
+
+    /// The id of an object named `name` in a list
+    fn find_id(&self, path: &str, name: &str) -> Result<Option<String>, Error> {
+        let path = format!("{}{}name={}", path, if path.contains('?') { '&' } else { '?' },
+                           percent_encode(name));
+        let result = try!(self.request(Method::Get, &path, None));
+        Ok(result.as_array()
+            .and_then(|items| items.first())
+            .and_then(|item| item.find("id"))
+            .and_then(|id| id.as_string())
+            .map(str::to_owned))
+    }
+
+}
+
+impl Updater for Cloudflare {
+    fn name(&self) -> &str {
+        &self.record
+    }
+
+    fn update(&self, ip: &MyIp) -> Result<(), Error> {
+        self.update_all(&[*ip])
+    }
+
+    /// Point the record to `ips`, the A record is set to the first
+    /// IPv4 address and the AAAA record to the first IPv6 address.
+    /// Missing records are created.
+    fn update_all(&self, ips: &[MyIp]) -> Result<(), Error> {
+        let zone_id = match try!(self.find_id("/zones", &self.zone)) {
+            Some(id) => id,
+            None => return Err(Error::Protocol(format!("No Cloudflare zone {}", self.zone))),
+        };
+
+        let v4 = ips.iter().find(|ip| ip.is_ipv4());
+        let v6 = ips.iter().find(|ip| ip.is_ipv6());
+        for &(rtype, ip) in &[("A", v4), ("AAAA", v6)] {
+            let ip = match ip {
+                Some(ip) => ip,
+                None => continue,
+            };
+            let records = format!("/zones/{}/dns_records", zone_id);
+            let body = cloudflare_record(rtype, &self.record, ip);
+            match try!(self.find_id(&format!("{}?type={}", records, rtype), &self.record)) {
+                Some(id) => try!(self.request(Method::Put, &format!("{}/{}", records, id), Some(body))),
+                None => try!(self.request(Method::Post, &records, Some(body))),
+            };
+            info!("Cloudflare {} {} => {}", rtype, self.record, ip);
+        }
+        Ok(())
+    }
+}
+
+/// The `result` of a Cloudflare API response, or its errors
+fn cloudflare_result(json: Json) -> Result<Json, Error> {
+    if json.find("success").and_then(|val| val.as_boolean()) == Some(true) {
+        return Ok(json.find("result").cloned().unwrap_or(Json::Null));
+    }
+    let errors = json.find("errors")
+                    .and_then(|errors| errors.as_array())
+                    .map(|errors| {
+                        errors.iter()
+                            .filter_map(|err| err.find("message").and_then(|msg| msg.as_string()))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    })
+                    .unwrap_or_default();
+    Err(Error::Protocol(format!("Cloudflare API error: {}", errors)))
+}
+
+/// The JSON body to create or replace a record
+fn cloudflare_record(rtype: &str, name: &str, ip: &MyIp) -> String {
+    let mut obj = BTreeMap::new();
+    obj.insert("type".to_owned(), Json::String(rtype.to_owned()));
+    obj.insert("name".to_owned(), Json::String(name.to_owned()));
+    obj.insert("content".to_owned(), Json::String(ip.to_string()));
+    // Automatic TTL
+    obj.insert("ttl".to_owned(), Json::U64(1));
+    obj.insert("proxied".to_owned(), Json::Boolean(false));
+    Json::Object(obj).to_string()
+}
+
+/// DuckDNS update URL
+pub const DUCKDNS_URL: &'static str = "https://www.duckdns.org/update";
+
+/// Updates DuckDNS subdomains with the account token
+pub struct DuckDns {
+    url: String,
+    domains: String,
+    token: String,
+    timeout: Duration,
+}
+
+impl DuckDns {
+    /// `domains` is a comma separated list of subdomains, without
+    /// `.duckdns.org`
+    pub fn new(domains: &str, token: &str) -> DuckDns {
+        DuckDns {
+            url: DUCKDNS_URL.to_owned(),
+            domains: domains.to_owned(),
+            token: token.to_owned(),
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// (defaults to **10 seconds**)
+    pub fn timeout(mut self, timeout: Duration) -> DuckDns {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The update URL, with the first IPv4 and IPv6 address in `ips`
+    fn update_url(&self, ips: &[MyIp]) -> String {
+        let mut url = format!("{}?domains={}&token={}", self.url,
+                              percent_encode(&self.domains), percent_encode(&self.token));
+        if let Some(ip) = ips.iter().find(|ip| ip.is_ipv4()) {
+            url.push_str(&format!("&ip={}", ip));
+        }
+        if let Some(ip) = ips.iter().find(|ip| ip.is_ipv6()) {
+            url.push_str(&format!("&ipv6={}", percent_encode(&ip.to_string())));
+        }
+        url
+    }
+
+}
+
+impl Updater for DuckDns {
+    fn name(&self) -> &str {
+        &self.domains
+    }
+
+    fn update(&self, ip: &MyIp) -> Result<(), Error> {
+        self.update_all(&[*ip])
+    }
+
+    /// Point the domains to `ips`
+    fn update_all(&self, ips: &[MyIp]) -> Result<(), Error> {
+        let cli = client::new_client(&ClientConfig::new(Some(self.timeout)));
+        let mut res = try!(cli.get(&self.update_url(ips))
+                            .send()
+                            .map_err(Error::from));
+        if !res.status.is_success() {
+            return Err(Error::Http { status: res.status.to_u16() });
+        }
+        let mut s = String::new();
+        try!(res.read_to_string(&mut s)
+            .map_err(Error::from));
+        debug!("{} => {}", self.domains, s.trim());
+        // The answer is just OK or KO
+        if s.trim() != "OK" {
+            return Err(Error::Protocol(format!("DuckDNS update failed: {}", s.trim())));
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_dyndns2() {
+    use std::str::FromStr;
+    let ddns = Dyndns2::new(NOIP_URL, "a.example.com,b.example.com", "user", "pass");
+    let ips = [MyIp::from_str("192.0.2.1").unwrap(), MyIp::from_str("2001:db8::1").unwrap()];
+    assert_eq!(ddns.update_url(&ips),
+               "https://dynupdate.no-ip.com/nic/update?hostname=a.example.com,b.example.com\
+                &myip=192.0.2.1,2001:db8::1");
+    assert!(check_response("good 192.0.2.1\nnochg 192.0.2.1\n").is_ok());
+    assert!(check_response("badauth").is_err());
+}
+
+#[test]
+fn test_cloudflare_result() {
+    let ok = Json::from_str(r#"{"success":true,"errors":[],"result":[{"id":"abc"}]}"#).unwrap();
+    assert!(cloudflare_result(ok).unwrap().is_array());
+    let err = Json::from_str(r#"{"success":false,"errors":[{"code":9109,"message":"Invalid access token"}]}"#).unwrap();
+    match cloudflare_result(err) {
+        Err(Error::Protocol(msg)) => assert!(msg.contains("Invalid access token")),
+        _ => panic!(),
+    }
+}
+
+#[test]
+fn test_duckdns() {
+    use std::str::FromStr;
+    let duck = DuckDns::new("myhome", "token");
+    assert_eq!(duck.update_url(&[MyIp::from_str("2001:db8::1").unwrap(),
+                                 MyIp::from_str("192.0.2.1").unwrap()]),
+               "https://www.duckdns.org/update?domains=myhome&token=token&ip=192.0.2.1&ipv6=2001:db8::1");
+}
+
+#[test]
+fn test_updater_update_all() {
+    use std::str::FromStr;
+    use std::sync::Mutex;
+    struct Record(Mutex<Vec<MyIp>>);
+    impl Updater for Record {
+        fn name(&self) -> &str {
+            "record"
+        }
+        fn update(&self, ip: &MyIp) -> Result<(), Error> {
+            self.0.lock().unwrap().push(*ip);
+            Ok(())
+        }
+    }
+    let ips = vec![MyIp::from_str("192.0.2.1").unwrap(), MyIp::from_str("2001:db8::1").unwrap()];
+    let record = Record(Mutex::new(Vec::new()));
+    record.update_all(&ips).unwrap();
+    assert_eq!(*record.0.lock().unwrap(), ips);
+}
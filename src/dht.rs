@@ -0,0 +1,190 @@
+//!
+//! BitTorrent DHT nodes include the address they see us with in
+//! their replies (the `ip` key from BEP 42)
+//!
+
+use std::cmp::Reverse;
+use std::net::{UdpSocket, ToSocketAddrs, Ipv4Addr, Ipv6Addr};
+use std::time::{Duration, Instant};
+use rand::{thread_rng, Rng};
+use {MyIp, Error};
+use bind::Bind;
+
+/// Well known DHT bootstrap nodes
+pub const DHT_NODES: &'static [&'static str] = &[
+    "router.bittorrent.com:6881",
+    "dht.transmissionbt.com:6881",
+    "router.utorrent.com:6881",
+    ];
+
+/// Returns the position after the bencoded value at `pos`
+fn skip_value(buf: &[u8], pos: usize) -> Result<usize, Error> {
+    if pos >= buf.len() {
+        return Err(Error::Parse("Truncated bencoded value".to_owned()));
+    }
+    match buf[pos] {
+        b'i' => match buf[pos..].iter().position(|&b| b == b'e') {
+            Some(end) => Ok(pos + end + 1),
+            None => Err(Error::Parse("Truncated bencoded integer".to_owned())),
+        },
+        b'l' | b'd' => {
+            let mut pos = pos + 1;
+            while pos < buf.len() && buf[pos] != b'e' {
+                pos = try!(skip_value(buf, pos));
+            }
+            Ok(pos + 1)
+        },
+        b'0'..=b'9' => read_string(buf, pos).map(|(_, end)| end),
+        b => Err(Error::Parse(format!("Invalid bencoded value {}", b))),
+    }
+}
+
+/// Returns the string at `pos` and the position after it
+fn read_string(buf: &[u8], pos: usize) -> Result<(&[u8], usize), Error> {
+    let colon = match buf[pos..].iter().position(|&b| b == b':') {
+        Some(colon) => pos + colon,
+        None => return Err(Error::Parse("Truncated bencoded string".to_owned())),
+    };
+    let len = try!(String::from_utf8_lossy(&buf[pos..colon]).parse::<usize>()
+                    .map_err(Error::from));
+    // The length comes from the network, it may overflow
+    let end = match len.checked_add(colon + 1) {
+        Some(end) if end <= buf.len() => end,
+        _ => return Err(Error::Parse("Truncated bencoded string".to_owned())),
+    };
+    Ok((&buf[colon+1..end], end))
+}
+
+/// Look up a string value in the top level dictionary
+fn dict_get<'a>(buf: &'a [u8], key: &[u8]) -> Result<Option<&'a [u8]>, Error> {
+    if buf.first() != Some(&b'd') {
+        return Err(Error::Parse("Expected a bencoded dictionary".to_owned()));
+    }
+    let mut pos = 1;
+    while pos < buf.len() && buf[pos] != b'e' {
+        let (k, next) = try!(read_string(buf, pos));
+        if k == key && next < buf.len() && buf[next] != b'd' && buf[next] != b'l' {
+            return read_string(buf, next).map(|(val, _)| Some(val));
+        }
+        pos = try!(skip_value(buf, next));
+    }
+    Ok(None)
+}
+
+/// Compact address and port (BEP 5)
+fn parse_compact(val: &[u8]) -> Result<MyIp, Error> {
+    match val.len() {
+        6 => Ok(MyIp::V4(Ipv4Addr::new(val[0], val[1], val[2], val[3]))),
+        18 => {
+            let mut segments = [0u16; 8];
+            for i in 0..8 {
+                segments[i] = (val[2*i] as u16) << 8 | val[2*i+1] as u16;
+            }
+            Ok(MyIp::V6(Ipv6Addr::new(segments[0], segments[1], segments[2], segments[3],
+                                      segments[4], segments[5], segments[6], segments[7])))
+        },
+        len => Err(Error::Parse(format!("Invalid compact address length {}", len))),
+    }
+}
+
+fn ping_query(tid: &[u8; 2], node_id: &[u8; 20]) -> Vec<u8> {
+    let mut msg = Vec::new();
+    msg.extend_from_slice(b"d1:ad2:id20:");
+    msg.extend_from_slice(node_id);
+    msg.extend_from_slice(b"e1:q4:ping1:t2:");
+    msg.extend_from_slice(tid);
+    msg.extend_from_slice(b"1:y1:qe");
+    msg
+}
+
+/// Ping the DHT nodes from `bind` and return the address most of
+/// them report, the nodes are IPv4 only
+pub fn dht_ip(nodes: &[String], bind: Bind, timeout: Duration) -> Result<MyIp, Error> {
+    let local = try!(bind.ipv4()
+                        .ok_or(Error::Protocol("No local IPv4 address for the DHT".to_owned())));
+    let socket = try!(UdpSocket::bind((local, 0))
+                        .map_err(Error::from));
+    try!(socket.set_read_timeout(Some(timeout))
+            .map_err(Error::from));
+
+    let mut node_id = [0u8; 20];
+    thread_rng().fill_bytes(&mut node_id);
+    let mut tid = [0u8; 2];
+    thread_rng().fill_bytes(&mut tid);
+    let query = ping_query(&tid, &node_id);
+
+    let mut sent = 0;
+    for node in nodes {
+        let addr = match node.to_socket_addrs().ok().and_then(|mut addrs| addrs.find(|a| a.is_ipv4())) {
+            Some(addr) => addr,
+            None => {
+                info!("Unable to resolve {}", node);
+                continue;
+            },
+        };
+        match socket.send_to(&query, addr) {
+            Ok(_) => sent += 1,
+            Err(err) => info!("{} => {}", node, err),
+        }
+    }
+
+    // (address, votes)
+    let mut votes: Vec<(MyIp, usize)> = Vec::new();
+    let start = Instant::now();
+    let mut buf = [0u8; 1500];
+    for _ in 0..sent {
+        let (len, from) = match socket.recv_from(&mut buf) {
+            Ok(res) => res,
+            Err(_) => break,
+        };
+        let reply = &buf[..len];
+        match dict_get(reply, b"t") {
+            Ok(Some(t)) if t == &tid[..] => (),
+            _ => continue,
+        }
+        match dict_get(reply, b"ip").and_then(|val| val.ok_or(Error::Parse("No ip key".to_owned())))
+                .and_then(parse_compact) {
+            Ok(ip) => {
+                debug!("DHT {} => {}", from, ip);
+                match votes.iter().position(|&(ref v, _)| *v == ip) {
+                    Some(idx) => votes[idx].1 += 1,
+                    None => votes.push((ip, 1)),
+                }
+            },
+            Err(err) => info!("DHT {} => {}", from, err),
+        }
+
+        let elapsed = start.elapsed();
+        if elapsed >= timeout {
+            break;
+        }
+        try!(socket.set_read_timeout(Some(timeout - elapsed))
+                .map_err(Error::from));
+    }
+
+    if votes.len() > 1 {
+        info!("DHT nodes disagree on our address");
+    }
+    votes.sort_by_key(|vote| Reverse(vote.1));
+    match votes.into_iter().next() {
+        Some((ip, _)) => Ok(ip),
+        None => Err(Error::Parse("No DHT node reported our address".to_owned())),
+    }
+}
+
+#[test]
+fn test_dict_get() {
+    let reply = b"d2:ip6:\xc0\x00\x02\x01\x1a\xe11:rd2:id20:abcdefghij0123456789e1:t2:aa1:y1:re";
+    assert_eq!(dict_get(reply, b"t").unwrap(), Some(&b"aa"[..]));
+    let ip = parse_compact(dict_get(reply, b"ip").unwrap().unwrap()).unwrap();
+    assert!(ip == MyIp::V4(Ipv4Addr::new(192, 0, 2, 1)));
+    assert_eq!(dict_get(reply, b"missing").unwrap(), None);
+}
+
+#[test]
+fn test_read_string_length() {
+    assert_eq!(read_string(b"2:ab", 0).unwrap(), (&b"ab"[..], 4));
+    assert!(read_string(b"3:ab", 0).is_err());
+    assert!(read_string(b"18446744073709551615:ab", 0).is_err());
+    assert!(dict_get(b"d2:ip18446744073709551615:abe", b"ip").is_err());
+}
@@ -0,0 +1,530 @@
+//!
+//! Minimal DNS client, just enough to ask resolvers that
+//! answer with the address of the client (e.g. myip.opendns.com)
+//!
+//! Queries go over UDP, or over HTTPS (RFC 8484) for providers with
+//! a DoH endpoint, which helps where UDP/53 to public resolvers is blocked.
+//!
+
+use std::fs::File;
+use std::io::Read;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+use rand::random;
+#[cfg(feature = "http")]
+use hyper::header::Headers;
+use {MyIp, Error, IpFamily, ip_from_str};
+use bind::Bind;
+#[cfg(feature = "http")]
+use client::{self, ClientConfig};
+use error;
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_TXT: u16 = 16;
+pub const TYPE_AAAA: u16 = 28;
+pub const CLASS_IN: u16 = 1;
+pub const CLASS_CH: u16 = 3;
+/// The name does not exist
+const RCODE_NXDOMAIN: u8 = 3;
+
+/// A DNS query whose answer is the address of the client
+#[derive(Clone)]
+pub struct DnsProvider {
+    /// Name used in logs
+    pub name: &'static str,
+    /// Resolver address (host:port), unused if `doh` is set
+    pub server: &'static str,
+    /// DNS over HTTPS endpoint, if set the query is sent there
+    pub doh: Option<&'static str>,
+    /// Name to query for
+    pub query: &'static str,
+    /// Query type, one of `TYPE_A`, `TYPE_AAAA` or `TYPE_TXT`
+    pub qtype: u16,
+    /// Query class, `CLASS_IN` or `CLASS_CH`
+    pub qclass: u16,
+}
+
+impl DnsProvider {
+    /// The family we reach the resolver over, from the server
+    /// address, Both for a host name or a DoH endpoint
+    pub fn transport(&self) -> IpFamily {
+        if self.doh.is_some() {
+            return IpFamily::Both;
+        }
+        match self.server.parse() {
+            Ok(SocketAddr::V4(_)) => IpFamily::V4,
+            Ok(SocketAddr::V6(_)) => IpFamily::V6,
+            Err(_) => IpFamily::Both,
+        }
+    }
+}
+
+/// Default DNS providers
+pub const DNS_PROVIDERS: &'static [DnsProvider] = &[
+    DnsProvider {
+        name: "opendns",
+        server: "208.67.222.222:53",
+        query: "myip.opendns.com",
+        qtype: TYPE_A,
+        qclass: CLASS_IN,
+        doh: None,
+    },
+    DnsProvider {
+        name: "opendns6",
+        server: "[2620:119:35::35]:53",
+        query: "myip.opendns.com",
+        qtype: TYPE_AAAA,
+        qclass: CLASS_IN,
+        doh: None,
+    },
+    DnsProvider {
+        name: "cloudflare",
+        server: "1.1.1.1:53",
+        query: "whoami.cloudflare",
+        qtype: TYPE_TXT,
+        qclass: CLASS_CH,
+        doh: None,
+    },
+    DnsProvider {
+        name: "cloudflare6",
+        server: "[2606:4700:4700::1111]:53",
+        query: "whoami.cloudflare",
+        qtype: TYPE_TXT,
+        qclass: CLASS_CH,
+        doh: None,
+    },
+    DnsProvider {
+        name: "google",
+        server: "ns1.google.com:53",
+        query: "o-o.myaddr.l.google.com",
+        qtype: TYPE_TXT,
+        qclass: CLASS_IN,
+        doh: None,
+    },
+    ];
+
+/// The same queries over DNS over HTTPS, for use with
+/// `WhatsMyIp::dns_providers()`. Google is left out since its
+/// public resolver answers with its own address.
+pub const DOH_PROVIDERS: &'static [DnsProvider] = &[
+    DnsProvider {
+        name: "opendns-doh",
+        server: "doh.opendns.com:443",
+        query: "myip.opendns.com",
+        qtype: TYPE_A,
+        qclass: CLASS_IN,
+        doh: Some("https://doh.opendns.com/dns-query"),
+    },
+    DnsProvider {
+        name: "cloudflare-doh",
+        server: "cloudflare-dns.com:443",
+        query: "whoami.cloudflare",
+        qtype: TYPE_TXT,
+        qclass: CLASS_CH,
+        doh: Some("https://cloudflare-dns.com/dns-query"),
+    },
+    ];
+
+/// Resolver for lookups that are not about our address, e.g. PTR
+/// records (OpenDNS)
+pub const DEFAULT_RESOLVER: &'static str = "208.67.222.222:53";
+
+/// Resource record data, only the types we care about
+enum Record {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Txt(String),
+    Ptr(String),
+    Other,
+}
+
+fn read_u16(buf: &[u8], pos: usize) -> u16 {
+    (buf[pos] as u16) << 8 | buf[pos+1] as u16
+}
+
+fn push_u16(buf: &mut Vec<u8>, val: u16) {
+    buf.push((val >> 8) as u8);
+    buf.push(val as u8);
+}
+
+fn build_query(id: u16, name: &str, qtype: u16, qclass: u16) -> Vec<u8> {
+    let mut req = Vec::with_capacity(32 + name.len());
+    push_u16(&mut req, id);
+    // Standard query, recursion desired
+    push_u16(&mut req, 0x0100);
+    // QDCOUNT, ANCOUNT, NSCOUNT, ARCOUNT
+    push_u16(&mut req, 1);
+    push_u16(&mut req, 0);
+    push_u16(&mut req, 0);
+    push_u16(&mut req, 0);
+    for label in name.trim_right_matches('.').split('.') {
+        req.push(label.len() as u8);
+        req.extend_from_slice(label.as_bytes());
+    }
+    req.push(0);
+    push_u16(&mut req, qtype);
+    push_u16(&mut req, qclass);
+    req
+}
+
+/// Returns the position after the (possibly compressed) name at `pos`
+fn skip_name(buf: &[u8], mut pos: usize) -> Result<usize, Error> {
+    loop {
+        if pos >= buf.len() {
+            return Err(Error::Parse("Truncated DNS name".to_owned()));
+        }
+        let len = buf[pos] as usize;
+        if len == 0 {
+            return Ok(pos + 1);
+        } else if len & 0xc0 == 0xc0 {
+            // Compression pointer, the name ends here
+            return Ok(pos + 2);
+        }
+        pos += 1 + len;
+    }
+}
+
+/// The (possibly compressed) name at `pos`
+fn read_name(buf: &[u8], mut pos: usize) -> Result<String, Error> {
+    let mut labels = Vec::new();
+    // Bounded, a pointer loop would never end
+    for _ in 0..128 {
+        if pos >= buf.len() {
+            return Err(Error::Parse("Truncated DNS name".to_owned()));
+        }
+        let len = buf[pos] as usize;
+        if len == 0 {
+            return Ok(labels.join("."));
+        } else if len & 0xc0 == 0xc0 {
+            if pos + 1 >= buf.len() {
+                return Err(Error::Parse("Truncated DNS name".to_owned()));
+            }
+            pos = (len & 0x3f) << 8 | buf[pos+1] as usize;
+            continue;
+        }
+        if pos + 1 + len > buf.len() {
+            return Err(Error::Parse("Truncated DNS name".to_owned()));
+        }
+        labels.push(String::from_utf8_lossy(&buf[pos+1..pos+1+len]).into_owned());
+        pos += 1 + len;
+    }
+    Err(Error::Parse("DNS name loop".to_owned()))
+}
+
+/// The records, or an error for any response code but success
+#[cfg_attr(not(feature = "http"), allow(dead_code))]
+fn parse_response(buf: &[u8], id: u16) -> Result<Vec<Record>, Error> {
+    parse_message(buf, id).and_then(check_rcode)
+}
+
+fn check_rcode((rcode, records): (u8, Vec<Record>)) -> Result<Vec<Record>, Error> {
+    if rcode != 0 {
+        return Err(Error::Protocol(format!("DNS error code {}", rcode)));
+    }
+    Ok(records)
+}
+
+/// The response code and the records, none unless it succeeded
+fn parse_message(buf: &[u8], id: u16) -> Result<(u8, Vec<Record>), Error> {
+    if buf.len() < 12 {
+        return Err(Error::Parse("Truncated DNS response".to_owned()));
+    }
+    if read_u16(buf, 0) != id {
+        return Err(Error::Parse("DNS response id mismatch".to_owned()));
+    }
+    let rcode = buf[3] & 0x0f;
+    if rcode != 0 {
+        return Ok((rcode, Vec::new()));
+    }
+
+    let qdcount = read_u16(buf, 4);
+    let ancount = read_u16(buf, 6);
+    let mut pos = 12;
+    for _ in 0..qdcount {
+        pos = try!(skip_name(buf, pos)) + 4;
+    }
+
+    let mut records = Vec::new();
+    for _ in 0..ancount {
+        pos = try!(skip_name(buf, pos));
+        if pos + 10 > buf.len() {
+            return Err(Error::Parse("Truncated DNS record".to_owned()));
+        }
+        let rtype = read_u16(buf, pos);
+        let rdlen = read_u16(buf, pos+8) as usize;
+        pos += 10;
+        if pos + rdlen > buf.len() {
+            return Err(Error::Parse("Truncated DNS record".to_owned()));
+        }
+        let rdata = &buf[pos..pos+rdlen];
+        records.push(match rtype {
+            TYPE_A if rdlen == 4 => {
+                Record::A(Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3]))
+            },
+            TYPE_AAAA if rdlen == 16 => {
+                let mut segments = [0u16; 8];
+                for (i, segment) in segments.iter_mut().enumerate() {
+                    *segment = read_u16(rdata, 2*i);
+                }
+                Record::Aaaa(Ipv6Addr::new(segments[0], segments[1], segments[2], segments[3],
+                                           segments[4], segments[5], segments[6], segments[7]))
+            },
+            TYPE_TXT => {
+                // One or more <length><text> strings
+                let mut txt = String::new();
+                let mut i = 0;
+                while i < rdlen {
+                    let end = i + 1 + rdata[i] as usize;
+                    if end > rdlen {
+                        return Err(Error::Parse("Truncated TXT record".to_owned()));
+                    }
+                    txt.push_str(&String::from_utf8_lossy(&rdata[i+1..end]));
+                    i = end;
+                }
+                Record::Txt(txt)
+            },
+            TYPE_PTR => Record::Ptr(try!(read_name(buf, pos))),
+            _ => Record::Other,
+        });
+        pos += rdlen;
+    }
+    Ok((0, records))
+}
+
+fn query(server: &SocketAddr, bind: Bind, name: &str, qtype: u16, qclass: u16,
+         timeout: Option<Duration>) -> Result<Vec<Record>, Error> {
+    query_message(server, bind, name, qtype, qclass, timeout).and_then(check_rcode)
+}
+
+/// Same as `query()` but other response codes are not an error
+fn query_message(server: &SocketAddr, bind: Bind, name: &str, qtype: u16, qclass: u16,
+                 timeout: Option<Duration>) -> Result<(u8, Vec<Record>), Error> {
+    let socket = try!(bind.udp(server)
+                        .map_err(Error::from));
+    try!(socket.set_read_timeout(timeout)
+            .map_err(Error::from));
+
+    let id = random::<u16>();
+    try!(socket.send_to(&build_query(id, name, qtype, qclass), server)
+            .map_err(Error::from));
+
+    let mut buf = [0u8; 512];
+    let (len, _) = try!(socket.recv_from(&mut buf)
+                        .map_err(Error::from));
+    parse_message(&buf[..len], id)
+}
+
+/// Send the query in the body of a POST, using the wire format
+#[cfg(feature = "http")]
+fn query_https(url: &str, bind: Bind, name: &str, qtype: u16, qclass: u16,
+               timeout: Option<Duration>) -> Result<Vec<Record>, Error> {
+    let cli = client::new_client(&ClientConfig {
+        bind: bind,
+        ..ClientConfig::new(timeout)
+    });
+
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"application/dns-message".to_vec()]);
+    headers.set_raw("Accept", vec![b"application/dns-message".to_vec()]);
+    // RFC 8484 recommends id 0, the response is matched by the request
+    let req = build_query(0, name, qtype, qclass);
+    let mut res = try!(cli.post(url)
+                        .headers(headers)
+                        .body(&req[..])
+                        .send()
+                        .map_err(Error::from));
+    if !res.status.is_success() {
+        return Err(Error::Http { status: res.status.to_u16() });
+    }
+
+    let mut buf = Vec::new();
+    try!(res.read_to_end(&mut buf)
+        .map_err(Error::from));
+    parse_response(&buf, 0)
+}
+
+#[cfg(not(feature = "http"))]
+fn query_https(url: &str, _: Bind, _: &str, _: u16, _: u16,
+               _: Option<Duration>) -> Result<Vec<Record>, Error> {
+    Err(Error::Protocol(format!("DNS over HTTPS ({}) needs the http feature", url)))
+}
+
+/// Query a DNS provider and return the first address in the answer
+pub fn dns_ip(provider: &DnsProvider, timeout: Option<Duration>) -> Result<MyIp, Error> {
+    dns_ip_bound(provider, Bind::Any, timeout)
+}
+
+pub(crate) fn dns_ip_bound(provider: &DnsProvider, bind: Bind,
+                           timeout: Option<Duration>) -> Result<MyIp, Error> {
+    let ip = try!(lookup_ip_bound(provider.server, provider.doh, provider.query,
+                                  provider.qtype, provider.qclass, bind, timeout));
+    debug!("{} => {}", provider.name, ip);
+    Ok(ip)
+}
+
+/// Same as `dns_ip()` without a `DnsProvider`, if `doh` is set
+/// the query goes there instead of `server`
+pub fn lookup_ip(server: &str, doh: Option<&str>, name: &str, qtype: u16, qclass: u16,
+                 timeout: Option<Duration>) -> Result<MyIp, Error> {
+    lookup_ip_bound(server, doh, name, qtype, qclass, Bind::Any, timeout)
+}
+
+fn server_addr(server: &str) -> Result<SocketAddr, Error> {
+    match try!(server.to_socket_addrs()
+                .map_err(Error::from)).next() {
+        Some(addr) => Ok(addr),
+        None => Err(error::unresolved(server)),
+    }
+}
+
+pub(crate) fn lookup_ip_bound(server: &str, doh: Option<&str>, name: &str, qtype: u16,
+                              qclass: u16, bind: Bind,
+                              timeout: Option<Duration>) -> Result<MyIp, Error> {
+    let records = if let Some(url) = doh {
+        try!(query_https(url, bind, name, qtype, qclass, timeout))
+    } else {
+        try!(query(&try!(server_addr(server)), bind, name, qtype, qclass, timeout))
+    };
+    for record in records {
+        let ip = match record {
+            Record::A(ip) => MyIp::V4(ip),
+            Record::Aaaa(ip) => MyIp::V6(ip),
+            Record::Txt(ref txt) => match ip_from_str(txt) {
+                Ok(ip) => ip,
+                Err(_) => continue,
+            },
+            Record::Ptr(_) | Record::Other => continue,
+        };
+        return Ok(ip);
+    }
+    Err(Error::Parse(format!("No address in DNS answer from {}", doh.unwrap_or(server))))
+}
+
+/// The labels of the reverse name of `ip`, octets or nibbles in
+/// reverse order, without the zone
+pub(crate) fn reverse_labels(ip: &MyIp) -> String {
+    match *ip {
+        MyIp::V4(ip) => {
+            let o = ip.octets();
+            format!("{}.{}.{}.{}", o[3], o[2], o[1], o[0])
+        },
+        MyIp::V6(ip) => {
+            let mut nibbles = Vec::with_capacity(32);
+            for seg in ip.segments().iter().rev() {
+                for shift in &[0, 4, 8, 12] {
+                    nibbles.push(format!("{:x}", (seg >> shift) & 0xf));
+                }
+            }
+            nibbles.join(".")
+        },
+    }
+}
+
+/// The host name of `ip` from its PTR record, asking `server`
+pub fn lookup_ptr(server: &str, ip: &MyIp, timeout: Option<Duration>) -> Result<Option<String>, Error> {
+    let zone = if ip.is_ipv4() { "in-addr.arpa" } else { "ip6.arpa" };
+    let name = format!("{}.{}", reverse_labels(ip), zone);
+    let records = try!(query(&try!(server_addr(server)), Bind::Any, &name, TYPE_PTR, CLASS_IN, timeout));
+    Ok(records.into_iter()
+        .filter_map(|record| match record {
+            Record::Ptr(host) => Some(host),
+            _ => None,
+        })
+        .next())
+}
+
+/// The A records for `name`, asking `server`. A name that does not
+/// exist is not an error, e.g. an address missing from a DNSBL.
+pub fn lookup_a(server: &str, name: &str, timeout: Option<Duration>) -> Result<Vec<Ipv4Addr>, Error> {
+    let (rcode, records) = try!(query_message(&try!(server_addr(server)), Bind::Any, name,
+                                              TYPE_A, CLASS_IN, timeout));
+    if rcode == RCODE_NXDOMAIN {
+        return Ok(Vec::new());
+    }
+    Ok(try!(check_rcode((rcode, records))).into_iter()
+        .filter_map(|record| match record {
+            Record::A(ip) => Some(ip),
+            _ => None,
+        })
+        .collect())
+}
+
+/// The first nameserver in /etc/resolv.conf, some services (e.g.
+/// Spamhaus) refuse queries from the big public resolvers
+pub fn system_resolver() -> Option<String> {
+    let mut conf = String::new();
+    if File::open("/etc/resolv.conf").and_then(|mut f| f.read_to_string(&mut conf)).is_err() {
+        return None;
+    }
+    conf.lines()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some("nameserver"), Some(addr)) => addr.parse::<IpAddr>().ok(),
+                _ => None,
+            }
+        })
+        .next()
+        .map(|ip| SocketAddr::new(ip, 53).to_string())
+}
+
+/// The TXT records for `name`, asking `server`
+pub fn lookup_txt(server: &str, name: &str, timeout: Option<Duration>) -> Result<Vec<String>, Error> {
+    let records = try!(query(&try!(server_addr(server)), Bind::Any, name, TYPE_TXT, CLASS_IN, timeout));
+    Ok(records.into_iter()
+        .filter_map(|record| match record {
+            Record::Txt(txt) => Some(txt),
+            _ => None,
+        })
+        .collect())
+}
+
+#[test]
+fn test_parse_a_record() {
+    let mut res = build_query(0x1234, "myip.opendns.com", TYPE_A, CLASS_IN);
+    // Flip to a response with one answer
+    res[2] = 0x81;
+    res[3] = 0x80;
+    res[7] = 1;
+    res.extend_from_slice(&[0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01,
+                            0x00, 0x00, 0x00, 0x00, 0x00, 0x04,
+                            192, 0, 2, 1]);
+    let ip = match parse_response(&res, 0x1234).unwrap().pop() {
+        Some(Record::A(ip)) => ip,
+        _ => panic!("Expected an A record"),
+    };
+    assert_eq!(ip, Ipv4Addr::new(192, 0, 2, 1));
+}
+
+#[test]
+fn test_parse_txt_record() {
+    let mut res = build_query(0x4321, "whoami.cloudflare", TYPE_TXT, CLASS_CH);
+    res[2] = 0x81;
+    res[3] = 0x80;
+    res[7] = 1;
+    res.extend_from_slice(&[0xc0, 0x0c, 0x00, 0x10, 0x00, 0x03,
+                            0x00, 0x00, 0x00, 0x00, 0x00, 0x0a, 0x09]);
+    res.extend_from_slice(b"192.0.2.1");
+    match parse_response(&res, 0x4321).unwrap().pop() {
+        Some(Record::Txt(txt)) => assert_eq!(txt, "192.0.2.1"),
+        _ => panic!("Expected a TXT record"),
+    }
+}
+
+#[test]
+fn test_parse_ptr_record() {
+    let mut res = build_query(0x5678, "1.2.0.192.in-addr.arpa", TYPE_PTR, CLASS_IN);
+    res[2] = 0x81;
+    res[3] = 0x80;
+    res[7] = 1;
+    // host + a pointer to the question name
+    res.extend_from_slice(&[0xc0, 0x0c, 0x00, 0x0c, 0x00, 0x01,
+                            0x00, 0x00, 0x00, 0x00, 0x00, 0x07,
+                            0x04, b'h', b'o', b's', b't', 0xc0, 0x0c]);
+    match parse_response(&res, 0x5678).unwrap().pop() {
+        Some(Record::Ptr(host)) => assert_eq!(host, "host.1.2.0.192.in-addr.arpa"),
+        _ => panic!("Expected a PTR record"),
+    }
+    assert_eq!(reverse_labels(&MyIp::V4(Ipv4Addr::new(192, 0, 2, 1))), "1.2.0.192");
+}
@@ -0,0 +1,131 @@
+//!
+//! Check if an address is on DNS blocklists, e.g. after the address
+//! of a mail server changed
+//!
+//! ```no_run
+//! use whatsmyip::{whatsmyip, DnsblCheck};
+//! let ip = whatsmyip().unwrap();
+//! for res in DnsblCheck::new().check(&ip).unwrap() {
+//!     if res.is_listed() {
+//!         println!("Listed in {}: {:?}", res.list, res.reason);
+//!     }
+//! }
+//! ```
+//!
+
+use std::net::Ipv4Addr;
+use std::time::Duration;
+use {MyIp, Error};
+use dns;
+
+/// Default blocklists
+pub const DNSBLS: &'static [&'static str] = &[
+    "zen.spamhaus.org",
+    "bl.spamcop.net",
+    "b.barracudacentral.org",
+    "psbl.surriel.com",
+    "dnsbl-1.uceprotect.net",
+];
+
+/// The answer from one blocklist
+#[derive(Debug)]
+pub struct DnsblResult {
+    /// The blocklist zone
+    pub list: String,
+    /// The return codes (e.g. 127.0.0.2), empty if not listed
+    pub codes: Result<Vec<Ipv4Addr>, Error>,
+    /// Why it is listed, from the TXT record
+    pub reason: Option<String>,
+}
+
+impl DnsblResult {
+    pub fn is_listed(&self) -> bool {
+        self.codes.as_ref().map(|codes| !codes.is_empty()).unwrap_or(false)
+    }
+}
+
+/// Queries DNS blocklists for an IPv4 address
+pub struct DnsblCheck {
+    lists: Vec<String>,
+    server: Option<String>,
+    timeout: Duration,
+}
+
+/// Spamhaus answers 127.255.255.x when it refuses the query,
+/// e.g. from a public resolver, that is not a listing
+fn check_codes(list: &str, codes: Vec<Ipv4Addr>) -> Result<Vec<Ipv4Addr>, Error> {
+    match codes.iter().find(|ip| ip.octets()[..3] == [127, 255, 255]) {
+        Some(code) => Err(Error::Protocol(format!("{} refused the query ({})", list, code))),
+        None => Ok(codes),
+    }
+}
+
+impl DnsblCheck {
+    pub fn new() -> DnsblCheck {
+        DnsblCheck {
+            lists: DNSBLS.iter().map(|s| s.to_string()).collect(),
+            server: None,
+            timeout: Duration::from_secs(3),
+        }
+    }
+
+    /// The blocklist zones to query
+    /// (defaults to **DNSBLS**)
+    pub fn lists(&mut self, lists: &[&str]) -> &mut Self {
+        self.lists = lists.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    /// The resolver to ask, as host:port
+    /// (defaults to **None** i.e. the system resolver)
+    pub fn server(&mut self, server: &str) -> &mut Self {
+        self.server = Some(server.to_owned());
+        self
+    }
+
+    /// Timeout for each query
+    /// (defaults to **3 seconds**)
+    pub fn timeout(&mut self, t: Duration) -> &mut Self {
+        self.timeout = t;
+        self
+    }
+
+    /// Query each blocklist for `ip`, a list that fails does not
+    /// stop the others. Only IPv4 addresses are supported.
+    pub fn check(&self, ip: &MyIp) -> Result<Vec<DnsblResult>, Error> {
+        if !ip.is_ipv4() {
+            return Err(Error::Parse(format!("{} is not an IPv4 address", ip)));
+        }
+        let server = self.server.clone()
+                        .or_else(dns::system_resolver)
+                        .unwrap_or_else(|| dns::DEFAULT_RESOLVER.to_owned());
+        let labels = dns::reverse_labels(ip);
+        Ok(self.lists.iter()
+            .map(|list| {
+                let name = format!("{}.{}", labels, list);
+                let codes = dns::lookup_a(&server, &name, Some(self.timeout))
+                                .and_then(|codes| check_codes(list, codes));
+                let reason = match codes {
+                    Ok(ref codes) if !codes.is_empty() => {
+                        dns::lookup_txt(&server, &name, Some(self.timeout)).ok()
+                            .and_then(|txts| txts.into_iter().next())
+                    },
+                    _ => None,
+                };
+                debug!("{} => {:?}", name, codes);
+                DnsblResult {
+                    list: list.clone(),
+                    codes: codes,
+                    reason: reason,
+                }
+            })
+            .collect())
+    }
+}
+
+#[test]
+fn test_check_codes() {
+    assert_eq!(check_codes("zen.spamhaus.org", vec![Ipv4Addr::new(127, 0, 0, 2)]).unwrap(),
+               vec![Ipv4Addr::new(127, 0, 0, 2)]);
+    assert!(check_codes("zen.spamhaus.org", vec![Ipv4Addr::new(127, 255, 255, 254)]).is_err());
+}
@@ -0,0 +1,210 @@
+//!
+//! Errors returned by whatsmyip
+//!
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::num::ParseIntError;
+#[cfg(feature = "http")]
+use hyper;
+use regex;
+use rustc_serialize::json::ParserError;
+
+/// Why we could not get an address
+#[derive(Debug)]
+pub enum Error {
+    /// I/O error, e.g. no internet access
+    Network(io::Error),
+    /// The service answered with an HTTP error status
+    Http { status: u16 },
+    /// The answer did not hold a valid address
+    Parse(String),
+    /// The service answered with an error, e.g. a DNS error code
+    /// or a STUN error response
+    Protocol(String),
+    /// The source did not answer in time
+    Timeout,
+    /// The service redirected too many times or from HTTPS to
+    /// plain HTTP, e.g. a captive portal login page
+    Redirect { location: String },
+    /// The service answered with a web page from `url`, after
+    /// redirects, instead of an address
+    Html { url: String },
+    /// Several services answered with a login page or redirected
+    /// to the same host, sign in to the network at `url` first
+    CaptivePortal { url: Option<String> },
+    /// No source is enabled
+    NoSources,
+    /// Every source failed, with the error from each one
+    AllProvidersFailed(Vec<ProviderError>),
+}
+
+/// The error from one source
+#[derive(Debug)]
+pub struct ProviderError {
+    /// Provider name or server address
+    pub source: String,
+    pub error: Error,
+}
+
+/// `host` did not resolve to any address
+pub fn unresolved(host: &str) -> Error {
+    Error::Network(io::Error::new(io::ErrorKind::NotFound,
+                                  format!("Unable to resolve {}", host)))
+}
+
+/// The host of `url`, e.g. example.com for http://example.com:8080/x
+fn url_host(url: &str) -> &str {
+    let rest = match url.find("://") {
+        Some(pos) => &url[pos + 3..],
+        None => url,
+    };
+    let end = rest.find(&['/', '?', '#'][..]).unwrap_or(rest.len());
+    let host = &rest[..end];
+    match host.rfind(':') {
+        Some(pos) if !host[pos..].contains(']') => &host[..pos],
+        _ => host,
+    }
+}
+
+/// A captive portal, if two or more sources got a web page or
+/// were sent to the same host. The portal URL is known when the
+/// pages came from the same host.
+pub fn captive_portal(errors: &[ProviderError]) -> Option<Error> {
+    let pages: Vec<&str> = errors.iter()
+                            .filter_map(|err| match err.error {
+                                Error::Html { ref url } => Some(&url[..]),
+                                Error::Redirect { ref location } => Some(&location[..]),
+                                _ => None,
+                            })
+                            .collect();
+    let shared = pages.iter().enumerate()
+                    .find(|&(idx, url)| pages[idx + 1..].iter()
+                                            .any(|other| url_host(other) == url_host(url)))
+                    .map(|(_, url)| url.to_string());
+    let html = errors.iter()
+                .filter(|err| matches!(err.error, Error::Html { .. }))
+                .count();
+    if shared.is_some() || html >= 2 {
+        Some(Error::CaptivePortal { url: shared })
+    } else {
+        None
+    }
+}
+
+impl Error {
+    /// True if trying again later may work, e.g. a timeout
+    /// or a server error
+    pub fn is_transient(&self) -> bool {
+        match *self {
+            Error::Network(_) | Error::Timeout => true,
+            Error::Http { status } => status >= 500,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Network(ref err) => write!(f, "{}", err),
+            Error::Http { status } => write!(f, "HTTP status {}", status),
+            Error::Parse(ref msg) => write!(f, "{}", msg),
+            Error::Protocol(ref msg) => write!(f, "{}", msg),
+            Error::Timeout => write!(f, "Timed out"),
+            Error::Redirect { ref location } => write!(f, "Redirect to {} not followed", location),
+            Error::Html { ref url } => write!(f, "Web page from {} instead of an address", url),
+            Error::CaptivePortal { url: Some(ref url) } => write!(f, "Captive portal at {}", url),
+            Error::CaptivePortal { url: None } => write!(f, "Captive portal, sign in to the network"),
+            Error::NoSources => write!(f, "No address sources enabled"),
+            Error::AllProvidersFailed(ref errors) => {
+                try!(write!(f, "Unable to find any IP address"));
+                for (idx, err) in errors.iter().enumerate() {
+                    try!(write!(f, "{} {} => {}", if idx == 0 { ":" } else { "," },
+                                err.source, err.error));
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Network(_) => "network error",
+            Error::Http { .. } => "HTTP error status",
+            Error::Parse(_) => "invalid answer",
+            Error::Protocol(_) => "error answer",
+            Error::Timeout => "timed out",
+            Error::Redirect { .. } => "redirect not followed",
+            Error::Html { .. } => "web page instead of an address",
+            Error::CaptivePortal { .. } => "captive portal",
+            Error::NoSources => "no address sources enabled",
+            Error::AllProvidersFailed(_) => "unable to find any IP address",
+        }
+    }
+
+    fn cause(&self) -> Option<&dyn error::Error> {
+        match *self {
+            Error::Network(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        match err.kind() {
+            // Socket read timeouts are WouldBlock on Unix
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => Error::Timeout,
+            _ => Error::Network(err),
+        }
+    }
+}
+
+#[cfg(feature = "http")]
+impl From<hyper::Error> for Error {
+    fn from(err: hyper::Error) -> Error {
+        match err {
+            hyper::Error::Io(err) => Error::from(err),
+            err => Error::Parse(format!("{}", err)),
+        }
+    }
+}
+
+impl From<ParserError> for Error {
+    fn from(err: ParserError) -> Error {
+        Error::Parse(format!("{}", err))
+    }
+}
+
+impl From<ParseIntError> for Error {
+    fn from(err: ParseIntError) -> Error {
+        Error::Parse(format!("{}", err))
+    }
+}
+
+impl From<regex::Error> for Error {
+    fn from(err: regex::Error) -> Error {
+        Error::Parse(format!("{}", err))
+    }
+}
+
+#[test]
+fn test_captive_portal() {
+    let err = |source: &str, error: Error| ProviderError { source: source.to_owned(), error: error };
+    assert_eq!(url_host("http://[2001:db8::1]:8080/login"), "[2001:db8::1]");
+    let errors = vec![
+        err("icanhazip", Error::Html { url: "http://portal.example.com/login?from=a".to_owned() }),
+        err("ipify", Error::Timeout),
+        err("ipinfo", Error::Redirect { location: "http://portal.example.com:80/".to_owned() }),
+    ];
+    match captive_portal(&errors) {
+        Some(Error::CaptivePortal { url: Some(url) }) =>
+            assert_eq!(url, "http://portal.example.com/login?from=a"),
+        res => panic!("{:?}", res),
+    }
+    assert!(captive_portal(&errors[1..]).is_none());
+}
@@ -0,0 +1,195 @@
+//!
+//! C bindings, with the ffi feature, see `include/whatsmyip.h`
+//!
+//! The crate builds as a Rust library only, for the shared and static
+//! libraries, `target/release/libwhatsmyip.so` and `libwhatsmyip.a`, use
+//! `cargo rustc --release --lib --features ffi --crate-type cdylib,staticlib`
+//!
+//! ```c
+//! whatsmyip_result *res = whatsmyip_find(WHATSMYIP_BOTH);
+//! if (!res) {
+//!     char *err = whatsmyip_last_error();
+//!     fprintf(stderr, "%s\n", err);
+//!     whatsmyip_string_free(err);
+//!     return 1;
+//! }
+//! for (size_t i = 0; i < whatsmyip_result_len(res); i++) {
+//!     char *ip = whatsmyip_result_get(res, i);
+//!     printf("%s\n", ip);
+//!     whatsmyip_string_free(ip);
+//! }
+//! whatsmyip_result_free(res);
+//! ```
+//!
+
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int};
+use std::panic;
+use std::ptr;
+use {WhatsMyIp, MyIp, IpFamily};
+
+/// IPv4 and IPv6 addresses
+pub const WHATSMYIP_BOTH: c_int = 0;
+/// Only IPv4 addresses
+pub const WHATSMYIP_V4: c_int = 4;
+/// Only IPv6 addresses
+pub const WHATSMYIP_V6: c_int = 6;
+
+thread_local!(static LAST_ERROR: RefCell<Option<String>> = const { RefCell::new(None) });
+
+fn set_error(msg: String) {
+    LAST_ERROR.with(|err| *err.borrow_mut() = Some(msg));
+}
+
+/// A string the caller frees with `whatsmyip_string_free()`, NULL if
+/// `s` holds a NUL byte
+fn to_c_string(s: String) -> *mut c_char {
+    CString::new(s).map(CString::into_raw).unwrap_or(ptr::null_mut())
+}
+
+/// The addresses from `whatsmyip_find()`, opaque to C
+pub struct FindResult {
+    ips: Vec<MyIp>,
+}
+
+/// Find the external addresses of `family` with the default options,
+/// NULL on error, see `whatsmyip_last_error()`. Free the result with
+/// `whatsmyip_result_free()`.
+#[no_mangle]
+pub extern "C" fn whatsmyip_find(family: c_int) -> *mut FindResult {
+    let family = match family {
+        WHATSMYIP_BOTH => IpFamily::Both,
+        WHATSMYIP_V4 => IpFamily::V4,
+        WHATSMYIP_V6 => IpFamily::V6,
+        _ => {
+            set_error(format!("Invalid address family {}", family));
+            return ptr::null_mut();
+        },
+    };
+    // A panic must not unwind into C
+    match panic::catch_unwind(|| WhatsMyIp::new().family(family).find()) {
+        Ok(Ok(ips)) => Box::into_raw(Box::new(FindResult { ips: ips })),
+        Ok(Err(err)) => {
+            set_error(err.to_string());
+            ptr::null_mut()
+        },
+        Err(_) => {
+            set_error("Internal error in whatsmyip_find()".to_owned());
+            ptr::null_mut()
+        },
+    }
+}
+
+/// The number of addresses in `res`, 0 for NULL
+///
+/// # Safety
+///
+/// `res` is NULL or a result from `whatsmyip_find()` that was not freed
+#[no_mangle]
+pub unsafe extern "C" fn whatsmyip_result_len(res: *const FindResult) -> usize {
+    match res.as_ref() {
+        Some(res) => res.ips.len(),
+        None => 0,
+    }
+}
+
+/// The address at `index` as a string, e.g. "192.0.2.1", NULL if
+/// `index` is out of range. Free it with `whatsmyip_string_free()`.
+///
+/// # Safety
+///
+/// `res` is NULL or a result from `whatsmyip_find()` that was not freed
+#[no_mangle]
+pub unsafe extern "C" fn whatsmyip_result_get(res: *const FindResult, index: usize) -> *mut c_char {
+    match res.as_ref().and_then(|res| res.ips.get(index)) {
+        Some(ip) => to_c_string(ip.to_string()),
+        None => ptr::null_mut(),
+    }
+}
+
+/// Free a result from `whatsmyip_find()`, NULL is ignored
+///
+/// # Safety
+///
+/// `res` is NULL or a result from `whatsmyip_find()` that was not freed
+#[no_mangle]
+pub unsafe extern "C" fn whatsmyip_result_free(res: *mut FindResult) {
+    if !res.is_null() {
+        drop(Box::from_raw(res));
+    }
+}
+
+/// The message of the last error in this thread, NULL if there was
+/// none. Free it with `whatsmyip_string_free()`.
+#[no_mangle]
+pub extern "C" fn whatsmyip_last_error() -> *mut c_char {
+    LAST_ERROR.with(|err| match *err.borrow() {
+        Some(ref msg) => to_c_string(msg.clone()),
+        None => ptr::null_mut(),
+    })
+}
+
+/// Free a string from this library, NULL is ignored
+///
+/// # Safety
+///
+/// `s` is NULL or a string from this library that was not freed
+#[no_mangle]
+pub unsafe extern "C" fn whatsmyip_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+#[test]
+fn test_ffi_result() {
+    use std::ffi::CStr;
+    use std::net::Ipv4Addr;
+    let res = Box::into_raw(Box::new(FindResult {
+        ips: vec![MyIp::V4(Ipv4Addr::new(192, 0, 2, 1))],
+    }));
+    unsafe {
+        assert_eq!(whatsmyip_result_len(res), 1);
+        let ip = whatsmyip_result_get(res, 0);
+        assert_eq!(CStr::from_ptr(ip).to_str().unwrap(), "192.0.2.1");
+        whatsmyip_string_free(ip);
+        assert!(whatsmyip_result_get(res, 1).is_null());
+        whatsmyip_result_free(res);
+        assert_eq!(whatsmyip_result_len(ptr::null()), 0);
+    }
+
+    assert!(whatsmyip_find(5).is_null());
+    let err = whatsmyip_last_error();
+    assert_eq!(unsafe { CStr::from_ptr(err) }.to_str().unwrap(), "Invalid address family 5");
+    unsafe { whatsmyip_string_free(err) };
+}
+
+/// The declarations in the header match the exported functions
+#[test]
+fn test_ffi_header() {
+    let header = include_str!("../include/whatsmyip.h");
+    let _: extern "C" fn(c_int) -> *mut FindResult = whatsmyip_find;
+    let _: unsafe extern "C" fn(*const FindResult) -> usize = whatsmyip_result_len;
+    let _: unsafe extern "C" fn(*const FindResult, usize) -> *mut c_char = whatsmyip_result_get;
+    let _: unsafe extern "C" fn(*mut FindResult) = whatsmyip_result_free;
+    let _: extern "C" fn() -> *mut c_char = whatsmyip_last_error;
+    let _: unsafe extern "C" fn(*mut c_char) = whatsmyip_string_free;
+    let decls = [
+        "whatsmyip_result *whatsmyip_find(int family);",
+        "size_t whatsmyip_result_len(const whatsmyip_result *res);",
+        "char *whatsmyip_result_get(const whatsmyip_result *res, size_t index);",
+        "void whatsmyip_result_free(whatsmyip_result *res);",
+        "char *whatsmyip_last_error(void);",
+        "void whatsmyip_string_free(char *s);",
+    ];
+    let prototypes: Vec<&str> = header.lines()
+                                    .filter(|line| line.ends_with(");"))
+                                    .collect();
+    assert_eq!(prototypes, decls);
+    for &(name, value) in &[("WHATSMYIP_BOTH", WHATSMYIP_BOTH), ("WHATSMYIP_V4", WHATSMYIP_V4),
+                            ("WHATSMYIP_V6", WHATSMYIP_V6)] {
+        assert!(header.contains(&format!("#define {} {}\n", name, value)));
+    }
+}
+
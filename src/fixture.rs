@@ -0,0 +1,112 @@
+//!
+//! A local HTTP server for tests, so they run without internet
+//! access, see `Fixture`
+//!
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// Serves canned responses on a local port, in its own thread,
+/// until the test process exits. Connections are kept alive.
+///
+/// - `/text`: 192.0.2.1 as plain text
+/// - `/json`: `{"ip":"192.0.2.2"}`
+/// - `/header`: 192.0.2.3 in the `X-Client-Ip` header
+/// - `/malformed`: text that is not an address
+/// - `/bad-json`: truncated JSON
+/// - `/portal`: a login page, like a captive portal
+/// - `/slow`: `/text` after 2 seconds
+/// - `/redirect`: redirects to `/text`
+/// - `/loop`: redirects to itself
+/// - `/down`: 503
+/// - anything else: 404
+pub struct Fixture {
+    port: u16,
+    connections: Arc<AtomicUsize>,
+}
+
+impl Fixture {
+    pub fn start() -> Fixture {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let accepted = connections.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                thread::spawn(move || serve(stream));
+            }
+        });
+        Fixture {
+            port: port,
+            connections: connections,
+        }
+    }
+
+    /// The number of connections accepted so far
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    /// The URL of `path` on this server
+    pub fn url(&self, path: &str) -> String {
+        format!("http://127.0.0.1:{}{}", self.port, path)
+    }
+}
+
+/// Status, extra headers and body for `path`
+fn respond(path: &str) -> (&'static str, Vec<&'static str>, &'static str) {
+    match path {
+        "/text" => ("200 OK", vec![], "192.0.2.1\n"),
+        "/json" => ("200 OK", vec!["Content-Type: application/json"], r#"{"ip":"192.0.2.2"}"#),
+        "/header" => ("200 OK", vec!["X-Client-Ip: 192.0.2.3, 10.0.0.1"], ""),
+        "/malformed" => ("200 OK", vec![], "not an address\n"),
+        "/bad-json" => ("200 OK", vec!["Content-Type: application/json"], r#"{"ip":"#),
+        "/portal" => ("200 OK", vec!["Content-Type: text/html"], "<html><body>Login</body></html>"),
+        "/slow" => {
+            thread::sleep(Duration::from_secs(2));
+            respond("/text")
+        },
+        "/redirect" => ("302 Found", vec!["Location: /text"], ""),
+        "/loop" => ("302 Found", vec!["Location: /loop"], ""),
+        "/down" => ("503 Service Unavailable", vec![], ""),
+        _ => ("404 Not Found", vec![], ""),
+    }
+}
+
+/// Answer requests until the client closes the connection
+fn serve(stream: TcpStream) {
+    let mut reader = BufReader::new(stream);
+    loop {
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(n) if n > 0 => (),
+            _ => return,
+        }
+        let path = line.split_whitespace().nth(1).unwrap_or("/").to_owned();
+        // Skip the request headers
+        loop {
+            let mut header = String::new();
+            match reader.read_line(&mut header) {
+                Ok(n) if n > 0 && header.trim() != "" => continue,
+                _ => break,
+            }
+        }
+
+        let (status, headers, body) = respond(&path);
+        let mut res = format!("HTTP/1.1 {}\r\nContent-Length: {}\r\n", status, body.len());
+        for header in headers {
+            res.push_str(header);
+            res.push_str("\r\n");
+        }
+        res.push_str("\r\n");
+        res.push_str(body);
+        if reader.get_mut().write_all(res.as_bytes()).is_err() {
+            return;
+        }
+    }
+}
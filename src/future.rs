@@ -0,0 +1,87 @@
+//!
+//! A future for the search, see `WhatsMyIp::find_async()`
+//!
+//! The sources use blocking sockets, so the search runs in a thread
+//! of its own and the future is woken when it is done. Polling never
+//! blocks the executor.
+//!
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context as TaskContext, Poll, Waker};
+use std::thread;
+use {MyIp, Error, WhatsMyIp};
+
+struct Shared {
+    result: Option<Result<Vec<MyIp>, Error>>,
+    waker: Option<Waker>,
+}
+
+/// Resolves to the result of `WhatsMyIp::find()`
+pub struct FindFuture {
+    shared: Arc<Mutex<Shared>>,
+}
+
+/// Start the search in a new thread
+pub fn spawn(opts: WhatsMyIp) -> FindFuture {
+    let shared = Arc::new(Mutex::new(Shared {
+        result: None,
+        waker: None,
+    }));
+    let worker = shared.clone();
+    thread::spawn(move || {
+        let res = opts.find();
+        let mut shared = worker.lock().unwrap();
+        shared.result = Some(res);
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    });
+    FindFuture {
+        shared: shared,
+    }
+}
+
+impl Future for FindFuture {
+    type Output = Result<Vec<MyIp>, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut TaskContext) -> Poll<Self::Output> {
+        let mut shared = self.shared.lock().unwrap();
+        match shared.result.take() {
+            Some(res) => Poll::Ready(res),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            },
+        }
+    }
+}
+
+#[test]
+fn test_find_async() {
+    use std::task::Wake;
+
+    struct Unpark(thread::Thread);
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    // Nothing enabled, the search fails right away
+    let mut opts = WhatsMyIp::new();
+    opts.igd(false).stun(false).dns(false).clear_providers();
+    let mut fut = opts.find_async();
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut cx = TaskContext::from_waker(&waker);
+    loop {
+        match Pin::new(&mut fut).poll(&mut cx) {
+            Poll::Ready(res) => {
+                assert!(res.is_err());
+                break;
+            },
+            Poll::Pending => thread::park(),
+        }
+    }
+}
@@ -0,0 +1,774 @@
+//!
+//! Internet Gateway Device support
+//!
+//! The external IPv4 address comes from WANIPConnection (via the igd crate).
+//! IGDv2 gateways also expose WANIPv6FirewallControl, which has no action
+//! for an address: we ask it about the pinholes for our own global IPv6
+//! addresses and the prefix of the one it accepts is the prefix the gateway
+//! routes to us, see `igd_ipv6_prefix()`. AVM gateways report their own
+//! external IPv6 address with an extension, see `igd_ipv6()`.
+//!
+
+use std::io::{Read, Write};
+use std::net::{UdpSocket, TcpListener, TcpStream, SocketAddr, SocketAddrV4, Ipv4Addr, Ipv6Addr,
+               ToSocketAddrs};
+use std::str;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+use igd;
+use std::fmt;
+use {MyIp, Error};
+use local::{self, Ipv6Preference};
+use tcp;
+
+const IP_CONNECTION_SERVICE: &'static str = "urn:schemas-upnp-org:service:WANIPConnection:1";
+/// The connection services of IGDv2 and IGDv1 gateways, PPPoE
+/// gateways have WANPPPConnection instead
+const IP_CONNECTION_SERVICES: &'static [&'static str] = &[
+    "urn:schemas-upnp-org:service:WANIPConnection:2",
+    IP_CONNECTION_SERVICE,
+    "urn:schemas-upnp-org:service:WANPPPConnection:1",
+];
+const IPV6_FIREWALL_SERVICE: &'static str = "urn:schemas-upnp-org:service:WANIPv6FirewallControl:1";
+
+/// IGDv2 has no action for the IPv6 address of the gateway, AVM
+/// routers add this one to WANIPConnection
+const IPV6_ADDRESS_ACTION: &'static str = "X_AVM_DE_GetExternalIPv6Address";
+
+/// Search from `local`, unspecified for any interface
+pub fn igd_ip(local: Ipv4Addr, timeout: Duration) -> Option<MyIp> {
+    match igd::search_gateway_from_timeout(local, timeout) {
+        Ok(gw) => match gw.get_external_ip() {
+            Ok(ip) => {
+                debug!("IGD => {}", ip);
+                return Some(MyIp::V4(ip))
+            },
+            Err(_) => info!("Unable to find IGD gateway"),
+        },
+        Err(err) => info!("Unable to find gateway: {}", err),
+    }
+    None
+}
+
+fn ssdp_location(response: &[u8]) -> Option<String> {
+    let text = match str::from_utf8(response) {
+        Ok(text) => text,
+        Err(_) => return None,
+    };
+    for line in text.lines() {
+        let mut parts = line.splitn(2, ':');
+        let name = parts.next().unwrap_or("");
+        if name.trim().to_lowercase() == "location" {
+            if let Some(location) = parts.next() {
+                return Some(location.trim().to_owned());
+            }
+        }
+    }
+    None
+}
+
+/// Send an SSDP search for the given service type, returns the
+/// description URLs of the devices that answer. If `all` is false
+/// only the first answer is returned, otherwise we wait for
+/// `timeout` and collect all answers.
+fn ssdp_search(local: Ipv4Addr, service: &str, timeout: Duration,
+               all: bool) -> Result<Vec<String>, Error> {
+    let socket = try!(UdpSocket::bind((local, 0))
+                        .map_err(Error::from));
+    try!(socket.set_read_timeout(Some(timeout))
+            .map_err(Error::from));
+
+    let req = format!("M-SEARCH * HTTP/1.1\r\n\
+                       Host:239.255.255.250:1900\r\n\
+                       ST:{}\r\n\
+                       Man:\"ssdp:discover\"\r\n\
+                       MX:3\r\n\r\n", service);
+    try!(socket.send_to(req.as_bytes(), "239.255.255.250:1900")
+            .map_err(Error::from));
+
+    let start = Instant::now();
+    let mut locations = Vec::new();
+    let mut buf = [0u8; 1024];
+    loop {
+        let len = match socket.recv_from(&mut buf) {
+            Ok((len, _)) => len,
+            // Timeout, the search window is over
+            Err(_) if !locations.is_empty() => break,
+            Err(err) => return Err(Error::from(err)),
+        };
+        match ssdp_location(&buf[..len]) {
+            Some(location) => if !locations.contains(&location) {
+                locations.push(location);
+            },
+            None => info!("Invalid SSDP response"),
+        }
+        if !all && !locations.is_empty() {
+            break;
+        }
+
+        let elapsed = start.elapsed();
+        if elapsed >= timeout {
+            break;
+        }
+        try!(socket.set_read_timeout(Some(timeout - elapsed))
+                .map_err(Error::from));
+    }
+
+    if locations.is_empty() {
+        Err(Error::Parse("Invalid SSDP response".to_owned()))
+    } else {
+        Ok(locations)
+    }
+}
+
+fn get_description(location: &str, timeout: Duration) -> Result<String, Error> {
+    gateway_request("GET", location, &[], "", timeout)
+}
+
+/// The scheme://host:port part of the location URL
+fn location_base(location: &str) -> &str {
+    let host_start = location.find("://").map(|pos| pos + 3).unwrap_or(0);
+    let host_end = location[host_start..].find('/')
+                    .map(|pos| host_start + pos)
+                    .unwrap_or(location.len());
+    &location[..host_end]
+}
+
+/// The host:port part of `base` from `location_base()`
+fn authority(base: &str) -> &str {
+    &base[base.find("://").map(|pos| pos + 3).unwrap_or(0)..]
+}
+
+/// The host and port in `base` from `location_base()`, port 80
+/// if not given
+fn host_port(base: &str) -> (&str, u16) {
+    let host = authority(base);
+    let brackets: &[_] = &['[', ']'];
+    match host.rfind(':') {
+        // Not a colon inside an IPv6 literal
+        Some(pos) if !host[pos..].contains(']') => match host[pos + 1..].parse() {
+            Ok(port) => (host[..pos].trim_matches(brackets), port),
+            Err(_) => (host, 80),
+        },
+        _ => (host.trim_matches(brackets), 80),
+    }
+}
+
+/// The HTTP/1.0 request for `url`, the Host header is the
+/// host:port from the URL
+fn format_request(method: &str, url: &str, headers: &[(&str, String)], body: &str) -> String {
+    let base = location_base(url);
+    let path = if url.len() > base.len() { &url[base.len()..] } else { "/" };
+    let mut req = format!("{} {} HTTP/1.0\r\nHost: {}\r\nContent-Length: {}\r\n",
+                          method, path, authority(base), body.len());
+    for &(name, ref value) in headers {
+        req.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    req.push_str("\r\n");
+    req.push_str(body);
+    req
+}
+
+/// A plain HTTP/1.0 request, returns the status and body. The
+/// gateway is on the LAN, there are no proxies, TLS or redirects to
+/// deal with.
+fn gateway_response(method: &str, url: &str, headers: &[(&str, String)], body: &str,
+                    timeout: Duration) -> Result<(u16, String), Error> {
+    let (host, port) = host_port(location_base(url));
+    let mut stream = try!(tcp::connect(host, port, timeout)
+                            .map_err(Error::from));
+    try!(stream.set_read_timeout(Some(timeout))
+            .and_then(|_| stream.set_write_timeout(Some(timeout)))
+            .map_err(Error::from));
+
+    let req = format_request(method, url, headers, body);
+    try!(stream.write_all(req.as_bytes())
+            .map_err(Error::from));
+
+    // HTTP/1.0, the gateway closes the connection after the body
+    let mut res = Vec::new();
+    try!(stream.read_to_end(&mut res)
+            .map_err(Error::from));
+    let res = String::from_utf8_lossy(&res);
+    let (head, body) = match res.find("\r\n\r\n") {
+        Some(pos) => (&res[..pos], &res[pos + 4..]),
+        None => return Err(Error::Parse(format!("Invalid HTTP response from {}", url))),
+    };
+    match head.split_whitespace().nth(1).and_then(|status| status.parse().ok()) {
+        Some(status) => Ok((status, body.to_owned())),
+        None => Err(Error::Parse(format!("Invalid HTTP response from {}", url))),
+    }
+}
+
+/// Same as `gateway_response()`, an error status is `Error::Http`
+fn gateway_request(method: &str, url: &str, headers: &[(&str, String)], body: &str,
+                   timeout: Duration) -> Result<String, Error> {
+    match try!(gateway_response(method, url, headers, body, timeout)) {
+        (status, body) if (200..300).contains(&status) => Ok(body),
+        (status, _) => Err(Error::Http { status: status }),
+    }
+}
+
+/// Find the control path for `service` in a device description
+fn control_path(description: &str, service: &str) -> Option<String> {
+    let start = match description.find(service) {
+        Some(pos) => pos,
+        None => return None,
+    };
+    let rest = &description[start..];
+    let end = rest.find("</service>").unwrap_or(rest.len());
+    let rest = &rest[..end];
+    let url_start = match rest.find("<controlURL>") {
+        Some(pos) => pos + "<controlURL>".len(),
+        None => return None,
+    };
+    let url_end = match rest[url_start..].find("</controlURL>") {
+        Some(pos) => url_start + pos,
+        None => return None,
+    };
+    let path = rest[url_start..url_end].trim();
+
+    // Some devices use absolute URLs
+    let path = if path.starts_with("http://") {
+        &path[location_base(path).len()..]
+    } else {
+        path
+    };
+    if path.starts_with('/') {
+        Some(path.to_owned())
+    } else {
+        Some(format!("/{}", path))
+    }
+}
+
+/// The error in a SOAP fault from the gateway
+#[derive(Debug, PartialEq)]
+struct UpnpError {
+    code: u32,
+    description: String,
+}
+
+impl UpnpError {
+    fn into_error(self, action: &str) -> Error {
+        Error::Protocol(format!("{} failed with UPnP error {} {}", action, self.code, self.description))
+    }
+}
+
+/// No such entry, e.g. past the last port mapping
+const SPECIFIED_ARRAY_INDEX_INVALID: u32 = 713;
+
+/// The UPnP error in a SOAP fault, None if `xml` is not one
+fn parse_upnp_error(xml: &str) -> Option<UpnpError> {
+    tag_value(xml, "errorCode")
+        .and_then(|code| code.parse().ok())
+        .map(|code| UpnpError {
+            code: code,
+            description: tag_value(xml, "errorDescription").unwrap_or("").to_owned(),
+        })
+}
+
+/// Call `action`, returns the response body or the UPnP error if
+/// the gateway answered with a SOAP fault
+fn soap_call(url: &str, service: &str, action: &str, args: &[(&str, String)],
+             timeout: Duration) -> Result<Result<String, UpnpError>, Error> {
+    let args: String = args.iter()
+                        .map(|&(name, ref val)| format!("<{name}>{val}</{name}>", name=name, val=val))
+                        .collect();
+    let body = format!("<?xml version=\"1.0\"?>\
+        <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+        s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+        <s:Body><u:{action} xmlns:u=\"{service}\">{args}</u:{action}></s:Body>\
+        </s:Envelope>", action=action, service=service, args=args);
+
+    let headers = [
+        ("SOAPAction", format!("\"{}#{}\"", service, action)),
+        ("Content-Type", "text/xml; charset=\"utf-8\"".to_owned()),
+    ];
+    match try!(gateway_response("POST", url, &headers, &body, timeout)) {
+        (status, body) if (200..300).contains(&status) => Ok(Ok(body)),
+        // SOAP faults come with 500 Internal Server Error
+        (500, body) => match parse_upnp_error(&body) {
+            Some(err) => Ok(Err(err)),
+            None => Err(Error::Http { status: 500 }),
+        },
+        (status, _) => Err(Error::Http { status: status }),
+    }
+}
+
+/// Same as `soap_call()`, a SOAP fault is `Error::Protocol`
+fn soap_request(url: &str, service: &str, action: &str, args: &[(&str, String)],
+                timeout: Duration) -> Result<String, Error> {
+    try!(soap_call(url, service, action, args, timeout)).map_err(|err| err.into_error(action))
+}
+
+/// The first connection service in `description` and its control path
+fn connection_service(description: &str, location: &str) -> Result<(&'static str, String), Error> {
+    IP_CONNECTION_SERVICES.iter()
+        .filter_map(|&service| control_path(description, service).map(|path| (service, path)))
+        .next()
+        .ok_or(Error::Parse(format!("No WAN connection service in {}", location)))
+}
+
+/// The address in a X_AVM_DE_GetExternalIPv6Address response, it
+/// must be a global one
+fn parse_ipv6_address(xml: &str) -> Result<Ipv6Addr, Error> {
+    let value = match tag_value(xml, "NewExternalIPv6Address") {
+        Some(value) if !value.is_empty() => value,
+        _ => return Err(Error::Parse("The gateway reports no IPv6 address".to_owned())),
+    };
+    let ip = try!(Ipv6Addr::from_str(value)
+                    .map_err(|_| Error::Parse(format!("Invalid IPv6 address {}", value))));
+    let first = ip.segments()[0];
+    // Unspecified, loopback, link local or unique local
+    if ip.is_unspecified() || ip.is_loopback()
+            || first & 0xffc0 == 0xfe80 || first & 0xfe00 == 0xfc00 {
+        return Err(Error::Parse(format!("{} is not a global IPv6 address", ip)));
+    }
+    Ok(ip)
+}
+
+/// An IGDv2 gateway with WANIPv6FirewallControl, its location and
+/// device description
+struct FirewallControl {
+    location: String,
+    description: String,
+    /// Control URL of the firewall service
+    url: String,
+}
+
+fn firewall_control(local: Ipv4Addr, timeout: Duration) -> Result<FirewallControl, Error> {
+    let location = try!(ssdp_search(local, IPV6_FIREWALL_SERVICE, timeout, false)).remove(0);
+    let description = try!(get_description(&location, timeout));
+    let path = try!(control_path(&description, IPV6_FIREWALL_SERVICE)
+                    .ok_or(Error::Parse(format!("No {} in {}", IPV6_FIREWALL_SERVICE, location))));
+    let url = format!("{}{}", location_base(&location), path);
+    let status = try!(soap_request(&url, IPV6_FIREWALL_SERVICE, "GetFirewallStatus", &[], timeout));
+    debug!("IGD IPv6 firewall enabled: {:?}, pinholes allowed: {:?}",
+           tag_value(&status, "FirewallEnabled"), tag_value(&status, "InboundPinholeAllowed"));
+    Ok(FirewallControl {
+        location: location,
+        description: description,
+        url: url,
+    })
+}
+
+fn igd_ipv6_inner(local: Ipv4Addr, timeout: Duration) -> Result<MyIp, Error> {
+    let fw = try!(firewall_control(local, timeout));
+    // The firewall service has no action for the address, AVM
+    // gateways add one to the connection service
+    let (service, path) = try!(connection_service(&fw.description, &fw.location));
+    let res = try!(soap_request(&format!("{}{}", location_base(&fw.location), path), service,
+                                IPV6_ADDRESS_ACTION, &[], timeout));
+    let ip = try!(parse_ipv6_address(&res));
+    debug!("IGD (IPv6) => {}", ip);
+    Ok(MyIp::V6(ip))
+}
+
+/// The external IPv6 address of an IGDv2 gateway, None unless the
+/// gateway has the AVM extension X_AVM_DE_GetExternalIPv6Address,
+/// for other gateways see `igd_ipv6_prefix()`
+pub fn igd_ipv6(local: Ipv4Addr, timeout: Duration) -> Option<MyIp> {
+    match igd_ipv6_inner(local, timeout) {
+        Ok(ip) => Some(ip),
+        Err(err) => {
+            info!("Unable to find IPv6 IGD gateway: {}", err);
+            None
+        },
+    }
+}
+
+/// An IPv6 prefix, see `igd_ipv6_prefix()`
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Ipv6Prefix {
+    /// The address with the host bits cleared
+    pub prefix: Ipv6Addr,
+    pub len: u8,
+}
+
+impl Ipv6Prefix {
+    /// The /64 `ip` belongs to, the prefix of a LAN with SLAAC
+    fn from_ip_64(ip: Ipv6Addr) -> Ipv6Prefix {
+        let s = ip.segments();
+        Ipv6Prefix {
+            prefix: Ipv6Addr::new(s[0], s[1], s[2], s[3], 0, 0, 0, 0),
+            len: 64,
+        }
+    }
+}
+
+impl fmt::Display for Ipv6Prefix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.prefix, self.len)
+    }
+}
+
+/// The IPv6 prefix an IGDv2 gateway routes to this host, from the
+/// WANIPv6FirewallControl service.
+///
+/// The service has no action for the prefix itself, so we ask it for
+/// the outbound pinhole timeout of each of our global IPv6 addresses.
+/// The gateway only answers for addresses on its LAN, the /64 of the
+/// first one it accepts is the prefix.
+pub fn igd_ipv6_prefix(timeout: Duration) -> Result<Ipv6Prefix, Error> {
+    ipv6_prefix_from(Ipv4Addr::new(0, 0, 0, 0), timeout)
+}
+
+/// Same as `igd_ipv6_prefix()`, searching from `local`
+pub fn ipv6_prefix_from(local: Ipv4Addr, timeout: Duration) -> Result<Ipv6Prefix, Error> {
+    let fw = try!(firewall_control(local, timeout));
+    let ips = try!(local::public_ipv6(Ipv6Preference::Stable));
+    for ip in ips.into_iter().filter_map(|(ip, _)| ip.to_ipv6()) {
+        // Any remote host and port, TCP
+        let args = [
+            ("RemoteHost", String::new()),
+            ("RemotePort", "0".to_owned()),
+            ("InternalClient", ip.to_string()),
+            ("InternalPort", "0".to_owned()),
+            ("Protocol", "6".to_owned()),
+        ];
+        match soap_request(&fw.url, IPV6_FIREWALL_SERVICE, "GetOutboundPinholeTimeout", &args,
+                           timeout) {
+            Ok(_) => {
+                let prefix = Ipv6Prefix::from_ip_64(ip);
+                debug!("IGD (IPv6 prefix) => {}", prefix);
+                return Ok(prefix);
+            },
+            Err(err) => info!("IGD {} does not route {}: {}", fw.location, ip, err),
+        }
+    }
+    Err(Error::Protocol(format!("IGD {} routes none of our IPv6 addresses", fw.location)))
+}
+
+/// External address reported by one of the gateways
+pub struct GatewayIp {
+    /// Address of the gateway that answered
+    pub gateway: SocketAddrV4,
+    /// URL of the gateway device description
+    pub location: String,
+    pub ip: MyIp,
+}
+
+/// A UPnP port mapping on the gateway, see `list_port_mappings()`
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PortMapping {
+    /// Only connections from this host are forwarded, None for any
+    pub remote_host: Option<String>,
+    pub external_port: u16,
+    /// TCP or UDP
+    pub protocol: String,
+    /// Local address the connections go to
+    pub internal_client: String,
+    pub internal_port: u16,
+    pub enabled: bool,
+    pub description: String,
+    /// Time left, zero for a permanent mapping
+    pub lease: Duration,
+}
+
+/// Most mappings we ask for, in case a gateway never says we
+/// are past the end
+const MAX_PORT_MAPPINGS: u32 = 1024;
+
+/// The text of the first `<tag>` in `xml`
+fn tag_value<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let open = format!("<{}>", tag);
+    let start = match xml.find(&open) {
+        Some(pos) => pos + open.len(),
+        None => return None,
+    };
+    xml[start..].find(&format!("</{}>", tag))
+        .map(|end| xml[start..start+end].trim())
+}
+
+/// Parse a GetGenericPortMappingEntry response
+fn parse_port_mapping(xml: &str) -> Result<PortMapping, Error> {
+    let value = |tag| tag_value(xml, tag).unwrap_or("");
+    let port = |tag| value(tag).parse::<u16>()
+                        .map_err(|_| Error::Parse(format!("Invalid {} in port mapping", tag)));
+    Ok(PortMapping {
+        remote_host: Some(value("NewRemoteHost")).filter(|host| !host.is_empty()).map(str::to_owned),
+        external_port: try!(port("NewExternalPort")),
+        protocol: value("NewProtocol").to_owned(),
+        internal_client: value("NewInternalClient").to_owned(),
+        internal_port: try!(port("NewInternalPort")),
+        enabled: value("NewEnabled") == "1",
+        description: value("NewPortMappingDescription").to_owned(),
+        lease: Duration::from_secs(value("NewLeaseDuration").parse().unwrap_or(0)),
+    })
+}
+
+impl GatewayIp {
+    /// The UPnP port mappings on this gateway
+    pub fn list_port_mappings(&self, timeout: Duration) -> Result<Vec<PortMapping>, Error> {
+        let description = try!(get_description(&self.location, timeout));
+        let (service, path) = try!(connection_service(&description, &self.location));
+        let url = format!("{}{}", location_base(&self.location), path);
+        let mut mappings = Vec::new();
+        for index in 0..MAX_PORT_MAPPINGS {
+            let res = try!(soap_call(&url, service, "GetGenericPortMappingEntry",
+                                     &[("NewPortMappingIndex", index.to_string())], timeout));
+            match res {
+                Ok(xml) => mappings.push(try!(parse_port_mapping(&xml))),
+                // Past the last entry
+                Err(ref err) if err.code == SPECIFIED_ARRAY_INDEX_INVALID => break,
+                Err(err) => return Err(err.into_error("GetGenericPortMappingEntry")),
+            }
+        }
+        debug!("IGD {} => {} port mappings", self.gateway, mappings.len());
+        Ok(mappings)
+    }
+}
+
+/// The port mappings on the first gateway that answers within `window`
+pub fn list_port_mappings(window: Duration) -> Result<Vec<PortMapping>, Error> {
+    let gw = try!(search_gateways(window)).remove(0);
+    gw.list_port_mappings(window)
+}
+
+/// The IPv4 address and port of the gateway at `location`, port 80
+/// if the URL has none
+fn gateway_addr(location: &str) -> Result<SocketAddrV4, Error> {
+    let (host, port) = host_port(location_base(location));
+    let addrs = try!((host, port).to_socket_addrs()
+                        .map_err(|err| Error::Parse(format!("{}: {}", location, err))));
+    for addr in addrs {
+        if let SocketAddr::V4(addr) = addr {
+            return Ok(addr);
+        }
+    }
+    Err(Error::Parse(format!("{}: no IPv4 address for {}", location, host)))
+}
+
+fn gateway_ip(location: &str, timeout: Duration) -> Result<GatewayIp, Error> {
+    let addr = try!(gateway_addr(location));
+    let description = try!(get_description(location, timeout));
+    let path = try!(control_path(&description, IP_CONNECTION_SERVICE)
+                    .ok_or(Error::Parse(format!("No {} in {}", IP_CONNECTION_SERVICE, location))));
+
+    let gw = igd::Gateway {
+        addr: addr,
+        control_url: path,
+    };
+    let ip = try!(gw.get_external_ip()
+                    .map_err(|err| Error::Protocol(format!("{}", err))));
+    debug!("IGD {} => {}", addr, ip);
+    Ok(GatewayIp {
+        gateway: addr,
+        location: location.to_owned(),
+        ip: MyIp::V4(ip),
+    })
+}
+
+/// Search for all gateways that answer within `window` and
+/// query each of them for its external address
+pub fn search_gateways(window: Duration) -> Result<Vec<GatewayIp>, Error> {
+    search_gateways_from(Ipv4Addr::new(0, 0, 0, 0), window)
+}
+
+/// Same as `search_gateways()`, searching from `local`
+pub fn search_gateways_from(local: Ipv4Addr, window: Duration) -> Result<Vec<GatewayIp>, Error> {
+    let locations = try!(ssdp_search(local, IP_CONNECTION_SERVICE, window, true));
+    let mut results = Vec::new();
+    for location in locations {
+        match gateway_ip(&location, window) {
+            Ok(res) => results.push(res),
+            Err(err) => info!("{} => {}", location, err),
+        }
+    }
+    if results.is_empty() {
+        Err(Error::Protocol("Unable to find any IGD gateway".to_owned()))
+    } else {
+        Ok(results)
+    }
+}
+
+/// Result of the IGD port mapping self test
+pub struct IgdSelfTest {
+    /// Address of the gateway that answered
+    pub gateway: SocketAddrV4,
+    /// External address reported by the gateway
+    pub ip: MyIp,
+    /// True if a connection to the mapped external port reached us
+    pub routable: bool,
+}
+
+/// Local address used to reach the gateway
+fn local_ipv4(gateway: &SocketAddrV4) -> Result<Ipv4Addr, Error> {
+    let socket = try!(UdpSocket::bind("0.0.0.0:0")
+                        .map_err(Error::from));
+    try!(socket.connect(gateway)
+            .map_err(Error::from));
+    match socket.local_addr() {
+        Ok(SocketAddr::V4(addr)) => Ok(*addr.ip()),
+        Ok(addr) => Err(Error::Parse(format!("Unexpected local address {}", addr))),
+        Err(err) => Err(Error::from(err)),
+    }
+}
+
+/// Wait for a connection on `listener`, up to `timeout`
+fn accept_timeout(listener: &TcpListener, timeout: Duration) -> bool {
+    if listener.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let start = Instant::now();
+    while start.elapsed() < timeout {
+        if listener.accept().is_ok() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    false
+}
+
+/// Map a temporary port in the gateway and connect to the external
+/// address from inside. If the connection does not come back to us
+/// the IGD address is stale or there is another NAT upstream.
+///
+/// This needs the gateway to support hairpinning, a gateway without
+/// it will report its address as not routable.
+pub fn igd_self_test(timeout: Duration) -> Result<IgdSelfTest, Error> {
+    self_test_from(Ipv4Addr::new(0, 0, 0, 0), timeout)
+}
+
+/// Same as `igd_self_test()`, searching from `local`
+pub fn self_test_from(local: Ipv4Addr, timeout: Duration) -> Result<IgdSelfTest, Error> {
+    let gw = try!(igd::search_gateway_from_timeout(local, timeout)
+                    .map_err(|err| Error::Protocol(format!("Unable to find gateway: {}", err))));
+    let ext_ip = try!(gw.get_external_ip()
+                        .map_err(|err| Error::Protocol(format!("{}", err))));
+    let local_ip = try!(local_ipv4(&gw.addr));
+    let listener = try!(TcpListener::bind((local_ip, 0))
+                        .map_err(Error::from));
+    let local_port = try!(listener.local_addr()
+                            .map_err(Error::from)).port();
+
+    let ext_port = try!(gw.add_any_port(igd::PortMappingProtocol::TCP,
+                                        SocketAddrV4::new(local_ip, local_port),
+                                        60, "whatsmyip self test")
+                        .map_err(|err| Error::Protocol(format!("{}", err))));
+    debug!("IGD {} mapped {}:{} => {}:{}", gw.addr, ext_ip, ext_port, local_ip, local_port);
+
+    let ext_addr = SocketAddr::V4(SocketAddrV4::new(ext_ip, ext_port));
+    let routable = match TcpStream::connect_timeout(&ext_addr, timeout) {
+        Ok(_) => accept_timeout(&listener, timeout),
+        Err(err) => {
+            info!("{} => {}", ext_addr, err);
+            false
+        },
+    };
+
+    if let Err(err) = gw.remove_port(igd::PortMappingProtocol::TCP, ext_port) {
+        info!("Unable to remove IGD port mapping {}: {}", ext_port, err);
+    }
+    Ok(IgdSelfTest {
+        gateway: gw.addr,
+        ip: MyIp::V4(ext_ip),
+        routable: routable,
+    })
+}
+
+#[test]
+fn test_control_path() {
+    let desc = "<service>\
+        <serviceType>urn:schemas-upnp-org:service:WANIPv6FirewallControl:1</serviceType>\
+        <controlURL>/ctl/IP6FCtl</controlURL></service>";
+    assert_eq!(control_path(desc, IPV6_FIREWALL_SERVICE),
+               Some("/ctl/IP6FCtl".to_owned()));
+    assert_eq!(location_base("http://192.168.1.1:5000/rootDesc.xml"),
+               "http://192.168.1.1:5000");
+    assert_eq!(host_port("http://192.168.1.1:5000"), ("192.168.1.1", 5000));
+    assert_eq!(host_port("http://192.168.1.1"), ("192.168.1.1", 80));
+    assert_eq!(host_port("http://[fe80::1]:5000"), ("fe80::1", 5000));
+    assert_eq!(host_port("http://[fe80::1]"), ("fe80::1", 80));
+}
+
+#[test]
+fn test_format_request() {
+    assert_eq!(format_request("GET", "http://192.168.1.1:5000/rootDesc.xml", &[], ""),
+               "GET /rootDesc.xml HTTP/1.0\r\nHost: 192.168.1.1:5000\r\nContent-Length: 0\r\n\r\n");
+    let headers = [("SOAPAction", "\"urn:x#Get\"".to_owned())];
+    assert_eq!(format_request("POST", "http://[fe80::1]:49152/ctl", &headers, "<x/>"),
+               "POST /ctl HTTP/1.0\r\nHost: [fe80::1]:49152\r\nContent-Length: 4\r\n\
+                SOAPAction: \"urn:x#Get\"\r\n\r\n<x/>");
+    assert!(format_request("GET", "http://192.168.1.1", &[], "")
+                .starts_with("GET / HTTP/1.0\r\nHost: 192.168.1.1\r\n"));
+}
+
+#[test]
+fn test_gateway_addr() {
+    assert_eq!(gateway_addr("http://192.168.1.1:5000/rootDesc.xml").unwrap(),
+               SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 1), 5000));
+    assert_eq!(gateway_addr("http://192.168.1.1/rootDesc.xml").unwrap(),
+               SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 1), 80));
+    assert_eq!(gateway_addr("http://localhost:49000/igddesc.xml").unwrap(),
+               SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 49000));
+    assert!(gateway_addr("http://[fe80::1]:49152/desc.xml").is_err());
+}
+
+#[test]
+fn test_parse_ipv6_address() {
+    let res = |ip: &str| format!("<s:Envelope><s:Body>\
+        <u:X_AVM_DE_GetExternalIPv6AddressResponse>\
+        <NewExternalIPv6Address>{}</NewExternalIPv6Address><NewPrefixLength>64</NewPrefixLength>\
+        </u:X_AVM_DE_GetExternalIPv6AddressResponse></s:Body></s:Envelope>", ip);
+    assert_eq!(parse_ipv6_address(&res("2001:db8:1::1")).unwrap(),
+               Ipv6Addr::from_str("2001:db8:1::1").unwrap());
+    assert!(parse_ipv6_address(&res("")).is_err());
+    assert!(parse_ipv6_address(&res("::")).is_err());
+    assert!(parse_ipv6_address(&res("fe80::1")).is_err());
+    assert!(parse_ipv6_address(&res("fd00::1")).is_err());
+    assert!(parse_ipv6_address("<s:Envelope></s:Envelope>").is_err());
+}
+
+#[test]
+fn test_parse_port_mapping() {
+    let xml = "<s:Body><u:GetGenericPortMappingEntryResponse>\
+        <NewRemoteHost></NewRemoteHost><NewExternalPort>8080</NewExternalPort>\
+        <NewProtocol>TCP</NewProtocol><NewInternalPort>80</NewInternalPort>\
+        <NewInternalClient>192.168.1.10</NewInternalClient><NewEnabled>1</NewEnabled>\
+        <NewPortMappingDescription>web</NewPortMappingDescription>\
+        <NewLeaseDuration>3600</NewLeaseDuration>\
+        </u:GetGenericPortMappingEntryResponse></s:Body>";
+    let mapping = parse_port_mapping(xml).unwrap();
+    assert_eq!(mapping.remote_host, None);
+    assert_eq!((mapping.external_port, mapping.internal_port), (8080, 80));
+    assert_eq!(mapping.internal_client, "192.168.1.10");
+    assert!(mapping.enabled);
+    assert_eq!(mapping.lease, Duration::from_secs(3600));
+}
+
+#[test]
+fn test_parse_upnp_error() {
+    let xml = "<s:Envelope><s:Body><s:Fault><faultcode>s:Client</faultcode>\
+        <faultstring>UPnPError</faultstring><detail>\
+        <UPnPError xmlns=\"urn:schemas-upnp-org:control-1-0\">\
+        <errorCode>713</errorCode><errorDescription>SpecifiedArrayIndexInvalid</errorDescription>\
+        </UPnPError></detail></s:Fault></s:Body></s:Envelope>";
+    assert_eq!(parse_upnp_error(xml), Some(UpnpError {
+        code: SPECIFIED_ARRAY_INDEX_INVALID,
+        description: "SpecifiedArrayIndexInvalid".to_owned(),
+    }));
+    assert_eq!(parse_upnp_error("<html>Internal Server Error</html>"), None);
+}
+
+#[ignore]
+#[test]
+fn test_igd() {
+    assert!(igd_ip(Ipv4Addr::new(0, 0, 0, 0), Duration::from_secs(3)).is_some())
+}
+
+#[test]
+fn test_ipv6_prefix() {
+    let prefix = Ipv6Prefix::from_ip_64(Ipv6Addr::from_str("2001:db8:1:2:a:b:c:d").unwrap());
+    assert_eq!(prefix.prefix, Ipv6Addr::from_str("2001:db8:1:2::").unwrap());
+    assert_eq!(prefix.to_string(), "2001:db8:1:2::/64");
+}
+
+#[ignore]
+#[test]
+fn test_igd_ipv6() {
+    assert!(igd_ipv6(Ipv4Addr::new(0, 0, 0, 0), Duration::from_secs(3)).is_some())
+}
@@ -0,0 +1,408 @@
+//!
+//! Where the address appears to be, from public geolocation services
+//! or an offline MaxMind database (with the `maxmind` feature)
+//!
+//! ```no_run
+//! use whatsmyip::{whatsmyip, GeoLookup};
+//! let ip = whatsmyip().unwrap();
+//! let geo = GeoLookup::new().lookup(&ip).unwrap();
+//! println!("{:?} {:?}", geo.country, geo.city);
+//! ```
+//!
+
+#[cfg(feature = "http")]
+use std::io::Read;
+#[cfg(feature = "http")]
+use std::time::Duration;
+#[cfg(feature = "maxmind")]
+use std::path::Path;
+#[cfg(feature = "maxmind")]
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+#[cfg(feature = "http")]
+use rustc_serialize::json::Json;
+use {MyIp, Error, ProviderError};
+#[cfg(feature = "http")]
+use client::{self, ClientConfig};
+
+#[cfg(feature = "http")]
+/// ip-api.com, the free service is plain HTTP only
+pub const IP_API_URL: &'static str = "http://ip-api.com/json/{ip}";
+#[cfg(feature = "http")]
+/// ipinfo.io
+pub const IPINFO_URL: &'static str = "https://ipinfo.io/{ip}/json";
+
+/// Location and network of an address, services leave out the
+/// fields they do not know
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct GeoInfo {
+    pub country: Option<String>,
+    pub region: Option<String>,
+    pub city: Option<String>,
+    /// Number of the AS announcing the address
+    pub asn: Option<u32>,
+    pub isp: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+}
+
+#[cfg(feature = "http")]
+/// GET `url` with `{ip}` replaced and parse the JSON response
+fn get_json(url: &str, ip: &MyIp, timeout: Duration) -> Result<Json, Error> {
+    let url = url.replace("{ip}", &ip.to_string());
+    let cli = client::new_client(&ClientConfig::new(Some(timeout)));
+    let mut res = try!(cli.get(&url)
+                    .send()
+                    .map_err(Error::from));
+    if !res.status.is_success() {
+        return Err(Error::Http { status: res.status.to_u16() });
+    }
+    let mut s = String::new();
+    try!(res.read_to_string(&mut s)
+        .map_err(Error::from));
+    debug!("{} => {}", url, s);
+    Json::from_str(&s).map_err(Error::from)
+}
+
+#[cfg(feature = "http")]
+fn string_field(json: &Json, name: &str) -> Option<String> {
+    json.find(name)
+        .and_then(|val| val.as_string())
+        .filter(|val| !val.is_empty())
+        .map(str::to_owned)
+}
+
+#[cfg(feature = "http")]
+/// Split `AS15169 Google LLC` into the number and the holder
+fn parse_as(s: &str) -> (Option<u32>, Option<String>) {
+    let mut parts = s.splitn(2, ' ');
+    let asn = parts.next()
+                .filter(|n| n.starts_with("AS"))
+                .and_then(|n| n[2..].parse().ok());
+    let name = parts.next().map(str::trim).filter(|name| !name.is_empty()).map(str::to_owned);
+    (asn, name)
+}
+
+#[cfg(feature = "http")]
+/// An ip-api.com response
+fn from_ip_api(json: &Json) -> Result<GeoInfo, Error> {
+    if json.find("status").and_then(|val| val.as_string()) != Some("success") {
+        let msg = string_field(json, "message").unwrap_or_default();
+        return Err(Error::Protocol(format!("ip-api.com lookup failed: {}", msg)));
+    }
+    let (asn, _) = string_field(json, "as").map_or((None, None), |s| parse_as(&s));
+    Ok(GeoInfo {
+        country: string_field(json, "country"),
+        region: string_field(json, "regionName"),
+        city: string_field(json, "city"),
+        asn: asn,
+        isp: string_field(json, "isp"),
+        latitude: json.find("lat").and_then(|val| val.as_f64()),
+        longitude: json.find("lon").and_then(|val| val.as_f64()),
+    })
+}
+
+#[cfg(feature = "http")]
+/// An ipinfo.io response, the network is in `org` and the
+/// coordinates in `loc` as `lat,lon`
+fn from_ipinfo(json: &Json) -> Result<GeoInfo, Error> {
+    if let Some(err) = json.find("error") {
+        let msg = err.find("message").and_then(|val| val.as_string()).unwrap_or("");
+        return Err(Error::Protocol(format!("ipinfo.io lookup failed: {}", msg)));
+    }
+    let (asn, isp) = string_field(json, "org").map_or((None, None), |s| parse_as(&s));
+    let loc: Vec<f64> = string_field(json, "loc")
+                            .map(|loc| loc.split(',').filter_map(|n| n.trim().parse().ok()).collect())
+                            .unwrap_or_default();
+    Ok(GeoInfo {
+        country: string_field(json, "country"),
+        region: string_field(json, "region"),
+        city: string_field(json, "city"),
+        asn: asn,
+        isp: isp,
+        latitude: if loc.len() == 2 { Some(loc[0]) } else { None },
+        longitude: if loc.len() == 2 { Some(loc[1]) } else { None },
+    })
+}
+
+/// A geolocation service or database, implement it to use
+/// your own with `GeoLookup::backend()`
+pub trait GeoBackend: Send + Sync {
+    /// Name used in logs and errors
+    fn name(&self) -> &str;
+
+    /// Where `ip` appears to be
+    fn lookup(&self, ip: &MyIp) -> Result<GeoInfo, Error>;
+}
+
+#[cfg(feature = "http")]
+/// ip-api.com
+pub struct IpApi {
+    url: String,
+    timeout: Duration,
+}
+
+#[cfg(feature = "http")]
+impl IpApi {
+    pub fn new() -> IpApi {
+        IpApi {
+            url: IP_API_URL.to_owned(),
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// The lookup URL, `{ip}` is replaced with the address
+    /// (defaults to **IP_API_URL**)
+    pub fn url(mut self, url: &str) -> IpApi {
+        self.url = url.to_owned();
+        self
+    }
+
+    /// (defaults to **5 seconds**)
+    pub fn timeout(mut self, timeout: Duration) -> IpApi {
+        self.timeout = timeout;
+        self
+    }
+}
+
+#[cfg(feature = "http")]
+impl GeoBackend for IpApi {
+    fn name(&self) -> &str {
+        "ip-api.com"
+    }
+
+    fn lookup(&self, ip: &MyIp) -> Result<GeoInfo, Error> {
+        get_json(&self.url, ip, self.timeout).and_then(|json| from_ip_api(&json))
+    }
+}
+
+#[cfg(feature = "http")]
+/// ipinfo.io, works without a token up to a monthly limit
+pub struct IpInfo {
+    url: String,
+    token: Option<String>,
+    timeout: Duration,
+}
+
+#[cfg(feature = "http")]
+impl IpInfo {
+    pub fn new() -> IpInfo {
+        IpInfo {
+            url: IPINFO_URL.to_owned(),
+            token: None,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// The lookup URL, `{ip}` is replaced with the address
+    /// (defaults to **IPINFO_URL**)
+    pub fn url(mut self, url: &str) -> IpInfo {
+        self.url = url.to_owned();
+        self
+    }
+
+    /// API token, for more requests
+    /// (defaults to **None**)
+    pub fn token(mut self, token: &str) -> IpInfo {
+        self.token = Some(token.to_owned());
+        self
+    }
+
+    /// (defaults to **5 seconds**)
+    pub fn timeout(mut self, timeout: Duration) -> IpInfo {
+        self.timeout = timeout;
+        self
+    }
+}
+
+#[cfg(feature = "http")]
+impl GeoBackend for IpInfo {
+    fn name(&self) -> &str {
+        "ipinfo.io"
+    }
+
+    fn lookup(&self, ip: &MyIp) -> Result<GeoInfo, Error> {
+        let url = match self.token {
+            Some(ref token) => format!("{}{}token={}", self.url,
+                                       if self.url.contains('?') { '&' } else { '?' }, token),
+            None => self.url.clone(),
+        };
+        get_json(&url, ip, self.timeout).and_then(|json| from_ipinfo(&json))
+    }
+}
+
+#[cfg(feature = "maxmind")]
+fn maxmind_error(err: MaxMindDBError) -> Error {
+    Error::Protocol(format!("MaxMind lookup failed: {}", err))
+}
+
+/// Offline lookups in MaxMind GeoIP2/GeoLite2 databases, with the
+/// `maxmind` feature
+#[cfg(feature = "maxmind")]
+pub struct MaxMind {
+    city: Reader<Vec<u8>>,
+    asn: Option<Reader<Vec<u8>>>,
+}
+
+#[cfg(feature = "maxmind")]
+impl MaxMind {
+    /// Open a City database, e.g. `GeoLite2-City.mmdb`
+    pub fn open<P: AsRef<Path>>(city: P) -> Result<MaxMind, Error> {
+        Ok(MaxMind {
+            city: try!(Reader::open_readfile(city).map_err(maxmind_error)),
+            asn: None,
+        })
+    }
+
+    /// Also open an ASN database, e.g. `GeoLite2-ASN.mmdb`, the City
+    /// databases have no ASN or ISP
+    pub fn asn<P: AsRef<Path>>(mut self, path: P) -> Result<MaxMind, Error> {
+        self.asn = Some(try!(Reader::open_readfile(path).map_err(maxmind_error)));
+        Ok(self)
+    }
+}
+
+#[cfg(feature = "maxmind")]
+impl GeoBackend for MaxMind {
+    fn name(&self) -> &str {
+        "maxmind"
+    }
+
+    fn lookup(&self, ip: &MyIp) -> Result<GeoInfo, Error> {
+        // Names are by language, use English
+        fn english(names: Option<::std::collections::BTreeMap<String, String>>) -> Option<String> {
+            names.and_then(|mut names| names.remove("en"))
+        }
+        let city: geoip2::City = try!(self.city.lookup((*ip).into()).map_err(maxmind_error));
+        let mut geo = GeoInfo {
+            country: city.country.and_then(|c| english(c.names)),
+            region: city.subdivisions
+                        .and_then(|subs| subs.into_iter().next())
+                        .and_then(|sub| english(sub.names)),
+            city: city.city.and_then(|c| english(c.names)),
+            latitude: city.location.as_ref().and_then(|loc| loc.latitude),
+            longitude: city.location.as_ref().and_then(|loc| loc.longitude),
+            ..GeoInfo::default()
+        };
+        if let Some(ref reader) = self.asn {
+            match reader.lookup::<geoip2::Asn>((*ip).into()) {
+                Ok(asn) => {
+                    geo.asn = asn.autonomous_system_number;
+                    geo.isp = asn.autonomous_system_organization;
+                },
+                // Some ranges are not announced
+                Err(MaxMindDBError::AddressNotFoundError(_)) => (),
+                Err(err) => return Err(maxmind_error(err)),
+            }
+        }
+        Ok(geo)
+    }
+}
+
+#[cfg(feature = "http")]
+fn default_backends() -> Vec<Box<dyn GeoBackend>> {
+    vec![Box::new(IpApi::new()), Box::new(IpInfo::new())]
+}
+
+#[cfg(not(feature = "http"))]
+fn default_backends() -> Vec<Box<dyn GeoBackend>> {
+    Vec::new()
+}
+
+/// Looks up an address with each backend in turn, until one answers
+pub struct GeoLookup {
+    backends: Vec<Box<dyn GeoBackend>>,
+}
+
+impl GeoLookup {
+    /// Uses ip-api.com, then ipinfo.io. Without the `http` feature
+    /// there are no default backends.
+    pub fn new() -> GeoLookup {
+        GeoLookup {
+            backends: default_backends(),
+        }
+    }
+
+    /// Remove all backends, including the defaults
+    pub fn clear_backends(&mut self) -> &mut Self {
+        self.backends.clear();
+        self
+    }
+
+    /// Add a backend, tried after the ones already added
+    pub fn backend<B: GeoBackend + 'static>(&mut self, backend: B) -> &mut Self {
+        self.backends.push(Box::new(backend));
+        self
+    }
+
+    /// Where `ip` appears to be, from the first backend that answers
+    pub fn lookup(&self, ip: &MyIp) -> Result<GeoInfo, Error> {
+        if self.backends.is_empty() {
+            return Err(Error::NoSources);
+        }
+        let mut errors = Vec::new();
+        for backend in &self.backends {
+            match backend.lookup(ip) {
+                Ok(geo) => {
+                    debug!("{} {} => {:?}", backend.name(), ip, geo);
+                    return Ok(geo);
+                },
+                Err(err) => {
+                    info!("{} {} => {}", backend.name(), ip, err);
+                    errors.push(ProviderError {
+                        source: backend.name().to_owned(),
+                        error: err,
+                    });
+                },
+            }
+        }
+        Err(Error::AllProvidersFailed(errors))
+    }
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn test_geo_json() {
+    let ip_api = Json::from_str(r#"{"status":"success","country":"United States",
+        "regionName":"California","city":"Mountain View","isp":"Google LLC",
+        "as":"AS15169 Google LLC","lat":37.4,"lon":-122.1}"#).unwrap();
+    let ipinfo = Json::from_str(r#"{"ip":"8.8.8.8","city":"Mountain View",
+        "region":"California","country":"US","loc":"37.4,-122.1",
+        "org":"AS15169 Google LLC"}"#).unwrap();
+    let geo = from_ip_api(&ip_api).unwrap();
+    assert_eq!(geo.asn, Some(15169));
+    assert_eq!(geo.isp, Some("Google LLC".to_owned()));
+    assert_eq!(geo.longitude, Some(-122.1));
+    assert_eq!(from_ipinfo(&ipinfo).unwrap(), GeoInfo {
+        country: Some("US".to_owned()),
+        ..geo
+    });
+    let failed = Json::from_str(r#"{"status":"fail","message":"private range"}"#).unwrap();
+    assert!(from_ip_api(&failed).is_err());
+}
+
+#[test]
+fn test_geo_lookup() {
+    use std::str::FromStr;
+    struct Fixed(Option<&'static str>);
+    impl GeoBackend for Fixed {
+        fn name(&self) -> &str {
+            "fixed"
+        }
+        fn lookup(&self, _: &MyIp) -> Result<GeoInfo, Error> {
+            match self.0 {
+                Some(country) => Ok(GeoInfo { country: Some(country.to_owned()), ..GeoInfo::default() }),
+                None => Err(Error::Timeout),
+            }
+        }
+    }
+    let ip = MyIp::from_str("192.0.2.1").unwrap();
+    let mut geo = GeoLookup::new();
+    geo.clear_backends();
+    match geo.lookup(&ip) {
+        Err(Error::NoSources) => (),
+        _ => panic!(),
+    }
+    geo.backend(Fixed(None)).backend(Fixed(Some("PT")));
+    assert_eq!(geo.lookup(&ip).unwrap().country, Some("PT".to_owned()));
+}
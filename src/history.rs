@@ -0,0 +1,122 @@
+//!
+//! Log of address changes
+//!
+
+use std::collections::vec_deque::{self, VecDeque};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use {MyIp, Error, Source};
+
+/// An address change
+#[derive(Clone, Debug)]
+pub struct HistoryEntry {
+    /// The new addresses
+    pub ips: Vec<MyIp>,
+    /// Where the new addresses came from
+    pub sources: Vec<Source>,
+    pub timestamp: SystemTime,
+}
+
+impl HistoryEntry {
+    /// One line for the history file, tab separated time in seconds
+    /// since the epoch, addresses and sources
+    fn to_line(&self) -> String {
+        let secs = self.timestamp.duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0);
+        let ips: Vec<String> = self.ips.iter().map(|ip| ip.to_string()).collect();
+        let sources: Vec<String> = self.sources.iter().map(|src| format!("{:?}", src)).collect();
+        format!("{}\t{}\t{}\n", secs, ips.join(","), sources.join(","))
+    }
+}
+
+/// The most recent address changes, oldest first
+pub struct History {
+    entries: VecDeque<HistoryEntry>,
+    capacity: usize,
+    file: Option<PathBuf>,
+}
+
+impl History {
+    /// Keeps up to `capacity` entries in memory
+    pub fn new(capacity: usize) -> History {
+        History {
+            entries: VecDeque::new(),
+            capacity: capacity,
+            file: None,
+        }
+    }
+
+    /// Also append every entry to `path`, the file is never truncated
+    pub fn file<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.file = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// Number of entries to keep in memory, the oldest are dropped first
+    pub fn capacity(&mut self, capacity: usize) -> &mut Self {
+        self.capacity = capacity;
+        while self.entries.len() > capacity {
+            self.entries.pop_front();
+        }
+        self
+    }
+
+    /// Record a change, it is kept in memory even if the file
+    /// cannot be written
+    pub fn push(&mut self, entry: HistoryEntry) -> Result<(), Error> {
+        let line = entry.to_line();
+        if self.capacity > 0 {
+            if self.entries.len() == self.capacity {
+                self.entries.pop_front();
+            }
+            self.entries.push_back(entry);
+        }
+        if let Some(ref path) = self.file {
+            let mut f = try!(OpenOptions::new()
+                            .create(true)
+                            .append(true)
+                            .open(path)
+                            .map_err(Error::from));
+            try!(f.write_all(line.as_bytes())
+                .map_err(Error::from));
+        }
+        Ok(())
+    }
+
+    pub fn iter<'a>(&'a self) -> vec_deque::Iter<'a, HistoryEntry> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The most recent change
+    pub fn last(&self) -> Option<&HistoryEntry> {
+        self.entries.back()
+    }
+}
+
+#[test]
+fn test_history() {
+    use std::net::Ipv4Addr;
+    let mut history = History::new(2);
+    for i in 1..4 {
+        history.push(HistoryEntry {
+            ips: vec![MyIp::V4(Ipv4Addr::new(192, 0, 2, i))],
+            sources: vec![Source::Igd],
+            timestamp: SystemTime::now(),
+        }).unwrap();
+    }
+    assert_eq!(history.len(), 2);
+    let ips: Vec<MyIp> = history.iter().map(|entry| entry.ips[0]).collect();
+    assert_eq!(ips, vec![MyIp::V4(Ipv4Addr::new(192, 0, 2, 2)),
+                         MyIp::V4(Ipv4Addr::new(192, 0, 2, 3))]);
+}
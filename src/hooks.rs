@@ -0,0 +1,170 @@
+//!
+//! Notify other programs when the address changes
+//!
+
+use std::process::Command;
+use {MyIp, Error, HistoryEntry};
+#[cfg(feature = "http")]
+use std::collections::BTreeMap;
+#[cfg(feature = "http")]
+use std::io::Read;
+#[cfg(feature = "http")]
+use std::time::{Duration, UNIX_EPOCH};
+#[cfg(feature = "http")]
+use hyper::header::{Authorization, Bearer, Headers};
+#[cfg(feature = "http")]
+use rustc_serialize::json::Json;
+#[cfg(feature = "http")]
+use client::{self, ClientConfig};
+
+fn join(ips: &[MyIp]) -> String {
+    let ips: Vec<String> = ips.iter().map(|ip| ip.to_string()).collect();
+    ips.join(" ")
+}
+
+/// Runs a command on each change, the new addresses are appended
+/// to the arguments. The environment has `WHATSMYIP_OLD_IP` and
+/// `WHATSMYIP_NEW_IP`, space separated (old is empty on the first
+/// check).
+pub struct ExecHook {
+    program: String,
+    args: Vec<String>,
+}
+
+impl ExecHook {
+    pub fn new(program: &str, args: &[&str]) -> ExecHook {
+        ExecHook {
+            program: program.to_owned(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+        }
+    }
+
+    /// Run the command and wait for it to exit
+    pub fn run(&self, old: &[MyIp], change: &HistoryEntry) -> Result<(), Error> {
+        let status = try!(Command::new(&self.program)
+                            .args(&self.args)
+                            .args(change.ips.iter().map(|ip| ip.to_string()))
+                            .env("WHATSMYIP_OLD_IP", join(old))
+                            .env("WHATSMYIP_NEW_IP", join(&change.ips))
+                            .status()
+                            .map_err(Error::from));
+        if !status.success() {
+            return Err(Error::Protocol(format!("{} exited with {}", self.program, status)));
+        }
+        Ok(())
+    }
+}
+
+/// POSTs the change as JSON to a URL, e.g.
+///
+/// ```text
+/// {"new":["192.0.2.2"],"old":["192.0.2.1"],"sources":["Igd"],"timestamp":1500000000}
+/// ```
+#[cfg(feature = "http")]
+pub struct WebHook {
+    url: String,
+    token: Option<String>,
+    timeout: Duration,
+}
+
+/// The JSON body for a change
+#[cfg(feature = "http")]
+fn webhook_payload(old: &[MyIp], change: &HistoryEntry) -> String {
+    let ips = |ips: &[MyIp]| Json::Array(ips.iter().map(|ip| Json::String(ip.to_string())).collect());
+    let secs = change.timestamp.duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+    let mut obj = BTreeMap::new();
+    obj.insert("old".to_owned(), ips(old));
+    obj.insert("new".to_owned(), ips(&change.ips));
+    obj.insert("timestamp".to_owned(), Json::U64(secs));
+    obj.insert("sources".to_owned(),
+               Json::Array(change.sources.iter()
+                            .map(|src| Json::String(format!("{:?}", src)))
+                            .collect()));
+    Json::Object(obj).to_string()
+}
+
+#[cfg(feature = "http")]
+impl WebHook {
+    pub fn new(url: &str) -> WebHook {
+        WebHook {
+            url: url.to_owned(),
+            token: None,
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Send `Authorization: Bearer <token>`
+    /// (defaults to **None**)
+    pub fn token(mut self, token: &str) -> WebHook {
+        self.token = Some(token.to_owned());
+        self
+    }
+
+    /// (defaults to **10 seconds**)
+    pub fn timeout(mut self, timeout: Duration) -> WebHook {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Send the change, fails unless the response is a success
+    pub fn run(&self, old: &[MyIp], change: &HistoryEntry) -> Result<(), Error> {
+        let cli = client::new_client(&ClientConfig::new(Some(self.timeout)));
+
+        let mut headers = Headers::new();
+        headers.set_raw("Content-Type", vec![b"application/json".to_vec()]);
+        if let Some(ref token) = self.token {
+            headers.set(Authorization(Bearer { token: token.clone() }));
+        }
+        let body = webhook_payload(old, change);
+        let mut res = try!(cli.post(&self.url)
+                            .headers(headers)
+                            .body(&body)
+                            .send()
+                            .map_err(Error::from));
+        // Drain the body so the connection can be reused
+        let mut s = String::new();
+        let _ = res.read_to_string(&mut s);
+        debug!("{} => {} {}", self.url, res.status, s);
+        if !res.status.is_success() {
+            return Err(Error::Http { status: res.status.to_u16() });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn test_webhook_payload() {
+    use std::net::Ipv4Addr;
+    use Source;
+    let change = HistoryEntry {
+        ips: vec![MyIp::V4(Ipv4Addr::new(192, 0, 2, 2))],
+        sources: vec![Source::Igd],
+        timestamp: UNIX_EPOCH + Duration::from_secs(1500000000),
+    };
+    let old = [MyIp::V4(Ipv4Addr::new(192, 0, 2, 1))];
+    assert_eq!(webhook_payload(&old, &change),
+               r#"{"new":["192.0.2.2"],"old":["192.0.2.1"],"sources":["Igd"],"timestamp":1500000000}"#);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_exec_hook() {
+    use std::net::Ipv4Addr;
+    use std::time::SystemTime;
+    let change = HistoryEntry {
+        ips: vec![MyIp::V4(Ipv4Addr::new(192, 0, 2, 2))],
+        sources: Vec::new(),
+        timestamp: SystemTime::now(),
+    };
+    let old = [MyIp::V4(Ipv4Addr::new(192, 0, 2, 1))];
+    let script = r#"test "$WHATSMYIP_OLD_IP" = 192.0.2.1 -a "$WHATSMYIP_NEW_IP" = "$1""#;
+    ExecHook::new("sh", &["-c", script, "sh"]).run(&old, &change).unwrap();
+    assert!(ExecHook::new("false", &[]).run(&old, &change).is_err());
+}
@@ -0,0 +1,327 @@
+//!
+//! HTTP requests for `HttpProvider`, with the `http` feature
+//!
+
+use std::cmp::min;
+use std::io::Read;
+use std::time::{Duration, Instant};
+use hyper::client::Response;
+use hyper::Url;
+use hyper::header::{Headers, Location, UserAgent};
+use hyper::status::StatusCode;
+use rustc_serialize::json::Json;
+use regex::Regex;
+use {MyIp, Error, Context, ip_from_str};
+use client::{self, ClientConfig};
+#[cfg(test)]
+use std::net::{Ipv4Addr, Ipv6Addr};
+#[cfg(test)]
+use {HttpProvider, WhatsMyIp, IPV4_PATTERN, Provider, provider};
+#[cfg(test)]
+use fixture::Fixture;
+
+/// The shorter of two timeouts, None is no timeout
+pub fn shorter(a: Option<Duration>, b: Option<Duration>) -> Option<Duration> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(min(a, b)),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+fn http_request(ctx: &Context, url: &str) -> Result<Response, Error> {
+    let mut headers = Headers::new();
+    if let Some(ref agent) = ctx.opts.user_agent {
+        headers.set(UserAgent(agent.clone()));
+    }
+    for &(ref name, ref value) in &ctx.opts.http_headers {
+        headers.set_raw(name.clone(), vec![value.as_bytes().to_vec()]);
+    }
+
+    // Redirects are followed here rather than by hyper, which
+    // has no limit and would follow them to plain HTTP
+    let mut url = try!(Url::parse(url)
+                        .map_err(|err| Error::Parse(format!("Invalid URL {}: {}", url, err))));
+    let mut redirects = 0;
+    loop {
+        let config = ClientConfig {
+            connect_timeout: shorter(ctx.opts.http_connect_timeout, ctx.timeout),
+            read_timeout: shorter(ctx.opts.http_read_timeout, ctx.timeout),
+            proxy: ctx.opts.proxy_for(&url.serialize()),
+            bind: try!(ctx.opts.local_bind()),
+            hosts: ctx.opts.hosts.clone(),
+            pins: ctx.pins.to_vec(),
+            follow_redirects: false,
+        };
+        let cli = match ctx.opts.http_pool {
+            Some(ref pool) => pool.client(&config),
+            None => client::new_client(&config),
+        };
+        let res = try!(cli.get(url.clone())
+                        .headers(headers.clone())
+                        .send()
+                        .map_err(Error::from));
+        let location = match res.headers.get::<Location>() {
+            Some(&Location(ref location)) if res.status.is_redirection() => location.clone(),
+            _ if res.status != StatusCode::Ok => return Err(Error::Http { status: res.status.to_u16() }),
+            _ => return Ok(res),
+        };
+        let next = try!(url.join(&location)
+                        .map_err(|_| Error::Parse(format!("Invalid redirect to {}", location))));
+        debug!("{} redirects to {}", url, next);
+        if redirects >= ctx.opts.max_redirects {
+            info!("Too many redirects from {}", url);
+            return Err(Error::Redirect { location: next.serialize() });
+        }
+        // Pins only protect HTTPS
+        if url.scheme == "https" && next.scheme != "https"
+                && (!ctx.opts.allow_downgrade || !ctx.pins.is_empty()) {
+            info!("Refusing redirect from {} to {}", url, next);
+            return Err(Error::Redirect { location: next.serialize() });
+        }
+        url = next;
+        redirects += 1;
+    }
+}
+
+/// Most bytes read from plain text services, room for a few
+/// addresses and some whitespace
+const TEXT_LIMIT: usize = 256;
+
+/// Most bytes read from other services, e.g. JSON or web pages
+const BODY_LIMIT: usize = 64 * 1024;
+
+/// The response body and the URL it came from, after redirects,
+/// bodies over `limit` bytes are an error
+fn http_page(ctx: &Context, url: &str, limit: usize) -> Result<(String, Url), Error> {
+    let start = Instant::now();
+    let mut res = try!(http_request(ctx, url));
+    // The read timeout is per read, a slow body could go on
+    // for much longer than the request timeout
+    let mut body = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = try!(res.read(&mut buf)
+                        .map_err(Error::from));
+        if n == 0 {
+            break;
+        }
+        body.extend_from_slice(&buf[..n]);
+        if body.len() > limit {
+            return Err(Error::Parse(format!("Response from {} is over {} bytes", url, limit)));
+        }
+        if ctx.timeout.map_or(false, |t| start.elapsed() > t) {
+            return Err(Error::Timeout);
+        }
+    }
+    let s = try!(String::from_utf8(body)
+                    .map_err(|_| Error::Parse(format!("Invalid UTF-8 in response from {}", url))));
+
+    debug!("{} => {}", &url, &s);
+    Ok((s, res.url.clone()))
+}
+
+/// True if `body` is a web page rather than data
+fn is_html(body: &str) -> bool {
+    let start = body.trim_left();
+    start.starts_with('<') || start.chars().take(256).collect::<String>()
+                                    .to_lowercase().contains("<html")
+}
+
+/// The body of a service that answers with data, a web page
+/// instead is most likely a captive portal
+fn http_get(ctx: &Context, url: &str, limit: usize) -> Result<String, Error> {
+    let (body, page) = try!(http_page(ctx, url, limit));
+    if is_html(&body) {
+        info!("{} answered with a web page from {}", url, page);
+        return Err(Error::Html { url: page.serialize() });
+    }
+    Ok(body)
+}
+
+pub fn http_ip_txt(ctx: &Context, url: &str, _: &str) -> Result<MyIp, Error> {
+    let s = try!(http_get(ctx, url, TEXT_LIMIT));
+    ip_from_str(&s)
+}
+
+fn ip_from_json(body: &str, field: &str) -> Result<MyIp, Error> {
+    let json = try!(Json::from_str(body)
+                    .map_err(Error::from));
+    match json.find(field).and_then(|val| val.as_string()) {
+        Some(ip_s) => ip_from_str(ip_s),
+        None => Err(Error::Parse(format!("No field {} in JSON response", field))),
+    }
+}
+
+/// Addresses in any of the comma separated `fields`
+fn ips_from_json(body: &str, fields: &str) -> Result<Vec<MyIp>, Error> {
+    let json = try!(Json::from_str(body)
+                    .map_err(Error::from));
+    let ips: Vec<MyIp> = fields.split(',')
+                            .filter_map(|field| json.find(field).and_then(|val| val.as_string()))
+                            .filter_map(|ip_s| ip_from_str(ip_s).ok())
+                            .collect();
+    if ips.is_empty() {
+        Err(Error::Parse(format!("No address in fields {} of JSON response", fields)))
+    } else {
+        Ok(ips)
+    }
+}
+
+pub fn http_ips_json(ctx: &Context, url: &str, fields: &str) -> Result<Vec<MyIp>, Error> {
+    let s = try!(http_get(ctx, url, BODY_LIMIT));
+    ips_from_json(&s, fields)
+}
+
+pub fn http_ips_txt(ctx: &Context, url: &str, _: &str) -> Result<Vec<MyIp>, Error> {
+    let s = try!(http_get(ctx, url, TEXT_LIMIT));
+    // Anything but addresses means this is not the answer we expect
+    let ips: Vec<MyIp> = try!(s.split_whitespace()
+                                .map(ip_from_str)
+                                .collect());
+    if ips.is_empty() {
+        Err(Error::Parse(format!("Invalid IP address {}", s)))
+    } else {
+        Ok(ips)
+    }
+}
+
+pub fn http_ip_json(ctx: &Context, url: &str, field: &str) -> Result<MyIp, Error> {
+    let s = try!(http_get(ctx, url, BODY_LIMIT));
+    ip_from_json(&s, field)
+}
+
+pub fn http_ip_header(ctx: &Context, url: &str, header: &str) -> Result<MyIp, Error> {
+    let res = try!(http_request(ctx, url));
+    let value = match res.headers.get_raw(header).and_then(|vals| vals.first()) {
+        Some(val) => String::from_utf8_lossy(val).into_owned(),
+        None => return Err(Error::Parse(format!("No header {} in response", header))),
+    };
+    debug!("{} => {}: {}", &url, header, &value);
+    // Proxy headers may hold a list of addresses, the client is the first
+    ip_from_str(value.split(',').next().unwrap_or(""))
+}
+
+/// Returns the first valid address matched by `pattern`. If the
+/// pattern has a capture group the address is taken from it.
+pub fn ip_from_regex(body: &str, pattern: &str) -> Result<MyIp, Error> {
+    let re = try!(Regex::new(pattern)
+                    .map_err(Error::from));
+    for caps in re.captures_iter(body) {
+        let m = match caps.at(1).or(caps.at(0)) {
+            Some(m) => m,
+            None => continue,
+        };
+        if let Ok(ip) = ip_from_str(m) {
+            return Ok(ip);
+        }
+    }
+    Err(Error::Parse(format!("No IP address matching {}", pattern)))
+}
+
+pub fn http_ip_regex(ctx: &Context, url: &str, pattern: &str) -> Result<MyIp, Error> {
+    let (s, _) = try!(http_page(ctx, url, BODY_LIMIT));
+    ip_from_regex(&s, pattern)
+}
+
+/// Needs internet access, run with `cargo test -- --ignored`
+#[ignore]
+#[test]
+fn test_http_providers() {
+    let w = WhatsMyIp::new();
+    let ctx = Context {
+        opts: &w,
+        timeout: None,
+        pins: &[],
+    };
+    for provider in provider::http_providers() {
+        assert!(provider.fetch(&ctx).is_ok());
+    }
+}
+
+/// Fetch from `provider` with a short timeout, for the fixture tests
+#[cfg(test)]
+fn fetch_with(opts: &WhatsMyIp, provider: HttpProvider) -> Result<MyIp, Error> {
+    let ctx = Context {
+        opts: opts,
+        timeout: Some(Duration::from_millis(500)),
+        pins: &[],
+    };
+    provider.fetch(&ctx)
+}
+
+#[test]
+fn test_fixture_providers() {
+    let fixture = Fixture::start();
+    let mut opts = WhatsMyIp::new();
+    opts.proxy_from_env(false);
+    let fetch = |provider| fetch_with(&opts, provider);
+
+    assert_eq!(fetch(HttpProvider::text("text", &fixture.url("/text"))).unwrap(),
+               MyIp::V4(Ipv4Addr::new(192, 0, 2, 1)));
+    assert_eq!(fetch(HttpProvider::json("json", &fixture.url("/json"), "ip")).unwrap(),
+               MyIp::V4(Ipv4Addr::new(192, 0, 2, 2)));
+    // The client is the first address in proxy headers
+    assert_eq!(fetch(HttpProvider::header("header", &fixture.url("/header"), "X-Client-Ip")).unwrap(),
+               MyIp::V4(Ipv4Addr::new(192, 0, 2, 3)));
+
+    match fetch(HttpProvider::text("malformed", &fixture.url("/malformed"))) {
+        Err(Error::Parse(_)) => (),
+        res => panic!("{:?}", res),
+    }
+    assert!(fetch(HttpProvider::json("bad-json", &fixture.url("/bad-json"), "ip")).is_err());
+    match fetch(HttpProvider::text("portal", &fixture.url("/portal"))) {
+        Err(Error::Html { .. }) => (),
+        res => panic!("{:?}", res),
+    }
+    match fetch(HttpProvider::text("down", &fixture.url("/down"))) {
+        Err(Error::Http { status: 503 }) => (),
+        res => panic!("{:?}", res),
+    }
+    match fetch(HttpProvider::text("missing", &fixture.url("/missing"))) {
+        Err(Error::Http { status: 404 }) => (),
+        res => panic!("{:?}", res),
+    }
+}
+
+#[test]
+fn test_fixture_slow_and_redirects() {
+    let fixture = Fixture::start();
+    let mut opts = WhatsMyIp::new();
+    opts.proxy_from_env(false).max_redirects(2);
+
+    match fetch_with(&opts, HttpProvider::text("slow", &fixture.url("/slow"))) {
+        Err(Error::Timeout) => (),
+        res => panic!("{:?}", res),
+    }
+    assert_eq!(fetch_with(&opts, HttpProvider::text("redirect", &fixture.url("/redirect"))).unwrap(),
+               MyIp::V4(Ipv4Addr::new(192, 0, 2, 1)));
+    match fetch_with(&opts, HttpProvider::text("loop", &fixture.url("/loop"))) {
+        Err(Error::Redirect { .. }) => (),
+        res => panic!("{:?}", res),
+    }
+}
+
+#[test]
+fn test_ip_from_json() {
+    let body = r#"{"status":"success","country":"Portugal","query":"192.0.2.1"}"#;
+    assert!(ip_from_json(body, "query").unwrap() == MyIp::V4(Ipv4Addr::new(192, 0, 2, 1)));
+    assert!(ip_from_json(body, "ip").is_err());
+}
+
+#[test]
+fn test_ips_from_json() {
+    let body = r#"{"ipv4":"192.0.2.1","ipv6":"2001:db8::1"}"#;
+    let ips = ips_from_json(body, "ipv4,ipv6,missing").unwrap();
+    assert!(ips == vec![MyIp::V4(Ipv4Addr::new(192, 0, 2, 1)),
+                        MyIp::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1))]);
+    assert!(ips_from_json(body, "ip").is_err());
+}
+
+#[test]
+fn test_ip_from_regex() {
+    let body = "<html><td>LAN</td><td>192.168.1.1</td>\
+                <td>WAN</td><td>192.0.2.1</td></html>";
+    assert!(ip_from_regex(body, IPV4_PATTERN).unwrap() == MyIp::V4(Ipv4Addr::new(192, 168, 1, 1)));
+    assert!(ip_from_regex(body, r"WAN</td><td>([^<]+)").unwrap() == MyIp::V4(Ipv4Addr::new(192, 0, 2, 1)));
+}
@@ -2,7 +2,9 @@
 //! Find out your external IP address, using
 //!
 //! 1. Internet Gateway Device protocol
-//! 2. Public HTTP Services for address information
+//! 2. Public STUN servers
+//! 3. DNS resolvers that answer with your address (OpenDNS, Cloudflare, Google)
+//! 4. Public HTTP Services for address information
 //!
 //! ## Usage
 //!
@@ -21,87 +23,236 @@
 //!                         .find().unwrap();
 //! ```
 //!
+//! ## Features
+//!
+//! Each kind of source has a cargo feature, all on by default. With
+//! `default-features = false` pick the ones you need, e.g. `dns` and
+//! `stun` for a build without hyper, or `http` with one of
+//! `openssl-tls` and `rustls-tls`.
+//!
+//! - `http`: HTTP providers and the rest of the HTTP based APIs
+//! - `igd`: the gateway, with UPnP IGD
+//! - `dns`: DNS providers, reverse DNS, ASN and blocklist lookups
+//! - `stun`: STUN and TURN servers
+//! - `cli`: the `whatsmyip` binary
+//!
+//! The `ffi` feature (off by default) adds C bindings, see `ffi`,
+//! and `python` a Python extension module with `find()` and
+//! `monitor()`, see `src/python.rs` for how to build it.
+//!
 
-extern crate hyper;
+// Keep the 2015 idioms (try!, ref patterns, explicit field names)
+// that newer lints would rewrite
+#![allow(deprecated, ellipsis_inclusive_range_patterns)]
+#![allow(clippy::redundant_field_names, clippy::redundant_static_lifetimes,
+         clippy::needless_borrowed_reference, clippy::match_ref_pats,
+         clippy::new_without_default, clippy::question_mark,
+         clippy::unnecessary_map_or, clippy::io_other_error)]
+
+#[cfg(feature = "http")] extern crate hyper;
 #[macro_use] extern crate log;
 extern crate rand;
-extern crate igd;
+#[cfg(feature = "igd")] extern crate igd;
+extern crate rustc_serialize;
+extern crate regex;
+extern crate crypto;
+extern crate get_if_addrs;
+extern crate socket2;
+#[cfg(all(feature = "openssl-tls", not(feature = "rustls-tls")))] extern crate openssl;
+#[cfg(feature = "rustls-tls")] extern crate rustls;
+#[cfg(feature = "rustls-tls")] extern crate webpki;
+#[cfg(feature = "rustls-tls")] extern crate webpki_roots;
+#[cfg(all(target_os = "linux", feature = "linux-netlink"))] extern crate libc;
+#[cfg(feature = "maxmind")] extern crate maxminddb;
+#[cfg(feature = "serde")] #[macro_use] extern crate serde;
+#[cfg(all(test, feature = "serde"))] extern crate serde_json;
+#[cfg(feature = "python")] #[macro_use] extern crate pyo3;
+
+#[cfg(all(feature = "http", not(any(feature = "openssl-tls", feature = "rustls-tls"))))]
+compile_error!("The http feature needs a TLS backend, enable openssl-tls or rustls-tls");
 
-use hyper::Client;
-use hyper::status::StatusCode;
 use std::io::Read;
-use rand::{StdRng, Rng};
+use std::fs::File;
+use std::path::Path;
+use rand::{thread_rng, Isaac64Rng, Rng, SeedableRng};
 use std::str::FromStr;
-use std::net::{Ipv4Addr, Ipv6Addr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::fmt;
-use std::time::Duration;
-use std::cmp::min;
+use std::time::{Duration, Instant, SystemTime};
+use std::cmp::{min, max, Reverse};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{Receiver, Sender};
+#[cfg(feature = "igd")]
+use std::sync::mpsc::channel;
+use std::thread;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use bind::Bind;
+#[cfg(feature = "http")]
+use client::HttpPool;
+use resolver::Hosts;
 
+mod bind;
+#[cfg(feature = "http")]
+mod client;
+mod config;
+mod dht;
+mod error;
+#[cfg(all(test, feature = "http"))]
+mod fixture;
+#[cfg(feature = "async")]
+mod future;
+#[cfg(feature = "igd")]
+mod gateway;
+mod history;
+mod hooks;
+#[cfg(feature = "http")]
+mod http;
+mod local;
+mod monitor;
+mod nat;
+mod pin;
+mod proxy;
+mod resolver;
+#[cfg(all(target_os = "linux", feature = "linux-netlink"))]
+mod netlink;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "http")]
+mod router;
+#[cfg(feature = "route53")]
+mod route53;
+mod sip;
+mod state;
+mod stream;
+#[cfg(feature = "stun")]
+mod stun;
+mod tcp;
+#[cfg(feature = "rustls-tls")]
+mod tls;
+mod websocket;
+#[cfg(feature = "dns")]
+pub mod asn;
+#[cfg(feature = "http")]
+pub mod cloud;
+#[cfg(feature = "http")]
+pub mod ddns;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "dns")]
+pub mod dns;
+#[cfg(feature = "dns")]
+pub mod dnsbl;
+pub mod geo;
+#[cfg(feature = "http")]
+pub mod portcheck;
+pub mod provider;
+#[cfg(feature = "dns")]
+pub use asn::{AsnInfo, Cymru};
+pub use stream::FindStream;
+#[cfg(feature = "stun")]
+pub use stun::STUN_SERVERS;
+#[cfg(feature = "http")]
+pub use client::WhatsMyIpClient;
+#[cfg(feature = "http")]
+pub use ddns::{Cloudflare, DuckDns, Dyndns2, Updater};
+pub use dht::DHT_NODES;
+#[cfg(feature = "dns")]
+pub use dnsbl::{DnsblCheck, DnsblResult};
+pub use error::{Error, ProviderError};
+#[cfg(feature = "async")]
+pub use future::FindFuture;
+pub use geo::{GeoBackend, GeoInfo, GeoLookup};
+#[cfg(feature = "igd")]
+pub use gateway::{GatewayIp, IgdSelfTest, Ipv6Prefix, PortMapping, igd_ipv6_prefix, igd_self_test,
+                  list_port_mappings, search_gateways};
+pub use history::{History, HistoryEntry};
+pub use local::Ipv6Preference;
+pub use hooks::ExecHook;
+#[cfg(feature = "http")]
+pub use hooks::WebHook;
+pub use monitor::IpMonitor;
+pub use nat::{NatStatus, NetworkSummary};
+pub use provider::{Provider, MockProvider, Context, IpFamily, ProviderStats, Strategy,
+                   BenchmarkResult};
+#[cfg(feature = "http")]
+pub use provider::HttpProvider;
+pub use pin::Pin;
+#[cfg(feature = "http")]
+pub use portcheck::PortCheck;
+pub use proxy::Proxy;
+pub use resolver::{Resolver, SystemResolver};
+#[cfg(feature = "http")]
+pub use router::RouterScrape;
+#[cfg(feature = "route53")]
+pub use route53::Route53;
+pub use state::State;
+pub use tcp::TcpTextProvider;
+pub use websocket::WebSocketProvider;
 
-// TODO: Get ip from local interfaces
 // TODO: PCP
 // TODO: NAT-PMP
 
-fn ip_from_str(ip_s: &str) -> Result<MyIp, String> {
-    // FIXME: check for private addresses and other
-    // erroneous cases
-    let ip_trimmed = ip_s.trim();
-    if let Ok(ip) = Ipv4Addr::from_str(ip_trimmed) {
-        return Ok(MyIp::V4(ip));
-    }
-    if let Ok(ip) = Ipv6Addr::from_str(ip_trimmed) {
-        return Ok(MyIp::V6(ip));
-    }
-    Err(format!("Invalid IP address {}", ip_s))
+fn ip_from_str(ip_s: &str) -> Result<MyIp, Error> {
+    // Private and reserved addresses are dropped later, see
+    // `WhatsMyIp::public_only()`
+    MyIp::from_str(ip_s.trim())
 }
 
-fn http_ip_txt(opts: &WhatsMyIp, url: &str) -> Result<MyIp,String> {
-    let mut cli = Client::new();
-    cli.set_read_timeout(opts.http_timeout);
-    cli.set_write_timeout(opts.http_timeout);
-    let mut res = try!(cli.get(url)
-                    .send()
-                    .map_err(|err| format!("{}", err)));
-    if res.status != StatusCode::Ok {
-        return Err(format!("{}", res.status))
-    }
+/// Matches IPv4 addresses in text, for use with `http_regex_provider()`
+pub const IPV4_PATTERN: &'static str = r"\b(?:\d{1,3}\.){3}\d{1,3}\b";
 
-    let mut s = String::new();
-    try!(res.read_to_string(&mut s)
-        .map_err(|err| format!("{}", err)));
+/// What kind of network an address belongs to, see `MyIp::scope()`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum IpScope {
+    /// Reachable from the internet
+    Public,
+    /// RFC 1918, e.g. 192.168.0.0/16
+    Private,
+    /// Shared address space of carrier grade NAT, 100.64.0.0/10
+    Cgnat,
+    /// 169.254.0.0/16 and fe80::/10
+    LinkLocal,
+    /// IPv6 unique local addresses, fc00::/7
+    UniqueLocal,
+    /// 127.0.0.0/8 and ::1
+    Loopback,
+    /// Multicast, documentation and other reserved ranges
+    Reserved,
+}
 
-    debug!("{} => {}", &url, &s);
-    ip_from_str(&s)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum MyIp {
+    V4(Ipv4Addr),
+    V6(Ipv6Addr),
 }
 
-fn igd_ip() -> Option<MyIp> {
-    match igd::search_gateway() {
-        Ok(gw) => match gw.get_external_ip() {
-            Ok(ip) => {
-                // FIXME: check for private IP addresses
-                debug!("IGD => {}", ip);
-                return Some(MyIp::V4(ip))
-            },
-            Err(_) => info!("Unable to find IGD gateway"),
-        },
-        Err(err) => info!("Unable to find gateway: {}", err),
+impl From<IpAddr> for MyIp {
+    fn from(ip: IpAddr) -> MyIp {
+        match ip {
+            IpAddr::V4(ip) => MyIp::V4(ip),
+            IpAddr::V6(ip) => MyIp::V6(ip),
+        }
     }
-    None
 }
 
-// TODO: ip-api.com/json 
-type Provider = (&'static str, fn(&WhatsMyIp, &str) -> Result<MyIp, String>);
-const HTTP_PROVIDERS: &'static [Provider] = &[
-    ("http://icanhazip.com", http_ip_txt),
-    ("http://myip.dnsomatic.com", http_ip_txt),
-    ("http://bot.whatismyipaddress.com/", http_ip_txt),
-    ("https://api.ipify.org?format=text", http_ip_txt),
-    ];
+impl From<MyIp> for IpAddr {
+    fn from(ip: MyIp) -> IpAddr {
+        match ip {
+            MyIp::V4(ip) => IpAddr::V4(ip),
+            MyIp::V6(ip) => IpAddr::V6(ip),
+        }
+    }
+}
 
-#[derive(PartialEq)]
-pub enum MyIp {
-    V4(Ipv4Addr),
-    V6(Ipv6Addr),
+impl FromStr for MyIp {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<MyIp, Error> {
+        IpAddr::from_str(s)
+            .map(MyIp::from)
+            .map_err(|_| Error::Parse(format!("Invalid IP address {}", s)))
+    }
 }
 
 impl fmt::Display for MyIp {
@@ -113,131 +264,2336 @@ impl fmt::Display for MyIp {
     }
 }
 
+/// Serialized as a string, same as `IpAddr`
+#[cfg(feature = "serde")]
+impl serde::Serialize for MyIp {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        IpAddr::from(*self).serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for MyIp {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<MyIp, D::Error> {
+        IpAddr::deserialize(deserializer).map(MyIp::from)
+    }
+}
+
+impl MyIp {
+    pub fn is_ipv4(&self) -> bool {
+        match *self {
+            MyIp::V4(_) => true,
+            MyIp::V6(_) => false,
+        }
+    }
+
+    pub fn is_ipv6(&self) -> bool {
+        !self.is_ipv4()
+    }
+
+    /// The IPv4 address, None for IPv6
+    pub fn to_ipv4(&self) -> Option<Ipv4Addr> {
+        match *self {
+            MyIp::V4(ip) => Some(ip),
+            MyIp::V6(_) => None,
+        }
+    }
+
+    /// The IPv6 address, None for IPv4
+    pub fn to_ipv6(&self) -> Option<Ipv6Addr> {
+        match *self {
+            MyIp::V4(_) => None,
+            MyIp::V6(ip) => Some(ip),
+        }
+    }
+
+    /// The kind of network the address belongs to
+    pub fn scope(&self) -> IpScope {
+        match *self {
+            MyIp::V4(ip) => {
+                let o = ip.octets();
+                if o[0] == 10 || (o[0] == 172 && o[1] & 0xf0 == 16)
+                        || (o[0] == 192 && o[1] == 168) {
+                    IpScope::Private
+                } else if o[0] == 100 && o[1] & 0xc0 == 64 {
+                    IpScope::Cgnat
+                } else if o[0] == 127 {
+                    IpScope::Loopback
+                } else if o[0] == 169 && o[1] == 254 {
+                    IpScope::LinkLocal
+                } else if o[0] == 0
+                        || (o[0] == 192 && o[1] == 0 && (o[2] == 0 || o[2] == 2))
+                        || (o[0] == 198 && o[1] & 0xfe == 18)
+                        || (o[0] == 198 && o[1] == 51 && o[2] == 100)
+                        || (o[0] == 203 && o[1] == 0 && o[2] == 113)
+                        // Multicast, reserved and broadcast
+                        || o[0] >= 224 {
+                    IpScope::Reserved
+                } else {
+                    IpScope::Public
+                }
+            },
+            MyIp::V6(ip) => {
+                let s = ip.segments();
+                if ip.is_loopback() {
+                    IpScope::Loopback
+                } else if s[0] & 0xffc0 == 0xfe80 {
+                    IpScope::LinkLocal
+                } else if s[0] & 0xfe00 == 0xfc00 {
+                    IpScope::UniqueLocal
+                // Global unicast, less the documentation prefix
+                } else if s[0] & 0xe000 == 0x2000 && !(s[0] == 0x2001 && s[1] == 0xdb8) {
+                    IpScope::Public
+                } else {
+                    IpScope::Reserved
+                }
+            },
+        }
+    }
+
+    /// The IPv4 address for an IPv4-mapped IPv6 address
+    /// (::ffff:a.b.c.d), any other address as is
+    pub fn to_canonical(&self) -> MyIp {
+        match *self {
+            MyIp::V6(ip) => {
+                let s = ip.segments();
+                if s[..5] == [0, 0, 0, 0, 0] && s[5] == 0xffff {
+                    MyIp::V4(Ipv4Addr::new((s[6] >> 8) as u8, s[6] as u8,
+                                           (s[7] >> 8) as u8, s[7] as u8))
+                } else {
+                    *self
+                }
+            },
+            MyIp::V4(_) => *self,
+        }
+    }
+
+    /// True for addresses reachable from the internet, see `scope()`
+    pub fn is_public(&self) -> bool {
+        self.scope() == IpScope::Public
+    }
+
+    /// The 6to4 address (2002:AABB:CCDD::) for a public IPv4 address
+    pub fn to_6to4(&self) -> Option<Ipv6Addr> {
+        match self {
+            &MyIp::V4(ip) => {
+                if self.scope() != IpScope::Public {
+                    return None;
+                }
+                let o = ip.octets();
+                Some(Ipv6Addr::new(0x2002, (o[0] as u16) << 8 | o[1] as u16,
+                                   (o[2] as u16) << 8 | o[3] as u16, 0, 0, 0, 0, 0))
+            },
+            &MyIp::V6(_) => None,
+        }
+    }
+
+    /// For a Teredo address (2001:0::/32) the external IPv4 address
+    /// of the client, stored in the last 32 bits with every bit flipped
+    pub fn teredo_ipv4(&self) -> Option<Ipv4Addr> {
+        match self {
+            &MyIp::V6(ip) => {
+                let s = ip.segments();
+                if s[0] != 0x2001 || s[1] != 0 {
+                    return None;
+                }
+                let hi = s[6] ^ 0xffff;
+                let lo = s[7] ^ 0xffff;
+                Some(Ipv4Addr::new((hi >> 8) as u8, hi as u8, (lo >> 8) as u8, lo as u8))
+            },
+            &MyIp::V4(_) => None,
+        }
+    }
+}
+
+/// Addresses found so far, one report per source
+struct Found {
+    reports: Vec<IpReport>,
+    // Addresses of other families are ignored
+    family: IpFamily,
+    // Private and reserved addresses are errors
+    public_only: bool,
+    // IPv4-mapped addresses are reported as IPv4
+    canonical: bool,
+    errors: Vec<ProviderError>,
+    // Gets a copy of each report as it arrives, see `find_stream()`
+    tx: Option<Sender<IpReport>>,
+}
+
+impl Found {
+    fn new(family: IpFamily) -> Found {
+        Found {
+            reports: Vec::new(),
+            family: family,
+            public_only: false,
+            canonical: false,
+            errors: Vec::new(),
+            tx: None,
+        }
+    }
+
+    /// Record a source that failed
+    fn fail(&mut self, source: &str, err: Error) {
+        info!("{} => {}", source, err);
+        self.errors.push(ProviderError {
+            source: source.to_owned(),
+            error: err,
+        });
+    }
+
+    /// Record an address reported by `source`, the query
+    /// started at `start`
+    fn add(&mut self, ip: MyIp, source: Source, start: Instant) {
+        self.add_timed(ip, source, start.elapsed())
+    }
+
+    /// Same as `add()` with a known latency
+    fn add_timed(&mut self, ip: MyIp, source: Source, latency: Duration) {
+        let ip = if self.canonical { ip.to_canonical() } else { ip };
+        if !self.family.includes(&ip) {
+            debug!("Ignoring {}, not {:?}", ip, self.family);
+            return;
+        }
+        self.push(IpReport {
+            ip: ip,
+            scope: ip.scope(),
+            source: source,
+            latency: latency,
+            timestamp: SystemTime::now(),
+            hostname: None,
+        });
+    }
+
+    /// Add the reports and errors from `other`
+    fn merge(&mut self, other: Found) {
+        for report in other.reports {
+            self.push(report);
+        }
+        self.errors.extend(other.errors);
+    }
+
+    fn push(&mut self, report: IpReport) {
+        if self.public_only && !report.ip.is_public() {
+            let source = format!("{:?}", report.source);
+            self.fail(&source, Error::Parse(format!("{} is not a public address", report.ip)));
+            return;
+        }
+        if let Some(ref tx) = self.tx {
+            // The receiver may be gone, that is fine
+            let _ = tx.send(report.clone());
+        }
+        self.reports.push(report);
+    }
+
+    /// Number of sources that reported `ip`
+    fn count(&self, ip: &MyIp) -> usize {
+        self.reports.iter().filter(|r| r.ip == *ip).count()
+    }
+
+    /// Each address once, in the order they were found
+    fn distinct(&self) -> Vec<&MyIp> {
+        let mut ips: Vec<&MyIp> = Vec::new();
+        for report in &self.reports {
+            if !ips.contains(&&report.ip) {
+                ips.push(&report.ip);
+            }
+        }
+        ips
+    }
+
+    /// True if an address of `family` has at least `votes`
+    fn has(&self, family: IpFamily, votes: usize) -> bool {
+        self.reports.iter().any(|r| family.includes(&r.ip) && self.count(&r.ip) >= votes)
+    }
+
+    /// Votes for the most reported address
+    fn votes(&self) -> usize {
+        self.reports.iter().map(|r| self.count(&r.ip)).max().unwrap_or(0)
+    }
+
+    fn into_ips(self) -> Vec<MyIp> {
+        unique_ips(self.reports)
+    }
+}
+
+impl fmt::Display for Found {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (idx, ip) in self.distinct().into_iter().enumerate() {
+            if idx > 0 {
+                try!(write!(f, ", "));
+            }
+            try!(write!(f, "{} ({})", ip, self.count(ip)));
+        }
+        Ok(())
+    }
+}
+
+/// Query the IGD gateway for the IPv4 address, see
+/// `WhatsMyIp::igd_all()` and `igd_verify()`, then for the IPv6
+/// address unless `enough` is true
+#[cfg(feature = "igd")]
+fn igd_sources<F: Fn(&Found) -> bool>(results: &mut Found, local: Ipv4Addr, all: bool,
+                                      verify: bool, timeout: Duration, enough: F) {
+    let start = Instant::now();
+    if all {
+        match gateway::search_gateways_from(local, timeout) {
+            Ok(gateways) => for gw in gateways {
+                info!("IGD {} => {}", gw.gateway, gw.ip);
+                results.add(gw.ip, Source::Igd, start);
+            },
+            Err(err) => results.fail("IGD", err),
+        }
+    } else if verify {
+        match gateway::self_test_from(local, timeout) {
+            Ok(ref test) if !test.routable => {
+                info!("IGD {} address {} does not route back to us, stale or double NAT",
+                      test.gateway, test.ip);
+            },
+            Ok(test) => results.add(test.ip, Source::Igd, start),
+            Err(err) => results.fail("IGD self test", err),
+        }
+    } else {
+        match gateway::igd_ip(local, timeout) {
+            Some(ip) => results.add(ip, Source::Igd, start),
+            None => results.fail("IGD", Error::Protocol("No IGD gateway".to_owned())),
+        }
+    }
+    if enough(results) {
+        return;
+    }
+
+    let start = Instant::now();
+    if let Some(ip) = gateway::igd_ipv6(local, timeout) {
+        results.add(ip, Source::Igd, start);
+    }
+}
+
+/// End of the `WhatsMyIp::deadline()` budget, if any
+#[derive(Clone, Copy)]
+struct Deadline(Option<Instant>);
+
+impl Deadline {
+    /// Time left, at least 1ms since a zero timeout is an error
+    /// for sockets
+    fn left(&self) -> Option<Duration> {
+        self.0.map(|deadline| {
+            let now = Instant::now();
+            if deadline > now {
+                max(deadline - now, Duration::from_millis(1))
+            } else {
+                Duration::from_millis(1)
+            }
+        })
+    }
+
+    fn expired(&self) -> bool {
+        match self.0 {
+            Some(deadline) => Instant::now() >= deadline,
+            None => false,
+        }
+    }
+
+    /// `timeout` cut down to the time left
+    fn cap(&self, timeout: Duration) -> Duration {
+        self.left().map_or(timeout, |left| min(timeout, left))
+    }
+
+    /// Same as `cap()`, None is no timeout
+    fn cap_opt(&self, timeout: Option<Duration>) -> Option<Duration> {
+        match (timeout, self.left()) {
+            (Some(timeout), Some(left)) => Some(min(timeout, left)),
+            (timeout, None) => timeout,
+            (None, left) => left,
+        }
+    }
+}
+
+/// The addresses in `reports`, without duplicates
+fn unique_ips(reports: Vec<IpReport>) -> Vec<MyIp> {
+    let mut ips = Vec::new();
+    for report in reports {
+        if !ips.contains(&report.ip) {
+            ips.push(report.ip);
+        }
+    }
+    ips
+}
+
+/// Sources reported different public addresses of the same family,
+/// e.g. a VPN with split tunneling or a stale IGD answer
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Disagreement {
+    pub family: IpFamily,
+    /// Each address and the sources that reported it
+    pub addresses: Vec<(MyIp, Vec<Source>)>,
+}
+
+impl fmt::Display for Disagreement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "Sources disagree on the {:?} address", self.family));
+        for (idx, &(ref ip, ref sources)) in self.addresses.iter().enumerate() {
+            try!(write!(f, "{} {} from {:?}", if idx == 0 { ":" } else { "," }, ip, sources));
+        }
+        Ok(())
+    }
+}
+
+/// The disagreements in `reports`, e.g. from `find_detailed()`, at
+/// most one per family. Private and derived 6to4 addresses are left
+/// out.
+pub fn disagreements(reports: &[IpReport]) -> Vec<Disagreement> {
+    [IpFamily::V4, IpFamily::V6].iter()
+        .filter_map(|&family| {
+            let mut addresses: Vec<(MyIp, Vec<Source>)> = Vec::new();
+            for report in reports.iter().filter(|r| family.includes(&r.ip)
+                                                    && r.scope == IpScope::Public
+                                                    && r.source != Source::SixToFour) {
+                match addresses.iter().position(|&(ip, _)| ip == report.ip) {
+                    Some(idx) => addresses[idx].1.push(report.source.clone()),
+                    None => addresses.push((report.ip, vec![report.source.clone()])),
+                }
+            }
+            if addresses.len() > 1 {
+                Some(Disagreement {
+                    family: family,
+                    addresses: addresses,
+                })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// A provider in the registry
+struct Registered {
+    provider: Box<dyn Provider>,
+    priority: u32,
+    weight: u32,
+    // Overrides http_timeout
+    timeout: Option<Duration>,
+    stats: Mutex<ProviderStats>,
+}
+
+impl Registered {
+    fn new(provider: Box<dyn Provider>, priority: u32, weight: u32) -> Registered {
+        Registered {
+            priority: priority,
+            weight: weight,
+            timeout: None,
+            stats: Mutex::new(ProviderStats::new(provider.name())),
+            provider: provider,
+        }
+    }
+
+    fn latency(&self) -> Option<Duration> {
+        self.stats.lock().ok().and_then(|stats| stats.latency)
+    }
+
+    /// False while the circuit breaker is open
+    fn available(&self, now: Instant) -> bool {
+        match self.stats.lock() {
+            Ok(stats) => stats.skip_until.map(|until| now >= until).unwrap_or(true),
+            Err(_) => true,
+        }
+    }
+
+    fn record_success(&self, latency: Duration) {
+        if let Ok(mut stats) = self.stats.lock() {
+            stats.successes += 1;
+            stats.consecutive_failures = 0;
+            stats.latency = Some(latency);
+            stats.skip_until = None;
+        }
+    }
+
+    /// After `threshold` failures in a row skip the provider for `cooldown`
+    fn record_failure(&self, threshold: u32, cooldown: Duration) {
+        if let Ok(mut stats) = self.stats.lock() {
+            stats.failures += 1;
+            stats.consecutive_failures += 1;
+            if threshold > 0 && stats.consecutive_failures >= threshold {
+                info!("{} failed {} times, skipping it for {}s",
+                      stats.name, stats.consecutive_failures, cooldown.as_secs());
+                stats.skip_until = Some(Instant::now() + cooldown);
+            }
+        }
+    }
+}
+
+/// Sort providers by priority, highest first. Providers with the
+/// same priority are shuffled, a higher weight makes a provider
+/// more likely to come first.
+fn weighted_order<'a, R: Rng>(rng: &mut R, mut entries: Vec<&'a Registered>) -> Vec<&'a Registered> {
+    let mut ordered = Vec::with_capacity(entries.len());
+    while !entries.is_empty() {
+        let top = entries.iter().map(|e| e.priority).max().unwrap_or(0);
+        let total: u32 = entries.iter()
+                            .filter(|e| e.priority == top)
+                            .map(|e| e.weight)
+                            .sum();
+        let mut pick = if total > 0 { rng.gen_range(0, total) } else { 0 };
+        let mut idx = 0;
+        for (i, e) in entries.iter().enumerate().filter(|&(_, e)| e.priority == top) {
+            idx = i;
+            if pick < e.weight {
+                break;
+            }
+            pick -= e.weight;
+        }
+        ordered.push(entries.remove(idx));
+    }
+    ordered
+}
+
+/// Where an address in `IpReport` came from
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Source {
+    /// Teredo address on a local interface
+    Teredo,
+    /// Public IPv6 address on a local interface, see `local_ipv6()`
+    Local { temporary: bool },
+    #[cfg(feature = "http")]
+    /// Cloud instance metadata
+    Cloud(cloud::Cloud),
+    /// Internet Gateway Device
+    Igd,
+    /// Router status page
+    Router,
+    /// STUN server
+    Stun(String),
+    /// TURN server
+    Turn(String),
+    /// SIP server
+    Sip(String),
+    /// TCP service, as host:port
+    Tcp(String),
+    /// WebSocket service URL
+    WebSocket(String),
+    /// BitTorrent DHT nodes
+    Dht,
+    /// DNS provider name
+    Dns(String),
+    /// Registered provider name, e.g. one of the HTTP services
+    Provider(String),
+    /// 6to4 address derived from another report
+    SixToFour,
+}
+
+/// An address and the source that reported it, see `find_detailed()`
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct IpReport {
+    pub ip: MyIp,
+    /// `ip.scope()`, e.g. to tell a CGNAT address apart
+    pub scope: IpScope,
+    pub source: Source,
+    /// Time the source took to answer
+    pub latency: Duration,
+    /// When the answer arrived
+    pub timestamp: SystemTime,
+    /// The name from the PTR record of `ip`, see `rdns()`
+    pub hostname: Option<String>,
+}
+
+/// The external address for each family, see `find_dual_stack()`
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DualStackResult {
+    pub v4: Option<Ipv4Addr>,
+    pub v6: Option<Ipv6Addr>,
+}
+
 pub struct WhatsMyIp {
+    teredo: bool,
+    local_ipv6: Option<Ipv6Preference>,
+    report_6to4: bool,
+    public_only: bool,
+    canonical: bool,
+    #[cfg(feature = "http")]
+    cloud: bool,
+    #[cfg(feature = "http")]
+    cloud_timeout: Duration,
     igd: bool,
+    #[cfg(feature = "igd")]
+    igd_all: bool,
+    #[cfg(feature = "igd")]
+    igd_verify: bool,
+    #[cfg(feature = "igd")]
+    race_igd: bool,
+    deadline: Option<Duration>,
+    retries: u32,
+    backoff: Duration,
+    #[cfg(feature = "igd")]
+    igd_timeout: Duration,
+    #[cfg(feature = "http")]
+    router: Option<RouterScrape>,
+    stun: bool,
+    #[cfg(feature = "stun")]
+    stun_servers: Vec<String>,
+    stun_timeout: Option<Duration>,
+    #[cfg(feature = "stun")]
+    turn: Option<stun::TurnServer>,
+    sip: Option<String>,
+    tcp_providers: Vec<TcpTextProvider>,
+    tcp_timeout: Duration,
+    websocket_providers: Vec<WebSocketProvider>,
+    dht: bool,
+    dht_nodes: Vec<String>,
+    dht_timeout: Duration,
+    dns: bool,
+    #[cfg(feature = "dns")]
+    dns_providers: Vec<dns::DnsProvider>,
+    #[cfg(feature = "dns")]
+    dns_timeout: Option<Duration>,
+    #[cfg(feature = "dns")]
+    rdns: bool,
     fast: bool,
-    http: usize,
+    family: IpFamily,
+    consensus: usize,
+    http: Option<usize>,
+    concurrency: usize,
+    providers: Vec<Registered>,
+    excluded: Vec<String>,
+    strategy: Strategy,
+    // Seed for Strategy::RandomShuffle, None for a new one each call
+    seed: Option<u64>,
+    breaker_failures: u32,
+    breaker_cooldown: Duration,
+    // Next provider for Strategy::RoundRobin
+    round_robin: AtomicUsize,
+    only: Option<Vec<String>>,
     http_timeout: Option<Duration>,
+    http_connect_timeout: Option<Duration>,
+    http_read_timeout: Option<Duration>,
+    max_redirects: usize,
+    allow_downgrade: bool,
+    // Set by WhatsMyIpClient
+    #[cfg(feature = "http")]
+    http_pool: Option<HttpPool>,
+    proxy: Option<Proxy>,
+    proxy_from_env: bool,
+    leak_safe: bool,
+    bind_address: Option<IpAddr>,
+    bind_interface: Option<String>,
+    transport: IpFamily,
+    user_agent: Option<String>,
+    http_headers: Vec<(String, String)>,
+    hosts: Hosts,
+    cache_ttl: Option<Duration>,
+    // Addresses from the last find() and when we got them
+    cache: Mutex<Option<(Instant, Vec<MyIp>)>>,
 }
 
 impl WhatsMyIp {
     pub fn new() -> WhatsMyIp {
         WhatsMyIp {
+            teredo: false,
+            local_ipv6: None,
+            report_6to4: false,
+            public_only: true,
+            canonical: true,
+            #[cfg(feature = "http")]
+            cloud: false,
+            #[cfg(feature = "http")]
+            cloud_timeout: Duration::from_secs(1),
             igd: true,
+            #[cfg(feature = "igd")]
+            igd_all: false,
+            #[cfg(feature = "igd")]
+            igd_verify: false,
+            #[cfg(feature = "igd")]
+            race_igd: false,
+            deadline: None,
+            retries: 0,
+            backoff: Duration::from_millis(500),
+            #[cfg(feature = "igd")]
+            igd_timeout: Duration::from_secs(3),
+            #[cfg(feature = "http")]
+            router: None,
+            stun: true,
+            #[cfg(feature = "stun")]
+            stun_servers: STUN_SERVERS.iter().map(|s| s.to_string()).collect(),
+            stun_timeout: Some(Duration::from_secs(3)),
+            #[cfg(feature = "stun")]
+            turn: None,
+            sip: None,
+            tcp_providers: Vec::new(),
+            tcp_timeout: Duration::from_secs(3),
+            websocket_providers: Vec::new(),
+            dht: false,
+            dht_nodes: DHT_NODES.iter().map(|s| s.to_string()).collect(),
+            dht_timeout: Duration::from_secs(3),
+            dns: true,
+            #[cfg(feature = "dns")]
+            dns_providers: dns::DNS_PROVIDERS.to_vec(),
+            #[cfg(feature = "dns")]
+            dns_timeout: Some(Duration::from_secs(3)),
+            #[cfg(feature = "dns")]
+            rdns: false,
             fast: false,
-            http: HTTP_PROVIDERS.len(),
+            family: IpFamily::Both,
+            consensus: 1,
+            concurrency: 1,
+            http: None,
+            providers: provider::default_providers().into_iter()
+                            .map(|p| Registered::new(p, 0, 1))
+                            .collect(),
+            excluded: Vec::new(),
+            strategy: Strategy::RandomShuffle,
+            seed: None,
+            breaker_failures: 3,
+            breaker_cooldown: Duration::from_secs(300),
+            round_robin: AtomicUsize::new(0),
+            only: None,
             http_timeout: None,
+            http_connect_timeout: None,
+            http_read_timeout: None,
+            max_redirects: 5,
+            allow_downgrade: false,
+            #[cfg(feature = "http")]
+            http_pool: None,
+            proxy: None,
+            proxy_from_env: false,
+            leak_safe: false,
+            bind_address: None,
+            bind_interface: None,
+            transport: IpFamily::Both,
+            user_agent: None,
+            http_headers: Vec::new(),
+            hosts: Hosts::new(),
+            cache_ttl: None,
+            cache: Mutex::new(None),
         }
     }
 
-    /// Enable/Disable the use of the Internet Gateway Device 
+    /// Enable/Disable decoding the external IPv4 address from
+    /// Teredo addresses on the local interfaces
+    /// (defaults to **false**)
+    pub fn teredo(&mut self, enabled: bool) -> &mut Self {
+        self.teredo = enabled;
+        self
+    }
+
+    /// Report the public IPv6 addresses on the local interfaces, IPv6
+    /// has no NAT so these are the external ones. On Linux temporary
+    /// (privacy) addresses are told apart from stable ones, see
+    /// `Ipv6Preference` and `Source::Local`.
+    /// (defaults to **None**, disabled)
+    pub fn local_ipv6(&mut self, preference: Option<Ipv6Preference>) -> &mut Self {
+        self.local_ipv6 = preference;
+        self
+    }
+
+    /// Discard private, loopback, link-local and reserved addresses,
+    /// e.g. from an IGD behind another NAT, see `MyIp::is_public()`.
+    /// Each one is recorded as an error of its source. If false they
+    /// are reported, see `IpReport::scope`.
     /// (defaults to **true**)
-    pub fn igd(&mut self, enabled: bool) -> &mut Self {
-        self.igd = enabled;
+    pub fn public_only(&mut self, enabled: bool) -> &mut Self {
+        self.public_only = enabled;
         self
     }
 
-    /// If true, `find()` will return as soon as
-    /// it gets one IP address. If false it will try all available
-    /// methods before returning.
+    /// Report IPv4-mapped IPv6 answers (::ffff:a.b.c.d), which some
+    /// services send to IPv4 clients, as the IPv4 address
+    /// (defaults to **true**)
+    pub fn canonical(&mut self, enabled: bool) -> &mut Self {
+        self.canonical = enabled;
+        self
+    }
+
+    /// If true, also report the 6to4 (2002::/16) address
+    /// for each public IPv4 address found
     /// (defaults to **false**)
-    pub fn fast(&mut self, enabled: bool) -> &mut Self {
-        self.fast = enabled;
+    pub fn report_6to4(&mut self, enabled: bool) -> &mut Self {
+        self.report_6to4 = enabled;
         self
     }
 
-    /// Limit the number of HTTP requests we can make
-    /// (defaults to **None** i.e. no limit)
-    pub fn http_limit(&mut self, count: Option<usize>) -> &mut Self {
-        self.http = match count {
-            Some(num) => min(num, HTTP_PROVIDERS.len()),
-            None => HTTP_PROVIDERS.len(),
-        };
+    #[cfg(feature = "http")]
+    /// Enable/Disable the use of cloud instance metadata services
+    /// (AWS, GCE, Azure), only useful on cloud VMs
+    /// (defaults to **false**)
+    pub fn cloud(&mut self, enabled: bool) -> &mut Self {
+        self.cloud = enabled;
         self
     }
 
-    /// Enforce HTTP request timeout for HTTP services (per service)
-    pub fn http_timeout(&mut self, t: Option<Duration>) -> &mut Self {
-        self.http_timeout = t;
+    #[cfg(feature = "http")]
+    /// Timeout for each metadata request, including the connection
+    /// (defaults to **1 second**)
+    pub fn cloud_timeout(&mut self, t: Duration) -> &mut Self {
+        self.cloud_timeout = t;
         self
     }
 
-    /// Returns a list of IP addresses, with no repeated entries.
-    ///
-    /// IP addresses are determined from various sources,
-    /// in this order:
-    ///
-    /// 1. Internet Gateway Device protocol
-    /// 2. external HTTP services (see the source for a full list)
-    ///
-    /// In general you can expect this method to be slow.
-    /// even if `fast(true)`.
-    pub fn find(&self) -> Result<Vec<MyIp>, String> {
-        let mut results = Vec::new();
+    /// Enable/Disable the use of the Internet Gateway Device 
+    /// (defaults to **true**)
+    pub fn igd(&mut self, enabled: bool) -> &mut Self {
+        self.igd = enabled;
+        self
+    }
 
-        if let Some(ip) = igd_ip() {
-            results.push(ip);
-            if self.fast {
-                return Ok(results);
-            }
-        }
+    #[cfg(feature = "igd")]
+    /// If true, query every gateway that answers the IGD search
+    /// instead of just the first one, see also `search_gateways()`
+    /// (defaults to **false**)
+    pub fn igd_all(&mut self, enabled: bool) -> &mut Self {
+        self.igd_all = enabled;
+        self
+    }
 
-        if self.http > 0 {
-            // Shuffle HTTP_PROVIDERS just in case
-            let mut providers = Vec::new();
-            for p in HTTP_PROVIDERS {
-                providers.push(p.clone());
-            };
-            if let Ok(mut rng) = StdRng::new() {
-                rng.shuffle(&mut providers);
-            }
+    #[cfg(feature = "igd")]
+    /// If true, check the IGD address with `igd_self_test()` and
+    /// drop it if it does not route back to us (stale address or
+    /// double NAT). Ignored with `igd_all(true)`.
+    /// (defaults to **false**)
+    pub fn igd_verify(&mut self, enabled: bool) -> &mut Self {
+        self.igd_verify = enabled;
+        self
+    }
 
-            for idx in 0..self.http {
-                let &(url, fun) = providers[idx];
-                let ip = match fun(self, url) {
-                    Ok(ip) => ip,
-                    Err(err) => {
-                        info!("{} => {}", &url, err);
-                        continue;
-                    },
-                };
+    #[cfg(feature = "igd")]
+    /// If true, query IGD in the background while the other sources
+    /// run, instead of waiting for it first. With `fast(true)` we
+    /// return as soon as any source answers, on networks without
+    /// UPnP this avoids waiting for the IGD timeout.
+    /// (defaults to **false**)
+    pub fn race_igd(&mut self, enabled: bool) -> &mut Self {
+        self.race_igd = enabled;
+        self
+    }
 
-                if !results.contains(&ip) {
-                    results.push(ip);
-                }
-                if self.fast {
-                    return Ok(results);
-                }
-            }
-        }
+    #[cfg(feature = "igd")]
+    /// Bound the time spent searching for the IGD gateway, with
+    /// `igd_all(true)` this is the window to collect answers from gateways
+    /// (defaults to **3 seconds**)
+    pub fn igd_timeout(&mut self, t: Duration) -> &mut Self {
+        self.igd_timeout = t;
+        self
+    }
 
-        if results.is_empty() {
-            Err("Unable to find any IP address".to_owned())
-        } else {
-            Ok(results)
-        }
+    #[cfg(feature = "http")]
+    /// Read the WAN address from the router status page
+    /// (defaults to **None**)
+    pub fn router_scrape(&mut self, router: &RouterScrape) -> &mut Self {
+        self.router = Some(router.clone());
+        self
     }
-}
 
-/// Returns the first IP address we can find
-pub fn whatsmyip() -> Result<MyIp, String> {
-    let mut addrs = try!(WhatsMyIp::new()
-                        .fast(true)
-                        .find());
-    addrs.pop()
-        .ok_or("Unable to find any IP address".to_owned())
-}
+    /// Enable/Disable the use of STUN servers
+    /// (defaults to **true**)
+    pub fn stun(&mut self, enabled: bool) -> &mut Self {
+        self.stun = enabled;
+        self
+    }
 
-#[test]
-fn test_http_providers() {
-    let w = WhatsMyIp::new();
-    for &(url, f) in HTTP_PROVIDERS {
-        assert!(f(&w, url).is_ok());
+    #[cfg(feature = "stun")]
+    /// Set the list of STUN servers (host:port) to query, in order
+    /// (defaults to `STUN_SERVERS`)
+    pub fn stun_servers(&mut self, servers: &[&str]) -> &mut Self {
+        self.stun_servers = servers.iter().map(|s| s.to_string()).collect();
+        self
     }
-}
 
-#[ignore]
-#[test]
-fn test_igd() {
-    assert!(igd_ip().is_some())
+    /// Timeout for each STUN request
+    /// (defaults to **3 seconds**)
+    pub fn stun_timeout(&mut self, t: Option<Duration>) -> &mut Self {
+        self.stun_timeout = t;
+        self
+    }
+
+    #[cfg(feature = "stun")]
+    /// Get the reflexive address from a TURN allocation in `server` (host:port)
+    /// using long-term credentials, useful when only the organization's TURN
+    /// relay is reachable. Uses the STUN timeout.
+    /// (defaults to **None**)
+    pub fn turn_server(&mut self, server: &str, username: &str, password: &str) -> &mut Self {
+        self.turn = Some(stun::TurnServer {
+            server: server.to_owned(),
+            username: username.to_owned(),
+            password: password.to_owned(),
+        });
+        self
+    }
+
+    /// Send a SIP OPTIONS request with `rport` to `server` (host:port)
+    /// and read the address from the Via header of the response, useful
+    /// where SIP is allowed but everything else is filtered.
+    /// Uses the STUN timeout.
+    /// (defaults to **None**)
+    pub fn sip_server(&mut self, server: &str) -> &mut Self {
+        self.sip = Some(server.to_owned());
+        self
+    }
+
+    /// Add a service that writes the address on a plain TCP connection
+    pub fn tcp_provider(&mut self, provider: &TcpTextProvider) -> &mut Self {
+        self.tcp_providers.push(provider.clone());
+        self
+    }
+
+    /// Add a WebSocket service (ws:// only) that sends the
+    /// address as its first message
+    pub fn websocket_provider(&mut self, provider: &WebSocketProvider) -> &mut Self {
+        self.websocket_providers.push(provider.clone());
+        self
+    }
+
+    /// Connect and read timeout for TCP and WebSocket services
+    /// (defaults to **3 seconds**)
+    pub fn tcp_timeout(&mut self, t: Duration) -> &mut Self {
+        self.tcp_timeout = t;
+        self
+    }
+
+    /// Enable/Disable asking BitTorrent DHT nodes, useful when
+    /// HTTP services are blocked
+    /// (defaults to **false**)
+    pub fn dht(&mut self, enabled: bool) -> &mut Self {
+        self.dht = enabled;
+        self
+    }
+
+    /// Set the list of DHT nodes (host:port) to ping
+    /// (defaults to `DHT_NODES`)
+    pub fn dht_nodes(&mut self, nodes: &[&str]) -> &mut Self {
+        self.dht_nodes = nodes.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    /// How long to wait for DHT replies
+    /// (defaults to **3 seconds**)
+    pub fn dht_timeout(&mut self, t: Duration) -> &mut Self {
+        self.dht_timeout = t;
+        self
+    }
+
+    /// Enable/Disable the use of DNS resolvers
+    /// (defaults to **true**)
+    pub fn dns(&mut self, enabled: bool) -> &mut Self {
+        self.dns = enabled;
+        self
+    }
+
+    #[cfg(feature = "dns")]
+    /// Set the list of DNS queries to use, in order. Use the entries
+    /// in `dns::DOH_PROVIDERS` to send them over HTTPS instead of UDP.
+    /// (defaults to `dns::DNS_PROVIDERS`)
+    pub fn dns_providers(&mut self, providers: &[dns::DnsProvider]) -> &mut Self {
+        self.dns_providers = providers.to_vec();
+        self
+    }
+
+    #[cfg(feature = "dns")]
+    /// Timeout for each DNS query
+    /// (defaults to **3 seconds**)
+    pub fn dns_timeout(&mut self, t: Option<Duration>) -> &mut Self {
+        self.dns_timeout = t;
+        self
+    }
+
+    #[cfg(feature = "dns")]
+    /// Look up the PTR record of each address, for the `hostname`
+    /// in `find_detailed()`. The name often tells the ISP or hosting
+    /// provider.
+    /// (defaults to **false**)
+    pub fn rdns(&mut self, enabled: bool) -> &mut Self {
+        self.rdns = enabled;
+        self
+    }
+
+    /// If true, `find()` will return as soon as
+    /// it gets one IP address. If false it will try all available
+    /// methods before returning.
+    /// (defaults to **false**)
+    pub fn fast(&mut self, enabled: bool) -> &mut Self {
+        self.fast = enabled;
+        self
+    }
+
+    /// Only look for addresses of this family, providers that
+    /// can only report the other family are skipped
+    /// (defaults to **Both**)
+    pub fn family(&mut self, family: IpFamily) -> &mut Self {
+        self.family = family;
+        self
+    }
+
+    /// Only return addresses reported by at least `n` sources, so a
+    /// single wrong or compromised source can not fool us. Addresses
+    /// of each family need their own `n` sources. With `fast(true)`
+    /// we stop as soon as one address has `n` sources.
+    /// (defaults to **1**)
+    pub fn consensus(&mut self, n: usize) -> &mut Self {
+        self.consensus = n;
+        self
+    }
+
+    /// Limit the number of providers we query, the default
+    /// providers are HTTP services
+    /// (defaults to **None** i.e. no limit)
+    pub fn http_limit(&mut self, count: Option<usize>) -> &mut Self {
+        self.http = count;
+        self
+    }
+
+    /// Query up to `n` providers at once, each in its own thread.
+    /// The next batch starts when all answers are in, with `fast(true)`
+    /// we stop after the first batch with an answer.
+    /// (defaults to **1**)
+    pub fn concurrency(&mut self, n: usize) -> &mut Self {
+        self.concurrency = max(n, 1);
+        self
+    }
+
+    /// Bound the whole search, once the deadline expires we return
+    /// the addresses found so far. Timeouts of the remaining queries
+    /// are cut down to fit.
+    /// (defaults to **None** i.e. no deadline)
+    pub fn deadline(&mut self, d: Option<Duration>) -> &mut Self {
+        self.deadline = d;
+        self
+    }
+
+    /// Load the settings and the provider list from a JSON file,
+    /// e.g.
+    ///
+    /// ```json
+    /// {
+    ///     "stun": false,
+    ///     "http_timeout": 5,
+    ///     "strategy": "fixed",
+    ///     "providers": [
+    ///         {"type": "text", "url": "https://ip.example.com/", "priority": 10,
+    ///          "pin": "sha256//AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="},
+    ///         {"type": "json", "name": "ipinfo", "url": "https://ipinfo.io/json",
+    ///          "field": "ip", "timeout": 2},
+    ///         {"type": "header", "url": "https://example.com/", "header": "X-Client-IP"},
+    ///         {"type": "regex", "url": "http://192.168.1.1/", "pattern": "WAN ([0-9.]+)"},
+    ///         {"type": "dns", "name": "opendns", "server": "208.67.222.222:53",
+    ///          "query": "myip.opendns.com", "qtype": "A"},
+    ///         {"type": "stun", "server": "stun.l.google.com:19302"}
+    ///     ]
+    /// }
+    /// ```
+    ///
+    /// The providers replace the default ones and are tried in
+    /// order of priority. `pin` is a certificate fingerprint in hex or
+    /// a public key hash, see `Pin`. Timeouts are in seconds, the strategy is one
+    /// of random, fixed, round-robin or latency. `igd`, `stun`, `dns`,
+    /// `fast`, `http_limit`, `http_connect_timeout`, `http_read_timeout`
+    /// and `max_redirects` set the options of the same name.
+    pub fn from_config<P: AsRef<Path>>(path: P) -> Result<WhatsMyIp, Error> {
+        let mut s = String::new();
+        try!(File::open(path.as_ref())
+                .and_then(|mut f| f.read_to_string(&mut s))
+                .map_err(Error::from));
+        config::from_str(&s)
+    }
+
+    /// Only use `providers`, tried in order, with IGD, STUN and DNS
+    /// disabled. Meant for tests with `MockProvider`, the addresses
+    /// still have to be public unless `public_only(false)`.
+    pub fn with_providers(providers: Vec<Box<dyn Provider>>) -> WhatsMyIp {
+        let mut opts = WhatsMyIp::new();
+        opts.igd(false)
+            .stun(false)
+            .dns(false)
+            .clear_providers()
+            .strategy(Strategy::FixedOrder);
+        for provider in providers {
+            opts.provider(provider);
+        }
+        opts
+    }
+
+    /// Remove all providers from the registry, including the defaults
+    pub fn clear_providers(&mut self) -> &mut Self {
+        self.providers.clear();
+        self
+    }
+
+    /// Add a provider to the registry, see `provider::Provider`
+    pub fn provider(&mut self, provider: Box<dyn Provider>) -> &mut Self {
+        self.weighted_provider(provider, 0, 1)
+    }
+
+    /// Add a provider with a priority and a weight. Providers with
+    /// a higher priority are tried first, among providers with the same
+    /// priority a higher weight makes it more likely to be tried earlier.
+    /// `provider()` uses priority **0** and weight **1**.
+    pub fn weighted_provider(&mut self, provider: Box<dyn Provider>,
+                             priority: u32, weight: u32) -> &mut Self {
+        self.providers.push(Registered::new(provider, priority, weight));
+        self
+    }
+
+    /// Set the timeout for the provider with this name, if
+    /// None the provider uses `http_timeout()`
+    pub fn provider_timeout(&mut self, name: &str, t: Option<Duration>) -> &mut Self {
+        for entry in self.providers.iter_mut().filter(|e| e.provider.name() == name) {
+            entry.timeout = t;
+        }
+        self
+    }
+
+    /// How to order providers with the same priority
+    /// (defaults to **RandomShuffle**)
+    pub fn strategy(&mut self, strategy: Strategy) -> &mut Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Shuffle providers with the same priority, or try them in the
+    /// order they were added, i.e. `Strategy::RandomShuffle` or
+    /// `Strategy::FixedOrder`
+    /// (defaults to **true**)
+    pub fn shuffle(&mut self, enabled: bool) -> &mut Self {
+        self.strategy(if enabled { Strategy::RandomShuffle } else { Strategy::FixedOrder })
+    }
+
+    /// Seed for the shuffle, each call to `find()` then tries the
+    /// providers in the same order. Without a seed a new one is
+    /// picked for each call, it is logged at debug level to
+    /// reproduce that order.
+    /// (defaults to **None**)
+    pub fn seed(&mut self, seed: Option<u64>) -> &mut Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Order the registered providers according to the strategy,
+    /// providers with a higher priority always come first
+    fn order_providers<'a>(&self, mut entries: Vec<&'a Registered>) -> Vec<&'a Registered> {
+        let mut entries = match self.strategy {
+            Strategy::RandomShuffle => {
+                let seed = self.seed.unwrap_or_else(|| thread_rng().gen());
+                debug!("Shuffling providers with seed {}", seed);
+                return weighted_order(&mut Isaac64Rng::from_seed(&[seed][..]), entries);
+            },
+            Strategy::FixedOrder => entries,
+            Strategy::RoundRobin => {
+                let start = if entries.is_empty() {
+                    0
+                } else {
+                    self.round_robin.fetch_add(1, Ordering::SeqCst) % entries.len()
+                };
+                let mut rotated = entries.split_off(start);
+                rotated.extend(entries);
+                rotated
+            },
+            Strategy::LowestLatencyFirst => {
+                // Providers we have not measured yet go first, Option
+                // sorts None before Some
+                entries.sort_by_key(|e| e.latency());
+                entries
+            },
+        };
+        // Stable sort, keeps the order within each priority
+        entries.sort_by_key(|entry| Reverse(entry.priority));
+        entries
+    }
+
+    /// Keep the addresses from `find()` for `ttl`, calls within the
+    /// TTL return them without any network traffic. Use `refresh()`
+    /// to search again before that.
+    /// (defaults to **None** i.e. no cache)
+    pub fn cache_ttl(&mut self, ttl: Option<Duration>) -> &mut Self {
+        self.cache_ttl = ttl;
+        self
+    }
+
+    /// Retry a provider up to `n` times on transient errors, i.e.
+    /// timeouts, connection errors and HTTP 5xx, before moving on
+    /// to the next one
+    /// (defaults to **0**)
+    pub fn retries(&mut self, n: u32) -> &mut Self {
+        self.retries = n;
+        self
+    }
+
+    /// Wait before the first retry, doubled for each retry after that
+    /// (defaults to **500ms**)
+    pub fn backoff(&mut self, initial: Duration) -> &mut Self {
+        self.backoff = initial;
+        self
+    }
+
+    /// Skip providers that failed `failures` times in a row for
+    /// `cooldown`, then try them again. Only useful when calling
+    /// `find()` repeatedly, 0 failures disables it.
+    /// (defaults to **3** failures and **5 minutes**)
+    pub fn circuit_breaker(&mut self, failures: u32, cooldown: Duration) -> &mut Self {
+        self.breaker_failures = failures;
+        self.breaker_cooldown = cooldown;
+        self
+    }
+
+    /// Success/failure counts and latency of the registered
+    /// providers across calls to `find()`
+    pub fn provider_stats(&self) -> Vec<ProviderStats> {
+        self.providers.iter()
+            .filter_map(|e| e.stats.lock().ok().map(|stats| stats.clone()))
+            .collect()
+    }
+
+    /// Change the priority and weight of a registered provider,
+    /// see `weighted_provider()`
+    pub fn provider_priority(&mut self, name: &str, priority: u32, weight: u32) -> &mut Self {
+        for entry in self.providers.iter_mut().filter(|e| e.provider.name() == name) {
+            entry.priority = priority;
+            entry.weight = weight;
+        }
+        self
+    }
+
+    #[cfg(feature = "http")]
+    /// Add a plain text HTTP endpoint (e.g. your own `echo $REMOTE_ADDR`),
+    /// the scheme defaults to http:// if missing. These have priority
+    /// **10** so they are tried before the default providers.
+    pub fn add_http_provider(&mut self, url: &str) -> &mut Self {
+        let url = if url.contains("://") {
+            url.to_owned()
+        } else {
+            format!("http://{}", url)
+        };
+        self.weighted_provider(Box::new(HttpProvider::text(&url, &url)), 10, 1)
+    }
+
+    #[cfg(feature = "http")]
+    /// Same as `add_http_provider()` but the scheme defaults to https://
+    pub fn add_https_provider(&mut self, url: &str) -> &mut Self {
+        let url = if url.contains("://") {
+            url.to_owned()
+        } else {
+            format!("https://{}", url)
+        };
+        self.weighted_provider(Box::new(HttpProvider::text(&url, &url)), 10, 1)
+    }
+
+    /// Names of the registered providers and DNS providers,
+    /// for use with `exclude_provider()` and `only_providers()`
+    pub fn provider_names(&self) -> Vec<&str> {
+        #[cfg_attr(not(feature = "dns"), allow(unused_mut))]
+        let mut names: Vec<&str> = self.providers.iter()
+            .map(|p| p.provider.name())
+            .collect();
+        #[cfg(feature = "dns")]
+        names.extend(self.dns_providers.iter().map(|p| p.name));
+        names
+    }
+
+    /// Do not use the provider (or DNS provider) with this name,
+    /// e.g. when it is blocked in your network
+    pub fn exclude_provider(&mut self, name: &str) -> &mut Self {
+        self.excluded.push(name.to_owned());
+        self
+    }
+
+    /// Only use the providers (and DNS providers) with these names
+    /// (defaults to **None** i.e. all providers)
+    pub fn only_providers(&mut self, names: &[&str]) -> &mut Self {
+        self.only = Some(names.iter().map(|s| s.to_string()).collect());
+        self
+    }
+
+    fn provider_enabled(&self, name: &str) -> bool {
+        if self.excluded.iter().any(|n| n == name) {
+            return false;
+        }
+        match self.only {
+            Some(ref names) => names.iter().any(|n| n == name),
+            None => true,
+        }
+    }
+
+    #[cfg(feature = "http")]
+    /// Add an HTTP service that returns the address in the
+    /// response header `header` (e.g. X-Client-IP)
+    pub fn http_header_provider(&mut self, url: &str, header: &str) -> &mut Self {
+        self.provider(Box::new(HttpProvider::header(url, url, header)))
+    }
+
+    #[cfg(feature = "http")]
+    /// Add an HTTP page (e.g. a router status page) and extract the
+    /// address from the body using a regular expression, see `IPV4_PATTERN`
+    pub fn http_regex_provider(&mut self, url: &str, pattern: &str) -> &mut Self {
+        self.provider(Box::new(HttpProvider::regex(url, url, pattern)))
+    }
+
+    /// Enforce HTTP request timeout for HTTP services (per service),
+    /// from connecting to the end of the response, see also
+    /// `provider_timeout()`
+    pub fn http_timeout(&mut self, t: Option<Duration>) -> &mut Self {
+        self.http_timeout = t;
+        self
+    }
+
+    /// Timeout to connect to HTTP services, keep it short to skip
+    /// providers that are down. Capped by `http_timeout()`.
+    /// (defaults to **None**)
+    pub fn http_connect_timeout(&mut self, t: Option<Duration>) -> &mut Self {
+        self.http_connect_timeout = t;
+        self
+    }
+
+    /// Timeout for each read from HTTP services, i.e. how long the
+    /// service may stay silent. Capped by `http_timeout()`.
+    /// (defaults to **None**)
+    pub fn http_read_timeout(&mut self, t: Option<Duration>) -> &mut Self {
+        self.http_read_timeout = t;
+        self
+    }
+
+    /// Most redirects to follow for each request to an HTTP service,
+    /// 0 to follow none. Going over is an `Error::Redirect`.
+    /// (defaults to **5**)
+    pub fn max_redirects(&mut self, n: usize) -> &mut Self {
+        self.max_redirects = n;
+        self
+    }
+
+    /// Follow redirects from HTTPS to plain HTTP, never done for
+    /// providers with pinned certificates
+    /// (defaults to **false**)
+    pub fn allow_downgrade(&mut self, allow: bool) -> &mut Self {
+        self.allow_downgrade = allow;
+        self
+    }
+
+    /// User-Agent for requests to HTTP services
+    /// (defaults to **None**, hyper's own)
+    pub fn user_agent(&mut self, agent: &str) -> &mut Self {
+        self.user_agent = Some(agent.to_owned());
+        self
+    }
+
+    /// Add a header to every request to HTTP services, e.g. an API
+    /// key for a private service. Setting a header twice replaces it.
+    pub fn http_header(&mut self, name: &str, value: &str) -> &mut Self {
+        self.http_headers.retain(|&(ref n, _)| !n.eq_ignore_ascii_case(name));
+        self.http_headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Resolve the host names of HTTP services with `resolver`
+    /// instead of the system resolver
+    /// (defaults to **None**)
+    pub fn resolver<R: Resolver + 'static>(&mut self, resolver: Option<R>) -> &mut Self {
+        self.hosts.set_resolver(resolver.map(|r| Arc::new(r) as Arc<dyn Resolver>));
+        self
+    }
+
+    /// Connect to `ip` for HTTP services on `host`, like an entry
+    /// in /etc/hosts. Can be called more than once for each host.
+    pub fn resolve(&mut self, host: &str, ip: IpAddr) -> &mut Self {
+        self.hosts.insert(host, ip);
+        self
+    }
+
+    /// Send the requests to HTTP services through a proxy
+    /// (defaults to **None**)
+    pub fn proxy(&mut self, proxy: Option<Proxy>) -> &mut Self {
+        self.proxy = proxy;
+        self
+    }
+
+    /// Without a `proxy()`, use the one in `HTTP_PROXY` or `HTTPS_PROXY`
+    /// (defaults to **false**)
+    pub fn proxy_from_env(&mut self, enabled: bool) -> &mut Self {
+        self.proxy_from_env = enabled;
+        self
+    }
+
+    /// When a proxy is set, skip the sources that do not go through
+    /// it (IGD, STUN, TURN, SIP, TCP, WebSocket, DHT and DNS) so the
+    /// address behind the proxy is never revealed. Custom providers
+    /// are still queried.
+    /// (defaults to **false**)
+    pub fn leak_safe(&mut self, enabled: bool) -> &mut Self {
+        self.leak_safe = enabled;
+        self
+    }
+
+    /// True if HTTP requests go through a proxy
+    fn proxied(&self) -> bool {
+        self.proxy.is_some() ||
+            (self.proxy_from_env && (Proxy::from_env("http").is_some() ||
+                                     Proxy::from_env("https").is_some()))
+    }
+
+    /// Send HTTP requests, DNS queries, STUN, SIP and DHT messages
+    /// and the IGD search from this local address, to find the
+    /// external address of one path on a multi-homed host. Sources
+    /// of the other family are skipped.
+    /// (defaults to **None**)
+    pub fn bind_address(&mut self, ip: Option<IpAddr>) -> &mut Self {
+        self.bind_address = ip;
+        self
+    }
+
+    /// Same as `bind_address()` with the addresses of an interface,
+    /// e.g. `eth1` or `tun0`, looked up on each search
+    /// (defaults to **None**)
+    pub fn bind_interface(&mut self, name: Option<&str>) -> &mut Self {
+        self.bind_interface = name.map(str::to_owned);
+        self
+    }
+
+    /// Connect to HTTP, DNS, STUN, SIP and DHT sources only over IPv4
+    /// or IPv6, whatever the family of the addresses we look for.
+    /// Providers that only answer over the other family are skipped.
+    /// (defaults to **Both**, the resolver picks)
+    pub fn transport(&mut self, family: IpFamily) -> &mut Self {
+        self.transport = family;
+        self
+    }
+
+    /// Where to bind outgoing sockets
+    fn local_bind(&self) -> Result<Bind, Error> {
+        let bind = match (self.bind_address, &self.bind_interface) {
+            (Some(ip), _) => Bind::address(ip),
+            (None, &Some(ref name)) => try!(Bind::interface(name)),
+            (None, &None) => Bind::Any,
+        };
+        match bind.only(self.transport) {
+            Bind::Addrs(None, None) =>
+                Err(Error::Protocol(format!("No local address for {:?} transport", self.transport))),
+            bind => Ok(bind),
+        }
+    }
+
+    #[cfg(feature = "dns")]
+    /// Whether to query `provider`, by name, the family of the answer
+    /// and the family of the resolver address
+    fn dns_enabled(&self, provider: &dns::DnsProvider) -> bool {
+        let family = match provider.qtype {
+            dns::TYPE_A => IpFamily::V4,
+            dns::TYPE_AAAA => IpFamily::V6,
+            _ => IpFamily::Both,
+        };
+        self.provider_enabled(provider.name) && self.family.overlaps(family)
+            && self.transport.overlaps(provider.transport())
+    }
+
+    #[cfg(feature = "http")]
+    /// The proxy for requests to `url`
+    fn proxy_for(&self, url: &str) -> Option<Proxy> {
+        if self.proxy.is_some() || !self.proxy_from_env {
+            return self.proxy.clone();
+        }
+        Proxy::from_env(if url.starts_with("https:") { "https" } else { "http" })
+    }
+
+    /// Returns a list of IP addresses, with no repeated entries.
+    ///
+    /// IP addresses are determined from various sources,
+    /// in this order:
+    ///
+    /// 1. Teredo addresses on local interfaces, if enabled
+    /// 2. Cloud instance metadata, if enabled
+    /// 3. Internet Gateway Device protocol
+    /// 4. Router status page, if configured
+    /// 5. STUN servers
+    /// 6. TURN server, if configured
+    /// 7. SIP server, if configured
+    /// 8. TCP and WebSocket services, if configured
+    /// 9. BitTorrent DHT nodes, if enabled
+    /// 10. DNS resolvers
+    /// 11. registered providers, by default external HTTP services
+    ///     (see `provider::http_providers()`)
+    ///
+    /// With `report_6to4(true)` the 6to4 addresses are appended last.
+    ///
+    /// In general you can expect this method to be slow.
+    /// even if `fast(true)`.
+    pub fn find(&self) -> Result<Vec<MyIp>, Error> {
+        if let Some(ttl) = self.cache_ttl {
+            let cache = self.cache.lock().unwrap();
+            if let Some((at, ref ips)) = *cache {
+                if at.elapsed() < ttl {
+                    debug!("Cached => {:?}", ips);
+                    return Ok(ips.clone());
+                }
+            }
+        }
+        self.refresh()
+    }
+
+    /// Same as `find()` but ignores the cache, the addresses
+    /// found are cached for the next calls
+    pub fn refresh(&self) -> Result<Vec<MyIp>, Error> {
+        let found = self.collect(self.igd, false);
+        let ips = try!(self.finish(found).map(unique_ips));
+        if self.cache_ttl.is_some() {
+            *self.cache.lock().unwrap() = Some((Instant::now(), ips.clone()));
+        }
+        Ok(ips)
+    }
+
+    /// Same as `find()`, but returns a future that can be awaited
+    /// from tokio or any other executor. Needs the `async` feature.
+    ///
+    /// ```edition2018,no_run
+    /// # async fn example() {
+    /// let mut opts = whatsmyip::WhatsMyIp::new();
+    /// opts.fast(true);
+    /// let addrs = opts.find_async().await.unwrap();
+    /// # }
+    /// ```
+    #[cfg(feature = "async")]
+    pub fn find_async(self) -> FindFuture {
+        future::spawn(self)
+    }
+
+    /// Query the sources in a background thread and yield each
+    /// answer as soon as it arrives, e.g. to show the first address
+    /// while waiting for confirmations. Every answer is yielded,
+    /// consensus and 6to4 are not applied.
+    ///
+    /// ```no_run
+    /// use whatsmyip::WhatsMyIp;
+    /// for report in WhatsMyIp::new().find_stream() {
+    ///     println!("{} from {:?}", report.ip, report.source);
+    /// }
+    /// ```
+    pub fn find_stream(self) -> FindStream {
+        stream::spawn(self)
+    }
+
+    /// Same as `find()`, but returns every answer with its
+    /// source, latency and timestamp. An address reported by
+    /// several sources shows up once for each of them.
+    /// Use `disagreements()` to check whether the sources agree.
+    pub fn find_detailed(&self) -> Result<Vec<IpReport>, Error> {
+        let found = self.collect(self.igd, false);
+        self.finish(found)
+    }
+
+    /// Keep querying sources until we have one address of each
+    /// family, or we run out of sources
+    pub fn find_dual_stack(&self) -> Result<DualStackResult, Error> {
+        let found = self.collect(self.igd, true);
+        let mut res = DualStackResult {
+            v4: None,
+            v6: None,
+        };
+        for ip in unique_ips(try!(self.finish(found))) {
+            match ip {
+                MyIp::V4(ip) => if res.v4.is_none() {
+                    res.v4 = Some(ip);
+                },
+                MyIp::V6(ip) => if res.v6.is_none() {
+                    res.v6 = Some(ip);
+                },
+            }
+        }
+        Ok(res)
+    }
+
+    /// Apply consensus and add the derived addresses
+    fn finish(&self, mut found: Found) -> Result<Vec<IpReport>, Error> {
+        let errors = found.errors.split_off(0);
+        let mut results = if self.consensus > 1 {
+            if found.distinct().len() > 1 {
+                info!("Sources disagree: {}", found);
+            }
+            let confirmed: Vec<bool> = found.reports.iter()
+                                        .map(|r| found.count(&r.ip) >= self.consensus)
+                                        .collect();
+            let reports: Vec<IpReport> = found.reports.into_iter()
+                                        .zip(confirmed)
+                                        .filter(|&(_, ok)| ok)
+                                        .map(|(r, _)| r)
+                                        .collect();
+            if reports.is_empty() {
+                return Err(Error::Parse(format!("No address confirmed by {} sources", self.consensus)));
+            }
+            reports
+        } else {
+            found.reports
+        };
+
+        if self.report_6to4 {
+            let derived: Vec<IpReport> = results.iter()
+                                        .filter_map(|r| r.ip.to_6to4().map(|ip| IpReport {
+                                            ip: MyIp::V6(ip),
+                                            scope: IpScope::Public,
+                                            source: Source::SixToFour,
+                                            latency: r.latency,
+                                            timestamp: r.timestamp,
+                                            hostname: None,
+                                        }))
+                                        .filter(|r| self.family.includes(&r.ip))
+                                        .collect();
+            for report in derived {
+                if !results.iter().any(|r| r.ip == report.ip) {
+                    results.push(report);
+                }
+            }
+        }
+
+        for disagreement in disagreements(&results) {
+            info!("{}", disagreement);
+        }
+
+        #[cfg(feature = "dns")]
+        if self.rdns {
+            for i in 0..results.len() {
+                let ip = results[i].ip;
+                // One lookup per address
+                let hostname = match results[..i].iter().find(|r| r.ip == ip) {
+                    Some(r) => r.hostname.clone(),
+                    None => match dns::lookup_ptr(dns::DEFAULT_RESOLVER, &ip, self.dns_timeout) {
+                        Ok(hostname) => hostname,
+                        Err(err) => {
+                            info!("PTR {} => {}", ip, err);
+                            None
+                        },
+                    },
+                };
+                results[i].hostname = hostname;
+            }
+        }
+
+        if results.is_empty() && errors.is_empty() {
+            Err(Error::NoSources)
+        } else if results.is_empty() {
+            match error::captive_portal(&errors) {
+                Some(portal) => {
+                    info!("{}", Error::AllProvidersFailed(errors));
+                    Err(portal)
+                },
+                None => Err(Error::AllProvidersFailed(errors)),
+            }
+        } else {
+            Ok(results)
+        }
+    }
+
+    /// True if we can stop querying sources, with `dual` once
+    /// we have an address of each family
+    fn done(&self, results: &Found, dual: bool) -> bool {
+        let votes = max(self.consensus, 1);
+        if dual {
+            results.has(IpFamily::V4, votes) && results.has(IpFamily::V6, votes)
+        } else {
+            self.fast && results.votes() >= votes
+        }
+    }
+
+    /// Query the enabled sources, see `find()`. IGD is only
+    /// used if `igd` is true.
+    fn collect(&self, igd: bool, dual: bool) -> Found {
+        self.collect_into(self.found(), igd, dual)
+    }
+
+    /// No addresses yet, filtered by our settings
+    fn found(&self) -> Found {
+        let mut found = Found::new(self.family);
+        found.public_only = self.public_only;
+        found.canonical = self.canonical;
+        found
+    }
+
+    /// Same as `collect()`, adding to `results`
+    fn collect_into(&self, mut results: Found, igd: bool, dual: bool) -> Found {
+        // Sources that connect directly, skipped in leak safe mode
+        let direct = !(self.leak_safe && self.proxied());
+        if !direct {
+            info!("Using a proxy, only querying HTTP providers");
+        }
+        let bind = match self.local_bind() {
+            Ok(bind) => bind,
+            Err(err) => {
+                results.fail("Bind", err);
+                return results;
+            },
+        };
+        // No IGD search without a local IPv4 address
+        let igd_local = bind.ipv4();
+        #[cfg_attr(not(feature = "igd"), allow(unused_variables))]
+        let igd = igd && direct && igd_local.is_some();
+        let deadline = Deadline(self.deadline.map(|d| Instant::now() + d));
+        #[cfg(feature = "igd")]
+        let igd_rx = if igd && self.race_igd {
+            Some(self.spawn_igd(igd_local.unwrap()))
+        } else {
+            None
+        };
+        #[cfg(not(feature = "igd"))]
+        let igd_rx = None;
+
+        if self.teredo {
+            let start = Instant::now();
+            match local::teredo_ips() {
+                Ok(ips) => for ip in ips {
+                    results.add(ip, Source::Teredo, start);
+                },
+                Err(err) => results.fail("Local interfaces", err),
+            }
+            if self.check_done(&mut results, &igd_rx, deadline, dual) {
+                return results;
+            }
+        }
+
+        if let Some(preference) = self.local_ipv6 {
+            let start = Instant::now();
+            match local::public_ipv6(preference) {
+                Ok(ips) => for (ip, temporary) in ips {
+                    results.add(ip, Source::Local { temporary: temporary }, start);
+                },
+                Err(err) => results.fail("Local interfaces", err),
+            }
+            if self.check_done(&mut results, &igd_rx, deadline, dual) {
+                return results;
+            }
+        }
+
+        #[cfg(feature = "http")]
+        if self.cloud {
+            for &c in cloud::CLOUDS {
+                if deadline.expired() {
+                    break;
+                }
+                let start = Instant::now();
+                match cloud::cloud_ip(c, deadline.cap(self.cloud_timeout)) {
+                    Ok(ip) => {
+                        results.add(ip, Source::Cloud(c), start);
+                        // We can only be running in one cloud
+                        break;
+                    },
+                    Err(err) => results.fail(&format!("{:?} metadata", c), err),
+                }
+            }
+            if self.check_done(&mut results, &igd_rx, deadline, dual) {
+                return results;
+            }
+        }
+
+        #[cfg(feature = "igd")]
+        if igd && !self.race_igd {
+            igd_sources(&mut results, igd_local.unwrap(), self.igd_all, self.igd_verify,
+                        deadline.cap(self.igd_timeout), |r| self.done(r, dual));
+            if self.check_done(&mut results, &igd_rx, deadline, dual) {
+                return results;
+            }
+        }
+
+        #[cfg(feature = "http")]
+        if let Some(ref router) = self.router {
+            let start = Instant::now();
+            match router.fetch(deadline.cap_opt(self.http_timeout)) {
+                Ok(ip) => {
+                    results.add(ip, Source::Router, start);
+                    if self.check_done(&mut results, &igd_rx, deadline, dual) {
+                        return results;
+                    }
+                },
+                Err(err) => results.fail("Router status page", err),
+            }
+        }
+
+        #[cfg(feature = "stun")]
+        if self.stun && direct {
+            for server in &self.stun_servers {
+                if deadline.expired() {
+                    break;
+                }
+                let start = Instant::now();
+                match stun::stun_ip(server, bind, deadline.cap_opt(self.stun_timeout)) {
+                    Ok(ip) => {
+                        results.add(ip, Source::Stun(server.clone()), start);
+                        // One STUN answer is enough, servers
+                        // all see the same mapping
+                        break;
+                    },
+                    Err(err) => results.fail(server, err),
+                }
+            }
+            if self.check_done(&mut results, &igd_rx, deadline, dual) {
+                return results;
+            }
+        }
+
+        #[cfg(feature = "stun")]
+        if let Some(turn) = self.turn.as_ref().filter(|_| direct) {
+            let start = Instant::now();
+            match stun::turn_ip(turn, bind, deadline.cap_opt(self.stun_timeout)) {
+                Ok(ip) => {
+                    results.add(ip, Source::Turn(turn.server.clone()), start);
+                    if self.check_done(&mut results, &igd_rx, deadline, dual) {
+                        return results;
+                    }
+                },
+                Err(err) => results.fail(&turn.server, err),
+            }
+        }
+
+        if let Some(server) = self.sip.as_ref().filter(|_| direct) {
+            let start = Instant::now();
+            match sip::sip_ip(server, bind, deadline.cap_opt(self.stun_timeout)) {
+                Ok(ip) => {
+                    results.add(ip, Source::Sip(server.clone()), start);
+                    if self.check_done(&mut results, &igd_rx, deadline, dual) {
+                        return results;
+                    }
+                },
+                Err(err) => results.fail(server, err),
+            }
+        }
+
+        let tcp_providers = if direct { &self.tcp_providers[..] } else { &[] };
+        for provider in tcp_providers {
+            if deadline.expired() {
+                break;
+            }
+            let start = Instant::now();
+            let ip = match provider.fetch(deadline.cap(self.tcp_timeout)) {
+                Ok(ip) => ip,
+                Err(err) => {
+                    results.fail(&format!("{}:{}", provider.host, provider.port), err);
+                    continue;
+                },
+            };
+
+            results.add(ip, Source::Tcp(format!("{}:{}", provider.host, provider.port)), start);
+            if self.check_done(&mut results, &igd_rx, deadline, dual) {
+                return results;
+            }
+        }
+
+        let websocket_providers = if direct { &self.websocket_providers[..] } else { &[] };
+        for provider in websocket_providers {
+            if deadline.expired() {
+                break;
+            }
+            let start = Instant::now();
+            let ip = match provider.fetch(deadline.cap(self.tcp_timeout)) {
+                Ok(ip) => ip,
+                Err(err) => {
+                    results.fail(&provider.url, err);
+                    continue;
+                },
+            };
+
+            results.add(ip, Source::WebSocket(provider.url.clone()), start);
+            if self.check_done(&mut results, &igd_rx, deadline, dual) {
+                return results;
+            }
+        }
+
+        if self.dht && direct {
+            let start = Instant::now();
+            match dht::dht_ip(&self.dht_nodes, bind, deadline.cap(self.dht_timeout)) {
+                Ok(ip) => {
+                    results.add(ip, Source::Dht, start);
+                    if self.check_done(&mut results, &igd_rx, deadline, dual) {
+                        return results;
+                    }
+                },
+                Err(err) => results.fail("DHT", err),
+            }
+        }
+
+        #[cfg(feature = "dns")]
+        if self.dns && direct {
+            for provider in self.dns_providers.iter().filter(|p| self.dns_enabled(p)) {
+                if deadline.expired() {
+                    break;
+                }
+                let start = Instant::now();
+                let ip = match dns::dns_ip_bound(provider, bind, deadline.cap_opt(self.dns_timeout)) {
+                    Ok(ip) => ip,
+                    Err(err) => {
+                        results.fail(provider.name, err);
+                        continue;
+                    },
+                };
+
+                results.add(ip, Source::Dns(provider.name.to_owned()), start);
+                if self.check_done(&mut results, &igd_rx, deadline, dual) {
+                    return results;
+                }
+            }
+        }
+
+        let now = Instant::now();
+        let enabled = self.providers.iter()
+                        .filter(|e| self.provider_enabled(e.provider.name()))
+                        .filter(|e| e.available(now))
+                        .filter(|e| self.family.overlaps(e.provider.families()))
+                        .filter(|e| self.transport.overlaps(e.provider.families()))
+                        .collect();
+        let providers = self.order_providers(enabled);
+
+        let http = min(self.http.unwrap_or(providers.len()), providers.len());
+        for batch in providers[..http].chunks(self.concurrency) {
+            if deadline.expired() {
+                break;
+            }
+            let answers = if batch.len() == 1 {
+                vec![self.fetch_registered(batch[0], deadline)]
+            } else {
+                thread::scope(|scope| {
+                    let handles: Vec<_> = batch.iter()
+                        .map(|entry| scope.spawn(move || self.fetch_registered(entry, deadline)))
+                        .collect();
+                    handles.into_iter().map(|h| h.join().unwrap()).collect()
+                })
+            };
+
+            for (entry, (res, latency)) in batch.iter().zip(answers) {
+                let ips = match res {
+                    Ok(ips) => ips,
+                    Err(err) => {
+                        results.fail(entry.provider.name(), err);
+                        entry.record_failure(self.breaker_failures, self.breaker_cooldown);
+                        continue;
+                    },
+                };
+                entry.record_success(latency);
+
+                for ip in ips {
+                    results.add_timed(ip, Source::Provider(entry.provider.name().to_owned()), latency);
+                }
+            }
+            if self.check_done(&mut results, &igd_rx, deadline, dual) {
+                return results;
+            }
+        }
+
+        // Nothing else left, wait for IGD
+        if let Some(rx) = igd_rx {
+            let found = match deadline.left() {
+                Some(left) => rx.recv_timeout(left).ok(),
+                None => rx.recv().ok(),
+            };
+            if let Some(found) = found {
+                results.merge(found);
+            }
+        }
+        results
+    }
+
+    #[cfg(feature = "igd")]
+    /// Query IGD in a thread of its own, see `race_igd()`
+    fn spawn_igd(&self, local: Ipv4Addr) -> Receiver<Found> {
+        let (tx, rx) = channel();
+        let mut found = self.found();
+        let all = self.igd_all;
+        let verify = self.igd_verify;
+        let timeout = self.igd_timeout;
+        thread::spawn(move || {
+            igd_sources(&mut found, local, all, verify, timeout, |_| false);
+            // We may have returned already
+            let _ = tx.send(found);
+        });
+        rx
+    }
+
+    /// Add the IGD answer if it arrived, then check `done()`
+    fn check_done(&self, results: &mut Found, igd_rx: &Option<Receiver<Found>>,
+                  deadline: Deadline, dual: bool) -> bool {
+        if let Some(ref rx) = *igd_rx {
+            if let Ok(found) = rx.try_recv() {
+                results.merge(found);
+            }
+        }
+        if deadline.expired() {
+            info!("Deadline expired");
+            return true;
+        }
+        self.done(results, dual)
+    }
+
+    /// Query a registered provider, returns the answer and the
+    /// time it took
+    fn fetch_registered(&self, entry: &Registered, deadline: Deadline)
+                        -> (Result<Vec<MyIp>, Error>, Duration) {
+        let start = Instant::now();
+        let mut delay = self.backoff;
+        let mut attempt = 0;
+        loop {
+            let ctx = Context {
+                opts: self,
+                timeout: deadline.cap_opt(entry.timeout.or(self.http_timeout)),
+                pins: &[],
+            };
+            match entry.provider.fetch_all(&ctx) {
+                Err(ref err) if attempt < self.retries && err.is_transient()
+                                && !deadline.expired() => {
+                    info!("{} => {}, retrying", entry.provider.name(), err);
+                    thread::sleep(deadline.cap(delay));
+                    delay *= 2;
+                    attempt += 1;
+                },
+                res => return (res, start.elapsed()),
+            }
+        }
+    }
+
+    /// Query every registered provider once and return the results
+    /// sorted by latency, providers that failed go last. The results
+    /// are also recorded in `provider_stats()`, which feeds
+    /// `Strategy::LowestLatencyFirst`.
+    pub fn benchmark(&self) -> Vec<BenchmarkResult> {
+        let mut report: Vec<BenchmarkResult> = self.providers.iter()
+            .filter(|e| self.provider_enabled(e.provider.name()))
+            .map(|entry| {
+                let ctx = Context {
+                    opts: self,
+                    timeout: entry.timeout.or(self.http_timeout),
+                    pins: &[],
+                };
+                let start = Instant::now();
+                let result = entry.provider.fetch(&ctx);
+                let latency = start.elapsed();
+                match result {
+                    Ok(_) => entry.record_success(latency),
+                    Err(_) => entry.record_failure(self.breaker_failures, self.breaker_cooldown),
+                }
+                debug!("{} => {}ms", entry.provider.name(),
+                       latency.as_secs() * 1000 + latency.subsec_nanos() as u64 / 1000000);
+                BenchmarkResult {
+                    name: entry.provider.name().to_owned(),
+                    latency: latency,
+                    result: result,
+                }
+            })
+            .collect();
+        report.sort_by_key(|res| (res.result.is_err(), res.latency));
+        report
+    }
+
+    /// Compare the address reported by the IGD gateway with the
+    /// address seen by the other sources, to tell if we are behind
+    /// more than one NAT. Needs IGD enabled.
+    #[cfg(feature = "igd")]
+    pub fn nat_status(&self) -> NatStatus {
+        if !self.igd {
+            return NatStatus::Unknown;
+        }
+        let local = match self.local_bind().map(|bind| bind.ipv4()) {
+            Ok(Some(local)) => local,
+            _ => return NatStatus::Unknown,
+        };
+        let igd = gateway::igd_ip(local, self.igd_timeout);
+        let external = self.collect(false, false).into_ips();
+        let status = nat::nat_status(igd.as_ref(), &external);
+        debug!("NAT status => {:?}", status);
+        status
+    }
+
+    /// Put the local interface addresses, the IGD answer (if IGD is
+    /// enabled) and the public addresses together: are we behind NAT,
+    /// how many layers, is it carrier grade NAT, do we have IPv6
+    pub fn network_summary(&self) -> NetworkSummary {
+        let local = local::local_ips().unwrap_or_else(|err| {
+            info!("Local addresses => {}", err);
+            Vec::new()
+        });
+        #[cfg(feature = "igd")]
+        let gateway = if self.igd {
+            self.local_bind().ok()
+                .and_then(|bind| bind.ipv4())
+                .and_then(|local| gateway::igd_ip(local, self.igd_timeout))
+        } else {
+            None
+        };
+        #[cfg(not(feature = "igd"))]
+        let gateway = None;
+        let public = self.collect(false, false).into_ips();
+        let summary = nat::summarize(local, gateway, public);
+        debug!("Network summary => {:?}", summary);
+        summary
+    }
+}
+
+/// Returns the first IP address we can find
+pub fn whatsmyip() -> Result<MyIp, Error> {
+    let mut addrs = try!(WhatsMyIp::new()
+                        .fast(true)
+                        .find());
+    addrs.pop()
+        .ok_or(Error::NoSources)
+}
+
+#[test]
+fn test_6to4_teredo() {
+    let ip = MyIp::V4(Ipv4Addr::new(8, 8, 4, 4));
+    assert_eq!(ip.to_6to4(), Some(Ipv6Addr::new(0x2002, 0x0808, 0x0404, 0, 0, 0, 0, 0)));
+    assert_eq!(MyIp::V4(Ipv4Addr::new(10, 0, 0, 1)).to_6to4(), None);
+    assert_eq!(MyIp::V4(Ipv4Addr::new(100, 64, 0, 1)).to_6to4(), None);
+    assert_eq!(MyIp::V4(Ipv4Addr::new(192, 0, 2, 1)).to_6to4(), None);
+    // Example from RFC 4380
+    let teredo = MyIp::V6(Ipv6Addr::from_str("2001:0:4136:e378:8000:63bf:3fff:fdd2").unwrap());
+    assert_eq!(teredo.teredo_ipv4(), Some(Ipv4Addr::new(192, 0, 2, 45)));
+    assert_eq!(ip.teredo_ipv4(), None);
+}
+
+#[test]
+fn test_weighted_order() {
+    let entries: Vec<Registered> = vec![(0, 1), (5, 1), (0, 0), (5, 3)].into_iter()
+        .map(|(priority, weight)| {
+            Registered::new(Box::new(MockProvider::new("test")),
+                            priority, weight)
+        })
+        .collect();
+    let ordered = weighted_order(&mut thread_rng(), entries.iter().collect());
+    let priorities: Vec<u32> = ordered.iter().map(|e| e.priority).collect();
+    assert_eq!(priorities, vec![5, 5, 0, 0]);
+    // Weight 0 goes last among its priority
+    assert_eq!(ordered[3].weight, 0);
+}
+
+#[test]
+fn test_seed() {
+    let names = |opts: &WhatsMyIp| -> Vec<String> {
+        opts.order_providers(opts.providers.iter().collect()).iter()
+            .map(|e| e.provider.name().to_owned())
+            .collect()
+    };
+    let mut opts = WhatsMyIp::new();
+    opts.seed(Some(42));
+    let order = names(&opts);
+    assert_eq!(names(&opts), order);
+    opts.shuffle(false);
+    let added: Vec<String> = opts.providers.iter().map(|e| e.provider.name().to_owned()).collect();
+    assert_eq!(names(&opts), added);
+}
+
+#[test]
+fn test_circuit_breaker() {
+    let entry = Registered::new(Box::new(MockProvider::new("test")), 0, 1);
+    let cooldown = Duration::from_secs(60);
+    entry.record_failure(2, cooldown);
+    assert!(entry.available(Instant::now()));
+    entry.record_failure(2, cooldown);
+    assert!(!entry.available(Instant::now()));
+    assert!(entry.available(Instant::now() + cooldown));
+    entry.record_success(Duration::from_millis(10));
+    assert!(entry.available(Instant::now()));
+}
+
+#[test]
+fn test_finish_reports() {
+    let mut opts = WhatsMyIp::new();
+    opts.consensus(2).report_6to4(true);
+    let start = Instant::now();
+    let mut found = Found::new(IpFamily::Both);
+    found.add(MyIp::V4(Ipv4Addr::new(8, 8, 4, 4)), Source::Stun("stun".to_owned()), start);
+    found.add(MyIp::V4(Ipv4Addr::new(198, 51, 100, 1)), Source::Igd, start);
+    found.add(MyIp::V4(Ipv4Addr::new(8, 8, 4, 4)), Source::Dns("opendns".to_owned()), start);
+    let reports = opts.finish(found).unwrap();
+    let sources: Vec<Source> = reports.iter().map(|r| r.source.clone()).collect();
+    assert_eq!(sources, vec![Source::Stun("stun".to_owned()), Source::Dns("opendns".to_owned()),
+                             Source::SixToFour]);
+    assert!(reports[2].ip == MyIp::V6(Ipv6Addr::new(0x2002, 0x0808, 0x0404, 0, 0, 0, 0, 0)));
+}
+
+#[test]
+fn test_public_only() {
+    assert!(MyIp::from_str("8.8.8.8").unwrap().is_public());
+    assert!(MyIp::from_str("2606:4700::1111").unwrap().is_public());
+    for ip in &["192.168.1.1", "10.0.0.1", "172.31.0.1", "100.64.0.1", "127.0.0.1",
+                "169.254.1.1", "192.0.2.1", "224.0.0.1", "255.255.255.255",
+                "fe80::1", "fd00::1", "::1", "::ffff:8.8.8.8", "2001:db8::1"] {
+        assert!(!MyIp::from_str(ip).unwrap().is_public(), "{}", ip);
+    }
+
+    assert_eq!(MyIp::from_str("100.100.0.1").unwrap().scope(), IpScope::Cgnat);
+    assert_eq!(MyIp::from_str("fd12::1").unwrap().scope(), IpScope::UniqueLocal);
+    assert_eq!(MyIp::from_str("198.51.100.7").unwrap().scope(), IpScope::Reserved);
+
+    let mut found = WhatsMyIp::new().found();
+    found.add(MyIp::V4(Ipv4Addr::new(192, 168, 1, 2)), Source::Igd, Instant::now());
+    found.add(MyIp::V4(Ipv4Addr::new(8, 8, 8, 8)), Source::Dht, Instant::now());
+    found.add(MyIp::from_str("::ffff:8.8.8.8").unwrap(), Source::Dns("x".to_owned()), Instant::now());
+    assert_eq!(found.distinct(), vec![&MyIp::V4(Ipv4Addr::new(8, 8, 8, 8))]);
+    assert_eq!(found.count(&MyIp::V4(Ipv4Addr::new(8, 8, 8, 8))), 2);
+    assert_eq!(found.errors.len(), 1);
+}
+
+#[test]
+fn test_disagreements() {
+    let mut found = Found::new(IpFamily::Both);
+    let start = Instant::now();
+    found.add(MyIp::V4(Ipv4Addr::new(8, 8, 8, 8)), Source::Igd, start);
+    found.add(MyIp::V4(Ipv4Addr::new(9, 9, 9, 9)), Source::Dht, start);
+    found.add(MyIp::V4(Ipv4Addr::new(9, 9, 9, 9)), Source::Router, start);
+    found.add(MyIp::V4(Ipv4Addr::new(192, 168, 1, 1)), Source::Teredo, start);
+    found.add(MyIp::from_str("2606:4700::1").unwrap(), Source::Dht, start);
+    assert_eq!(disagreements(&found.reports), vec![Disagreement {
+        family: IpFamily::V4,
+        addresses: vec![(MyIp::V4(Ipv4Addr::new(8, 8, 8, 8)), vec![Source::Igd]),
+                        (MyIp::V4(Ipv4Addr::new(9, 9, 9, 9)), vec![Source::Dht, Source::Router])],
+    }]);
+    assert!(disagreements(&found.reports[1..]).is_empty());
+}
+
+#[test]
+fn test_myip_conversions() {
+    let ip = MyIp::from_str("192.0.2.1").unwrap();
+    assert!(ip.is_ipv4());
+    assert_eq!(ip.to_ipv4(), Some(Ipv4Addr::new(192, 0, 2, 1)));
+    assert_eq!(ip.to_ipv6(), None);
+    assert_eq!(IpAddr::from(ip), IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+    assert_eq!(MyIp::from(IpAddr::from(ip)), ip);
+    assert!(MyIp::from_str("2001:db8::1").unwrap().is_ipv6());
+    assert!(MyIp::from_str(" 192.0.2.1").is_err());
+    assert!(ip < MyIp::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)));
+}
+
+#[cfg(feature = "dns")]
+#[test]
+fn test_dns_transport() {
+    let enabled = |opts: &WhatsMyIp| -> Vec<&'static str> {
+        opts.dns_providers.iter()
+            .filter(|p| opts.dns_enabled(p))
+            .map(|p| p.name)
+            .collect()
+    };
+    let mut opts = WhatsMyIp::new();
+    opts.transport(IpFamily::V4);
+    // By the resolver address, google and DoH resolve a host name
+    assert_eq!(enabled(&opts), vec!["opendns", "cloudflare", "google"]);
+    opts.transport(IpFamily::V6);
+    assert_eq!(enabled(&opts), vec!["opendns6", "cloudflare6", "google"]);
+    opts.transport(IpFamily::V4).dns_providers(dns::DOH_PROVIDERS);
+    assert_eq!(enabled(&opts), vec!["opendns-doh", "cloudflare-doh"]);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde() {
+    let ip = MyIp::V4(Ipv4Addr::new(192, 0, 2, 1));
+    assert_eq!(serde_json::to_string(&ip).unwrap(), r#""192.0.2.1""#);
+    assert_eq!(serde_json::from_str::<MyIp>(r#""2001:db8::1""#).unwrap(),
+               MyIp::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)));
+    let res = DualStackResult {
+        v4: Some(Ipv4Addr::new(192, 0, 2, 1)),
+        v6: None,
+    };
+    assert_eq!(serde_json::to_string(&res).unwrap(), r#"{"v4":"192.0.2.1","v6":null}"#);
+}
+
+/// Three providers answering 192.0.2.1-3 after `delay`
+#[cfg(test)]
+fn delayed(opts: &mut WhatsMyIp, delay: Duration) {
+    for &(name, last) in &[("a", 1), ("b", 2), ("c", 3)] {
+        let ip = MyIp::V4(Ipv4Addr::new(192, 0, 2, last));
+        opts.provider(Box::new(MockProvider::new(name).ip(ip).delay(delay)));
+    }
+}
+
+#[test]
+fn test_concurrency() {
+    let mut opts = WhatsMyIp::new();
+    opts.igd(false).stun(false).dns(false).public_only(false).clear_providers()
+        .strategy(Strategy::FixedOrder)
+        .concurrency(3)
+        // One at a time only a and b would answer in time
+        .deadline(Some(Duration::from_millis(2500)));
+    delayed(&mut opts, Duration::from_secs(1));
+    assert_eq!(opts.find().unwrap(), vec![MyIp::V4(Ipv4Addr::new(192, 0, 2, 1)),
+                                          MyIp::V4(Ipv4Addr::new(192, 0, 2, 2)),
+                                          MyIp::V4(Ipv4Addr::new(192, 0, 2, 3))]);
+}
+
+#[test]
+fn test_deadline() {
+    let mut opts = WhatsMyIp::new();
+    opts.igd(false).stun(false).dns(false).public_only(false).clear_providers()
+        .strategy(Strategy::FixedOrder)
+        .deadline(Some(Duration::from_secs(1)));
+    let a = MyIp::V4(Ipv4Addr::new(192, 0, 2, 1));
+    opts.provider(Box::new(MockProvider::new("a").ip(a)))
+        .provider(Box::new(MockProvider::new("b").ip(a).delay(Duration::from_secs(30))))
+        .provider(Box::new(MockProvider::new("c").ip(a)));
+    let start = Instant::now();
+    // b is cut short and c is never queried
+    assert_eq!(opts.find().unwrap(), vec![a]);
+    assert!(start.elapsed() < Duration::from_secs(20));
+}
+
+#[test]
+fn test_retries() {
+    struct Flaky(AtomicUsize);
+    impl Provider for Flaky {
+        fn name(&self) -> &str {
+            "flaky"
+        }
+
+        fn fetch(&self, _: &Context) -> Result<MyIp, Error> {
+            match self.0.fetch_add(1, Ordering::SeqCst) {
+                0 => Err(Error::Timeout),
+                1 => Err(Error::Http { status: 503 }),
+                _ => Ok(MyIp::V4(Ipv4Addr::new(192, 0, 2, 1))),
+            }
+        }
+    }
+
+    let mut opts = WhatsMyIp::new();
+    opts.igd(false).stun(false).dns(false).public_only(false).clear_providers()
+        .provider(Box::new(Flaky(AtomicUsize::new(0))))
+        .backoff(Duration::from_millis(1));
+    assert!(opts.find().is_err());
+    opts.retries(2);
+    assert!(opts.find().is_ok());
+}
+
+#[test]
+fn test_cache_ttl() {
+    struct Counter(Arc<AtomicUsize>);
+    impl Provider for Counter {
+        fn name(&self) -> &str {
+            "counter"
+        }
+
+        fn fetch(&self, _: &Context) -> Result<MyIp, Error> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(MyIp::V4(Ipv4Addr::new(192, 0, 2, 1)))
+        }
+    }
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let mut opts = WhatsMyIp::new();
+    opts.igd(false).stun(false).dns(false).public_only(false).clear_providers()
+        .provider(Box::new(Counter(calls.clone())))
+        .cache_ttl(Some(Duration::from_secs(60)));
+    assert!(opts.find().unwrap() == opts.find().unwrap());
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    opts.refresh().unwrap();
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn test_mock_provider() {
+    let a = MyIp::V4(Ipv4Addr::new(8, 8, 4, 4));
+    let b = MyIp::V4(Ipv4Addr::new(8, 8, 8, 8));
+    let mut opts = WhatsMyIp::with_providers(vec![
+        Box::new(MockProvider::new("down").fail_http(503)),
+        Box::new(MockProvider::new("mock").ip(a).ip(b)),
+    ]);
+    opts.fast(true);
+    assert_eq!(opts.find().unwrap(), vec![a]);
+    assert_eq!(opts.find().unwrap(), vec![b]);
+    assert_eq!(opts.find().unwrap(), vec![b]);
+
+    let mut slow = WhatsMyIp::with_providers(vec![
+        Box::new(MockProvider::new("slow").ip(a).delay(Duration::from_secs(5))),
+    ]);
+    slow.http_timeout(Some(Duration::from_millis(50)));
+    match slow.find() {
+        Err(Error::AllProvidersFailed(errors)) => match errors[0].error {
+            Error::Timeout => (),
+            ref err => panic!("{:?}", err),
+        },
+        res => panic!("{:?}", res),
+    }
 }
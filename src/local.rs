@@ -0,0 +1,134 @@
+//!
+//! Addresses configured on the local interfaces
+//!
+
+#[cfg(target_os = "linux")]
+use std::fs::File;
+#[cfg(target_os = "linux")]
+use std::io::Read;
+use std::net::{IpAddr, Ipv6Addr};
+use get_if_addrs::get_if_addrs;
+use {MyIp, Error};
+
+/// Which local IPv6 addresses to report, see `WhatsMyIp::local_ipv6()`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ipv6Preference {
+    /// Stable addresses, temporary ones only if there are none.
+    /// This is the address to publish e.g. in DNS.
+    Stable,
+    /// Temporary (RFC 4941) addresses, used for outgoing connections,
+    /// stable ones only if there are none
+    Temporary,
+    /// All of them
+    Both,
+}
+
+/// All addresses on the local interfaces, except loopback
+pub fn local_ips() -> Result<Vec<MyIp>, Error> {
+    let ifaces = try!(get_if_addrs()
+                        .map_err(Error::from));
+    Ok(ifaces.iter()
+        .filter(|iface| !iface.is_loopback())
+        .map(|iface| MyIp::from(iface.ip()))
+        .collect())
+}
+
+/// The external IPv4 address embedded in local Teredo addresses
+pub fn teredo_ips() -> Result<Vec<MyIp>, Error> {
+    let ips = try!(local_ips());
+    Ok(ips.iter()
+        .filter_map(|ip| {
+            let ipv4 = ip.teredo_ipv4();
+            if let Some(ref ipv4) = ipv4 {
+                debug!("Teredo {} => {}", ip, ipv4);
+            }
+            ipv4
+        })
+        .map(MyIp::V4)
+        .collect())
+}
+
+/// Marks the temporary addresses in `/proc/net/if_inet6`, a line
+/// is the address in hex, index, prefix length, scope, flags and name
+#[cfg(target_os = "linux")]
+fn parse_if_inet6(content: &str) -> Vec<(Ipv6Addr, bool)> {
+    const IFA_F_TEMPORARY: u32 = 0x01;
+    content.lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 6 || fields[0].len() != 32 {
+                return None;
+            }
+            let mut segments = [0u16; 8];
+            for (idx, segment) in segments.iter_mut().enumerate() {
+                *segment = match u16::from_str_radix(&fields[0][idx * 4..idx * 4 + 4], 16) {
+                    Ok(segment) => segment,
+                    Err(_) => return None,
+                };
+            }
+            let flags = match u32::from_str_radix(fields[4], 16) {
+                Ok(flags) => flags,
+                Err(_) => return None,
+            };
+            Some((Ipv6Addr::new(segments[0], segments[1], segments[2], segments[3],
+                                segments[4], segments[5], segments[6], segments[7]),
+                  flags & IFA_F_TEMPORARY != 0))
+        })
+        .collect()
+}
+
+/// The IPv6 addresses on the local interfaces and whether
+/// each one is temporary
+#[cfg(target_os = "linux")]
+fn ipv6_addresses() -> Result<Vec<(Ipv6Addr, bool)>, Error> {
+    let mut content = String::new();
+    try!(File::open("/proc/net/if_inet6")
+            .and_then(|mut f| f.read_to_string(&mut content))
+            .map_err(Error::from));
+    Ok(parse_if_inet6(&content))
+}
+
+/// Same as the Linux version, but with no way to tell temporary
+/// addresses apart all of them are stable
+#[cfg(not(target_os = "linux"))]
+fn ipv6_addresses() -> Result<Vec<(Ipv6Addr, bool)>, Error> {
+    Ok(try!(local_ips()).into_iter()
+        .filter_map(|ip| ip.to_ipv6())
+        .map(|ip| (ip, false))
+        .collect())
+}
+
+/// The public IPv6 addresses on the local interfaces, with no NAT
+/// for IPv6 these are the external addresses. Each one comes with
+/// whether it is temporary.
+pub fn public_ipv6(preference: Ipv6Preference) -> Result<Vec<(MyIp, bool)>, Error> {
+    let ips: Vec<(MyIp, bool)> = try!(ipv6_addresses()).into_iter()
+                                    .map(|(ip, temporary)| (MyIp::from(IpAddr::V6(ip)), temporary))
+                                    .filter(|&(ip, _)| ip.is_public())
+                                    .collect();
+    let wanted = match preference {
+        Ipv6Preference::Stable => false,
+        Ipv6Preference::Temporary => true,
+        Ipv6Preference::Both => return Ok(ips),
+    };
+    if ips.iter().any(|&(_, temporary)| temporary == wanted) {
+        Ok(ips.into_iter().filter(|&(_, temporary)| temporary == wanted).collect())
+    } else {
+        Ok(ips)
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_parse_if_inet6() {
+    let content = "\
+20010db8000000000000000000000001 02 40 00 80 eth0
+20010db80000000011223344556677ff 02 40 00 01 eth0
+fe800000000000000000000000000001 02 40 20 80 eth0
+00000000000000000000000000000001 01 80 10 80 lo
+";
+    let ips = parse_if_inet6(content);
+    assert_eq!(ips.len(), 4);
+    assert_eq!(ips[0], (Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1), false));
+    assert_eq!(ips[1], (Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0x1122, 0x3344, 0x5566, 0x77ff), true));
+}
@@ -0,0 +1,228 @@
+//!
+//! Watch the external address for changes
+//!
+//! ```no_run
+//! use whatsmyip::{WhatsMyIp, IpMonitor};
+//! let mut opts = WhatsMyIp::new();
+//! opts.fast(true);
+//! IpMonitor::new(opts).run(|ips| println!("New address {:?}", ips));
+//! ```
+//!
+
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use {MyIp, Error, ExecHook, History, HistoryEntry, State, WhatsMyIp};
+#[cfg(feature = "http")]
+use {Updater, WebHook};
+use unique_ips;
+#[cfg(all(target_os = "linux", feature = "linux-netlink"))]
+use netlink;
+
+/// Checks the external address periodically
+pub struct IpMonitor {
+    opts: WhatsMyIp,
+    interval: Duration,
+    last: Option<Vec<MyIp>>,
+    state_file: Option<PathBuf>,
+    history: History,
+    exec_hooks: Vec<ExecHook>,
+    #[cfg(feature = "http")]
+    webhooks: Vec<WebHook>,
+    #[cfg(feature = "http")]
+    updaters: Vec<Box<dyn Updater>>,
+}
+
+impl IpMonitor {
+    pub fn new(opts: WhatsMyIp) -> IpMonitor {
+        IpMonitor {
+            opts: opts,
+            interval: Duration::from_secs(300),
+            last: None,
+            state_file: None,
+            history: History::new(100),
+            exec_hooks: Vec::new(),
+            #[cfg(feature = "http")]
+            webhooks: Vec::new(),
+            #[cfg(feature = "http")]
+            updaters: Vec::new(),
+        }
+    }
+
+    /// Time between checks. On Linux with the `linux-netlink` feature
+    /// we also check as soon as an interface address or route changes.
+    /// (defaults to **5 minutes**)
+    pub fn interval(&mut self, interval: Duration) -> &mut Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Save the address and time to `path` after each check, and start
+    /// from the saved address so changes are detected across restarts
+    /// (defaults to **None**)
+    pub fn state_file<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.state_file = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// The addresses from the last successful check
+    pub fn last(&self) -> Option<&[MyIp]> {
+        self.last.as_ref().map(|ips| &ips[..])
+    }
+
+    /// Append every change to `path`
+    /// (defaults to **None**)
+    pub fn history_file<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.history.file(path);
+        self
+    }
+
+    /// Number of changes kept in `history()`
+    /// (defaults to **100**)
+    pub fn history_size(&mut self, size: usize) -> &mut Self {
+        self.history.capacity(size);
+        self
+    }
+
+    /// Run `program` with `args` when the address changes, see `ExecHook`
+    pub fn exec(&mut self, program: &str, args: &[&str]) -> &mut Self {
+        self.exec_hooks.push(ExecHook::new(program, args));
+        self
+    }
+
+    /// POST the change to a URL, see `WebHook`
+    #[cfg(feature = "http")]
+    pub fn webhook(&mut self, hook: WebHook) -> &mut Self {
+        self.webhooks.push(hook);
+        self
+    }
+
+    /// Publish the new addresses when they change, e.g. with `Dyndns2`,
+    /// `Cloudflare` or your own `Updater`
+    #[cfg(feature = "http")]
+    pub fn updater<U: Updater + 'static>(&mut self, updater: U) -> &mut Self {
+        self.updaters.push(Box::new(updater));
+        self
+    }
+
+    /// The most recent address changes
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /// The state saved in the state file, without a lookup
+    pub fn state(&self) -> Result<Option<State>, Error> {
+        match self.state_file {
+            Some(ref path) => State::load(path),
+            None => Ok(None),
+        }
+    }
+
+    /// Look up the address now, returns the addresses if they
+    /// changed since the last check
+    pub fn check(&mut self) -> Result<Option<Vec<MyIp>>, Error> {
+        if self.last.is_none() {
+            match self.state() {
+                Ok(state) => self.last = state.map(|state| state.ips),
+                Err(err) => info!("State file => {}", err),
+            }
+        }
+
+        let reports = try!(self.opts.find_detailed());
+        let mut sources = Vec::new();
+        for report in &reports {
+            if !sources.contains(&report.source) {
+                sources.push(report.source.clone());
+            }
+        }
+        let mut ips = unique_ips(reports);
+        ips.sort();
+        if let Some(ref path) = self.state_file {
+            try!(State::new(ips.clone()).save(path));
+        }
+        if self.last.as_ref() == Some(&ips) {
+            return Ok(None);
+        }
+        info!("Address changed to {:?}", ips);
+        let change = HistoryEntry {
+            ips: ips.clone(),
+            sources: sources,
+            timestamp: SystemTime::now(),
+        };
+        self.notify(self.last.as_ref().map(|ips| &ips[..]).unwrap_or(&[]), &change);
+        self.last = Some(ips.clone());
+        // The hooks ran, a history file that cannot be written must
+        // not make the next check report the same change again
+        if let Err(err) = self.history.push(change) {
+            info!("History => {}", err);
+        }
+        Ok(Some(ips))
+    }
+
+    /// Run the hooks, failures are logged but do not stop the others
+    fn notify(&self, old: &[MyIp], change: &HistoryEntry) {
+        for hook in &self.exec_hooks {
+            if let Err(err) = hook.run(old, change) {
+                info!("Hook => {}", err);
+            }
+        }
+        #[cfg(feature = "http")]
+        for hook in &self.webhooks {
+            if let Err(err) = hook.run(old, change) {
+                info!("Webhook {} => {}", hook.url(), err);
+            }
+        }
+        #[cfg(feature = "http")]
+        for updater in &self.updaters {
+            if let Err(err) = updater.update_all(&change.ips) {
+                info!("Updater {} => {}", updater.name(), err);
+            }
+        }
+    }
+
+    /// Block until the next check is due
+    #[cfg(all(target_os = "linux", feature = "linux-netlink"))]
+    fn wait(&self) {
+        match netlink::wait_for_change(self.interval) {
+            Ok(true) => debug!("Network configuration changed"),
+            Ok(false) => (),
+            Err(err) => {
+                info!("Netlink => {}", err);
+                thread::sleep(self.interval);
+            },
+        }
+    }
+
+    #[cfg(not(all(target_os = "linux", feature = "linux-netlink")))]
+    fn wait(&self) {
+        thread::sleep(self.interval);
+    }
+
+    /// Check forever, `f` is called with the addresses on the
+    /// first check and every time they change. With a state file the
+    /// first check only counts if the address differs from the saved one
+    pub fn run<F: FnMut(&[MyIp])>(&mut self, mut f: F) {
+        loop {
+            match self.check() {
+                Ok(Some(ips)) => f(&ips),
+                Ok(None) => (),
+                Err(err) => info!("{}", err),
+            }
+            self.wait();
+        }
+    }
+}
+
+#[test]
+fn test_history_file_error() {
+    use std::net::Ipv4Addr;
+    use MockProvider;
+    let ip = MyIp::V4(Ipv4Addr::new(8, 8, 8, 8));
+    let opts = WhatsMyIp::with_providers(vec![Box::new(MockProvider::new("mock").ip(ip))]);
+    let mut monitor = IpMonitor::new(opts);
+    monitor.history_file("/nonexistent/whatsmyip/history");
+    assert_eq!(monitor.check().unwrap(), Some(vec![ip]));
+    assert_eq!(monitor.history().len(), 1);
+    // Not the same change again
+    assert_eq!(monitor.check().unwrap(), None);
+}
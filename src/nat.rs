@@ -0,0 +1,148 @@
+//!
+//! Tell a single NAT from double NAT and carrier grade NAT
+//!
+
+use std::net::Ipv4Addr;
+use {IpScope, MyIp};
+
+/// What sits between us and the internet
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum NatStatus {
+    /// The gateway holds the public address, port forwarding works
+    OpenNat,
+    /// The gateway is itself behind another NAT
+    DoubleNat,
+    /// The gateway has an address in the shared 100.64.0.0/10 range,
+    /// i.e. the ISP uses carrier grade NAT
+    Cgnat,
+    /// Not enough information, e.g. no IGD gateway
+    Unknown,
+}
+
+fn is_cgnat(ip: &Ipv4Addr) -> bool {
+    let o = ip.octets();
+    o[0] == 100 && o[1] & 0xc0 == 64
+}
+
+/// Compare the IGD address with the addresses from the other sources
+pub fn nat_status(igd: Option<&MyIp>, external: &[MyIp]) -> NatStatus {
+    let gw = match igd {
+        Some(&MyIp::V4(ip)) => ip,
+        _ => return NatStatus::Unknown,
+    };
+    if is_cgnat(&gw) {
+        return NatStatus::Cgnat;
+    }
+    if gw.is_private() || gw.is_link_local() {
+        return NatStatus::DoubleNat;
+    }
+
+    let mut seen = false;
+    for ip in external {
+        if let &MyIp::V4(ip) = ip {
+            if ip == gw {
+                return NatStatus::OpenNat;
+            }
+            seen = true;
+        }
+    }
+    // The gateway has a public address, but not the one the
+    // internet sees, it is stale or there is another NAT
+    if seen {
+        NatStatus::DoubleNat
+    } else {
+        NatStatus::Unknown
+    }
+}
+
+/// What the local, gateway and public addresses say about the
+/// network, see `WhatsMyIp::network_summary()`
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NetworkSummary {
+    /// Addresses on the local interfaces, except loopback
+    pub local: Vec<MyIp>,
+    /// External address of the IGD gateway
+    pub gateway: Option<MyIp>,
+    /// Addresses the internet sees
+    pub public: Vec<MyIp>,
+    pub nat: NatStatus,
+    /// The public IPv4 address is not on a local interface, None
+    /// without a public IPv4 address
+    pub behind_nat: Option<bool>,
+    /// Best effort count of NATs between us and the internet, a
+    /// lower bound when the gateway does not tell
+    pub nat_layers: Option<u32>,
+    /// The ISP seems to use carrier grade NAT
+    pub cgnat_suspected: bool,
+    /// The internet sees an IPv6 address, or there is a public
+    /// IPv6 address on a local interface
+    pub ipv6: bool,
+}
+
+/// Cross reference the addresses, `gateway` is the IGD address
+pub fn summarize(local: Vec<MyIp>, gateway: Option<MyIp>, public: Vec<MyIp>) -> NetworkSummary {
+    let nat = nat_status(gateway.as_ref(), &public);
+    let local_cgnat = local.iter().any(|ip| ip.scope() == IpScope::Cgnat);
+    let behind_nat = public.iter()
+                        .find(|ip| ip.is_ipv4())
+                        .map(|ip| !local.contains(ip));
+    let nat_layers = behind_nat.map(|behind| {
+        if !behind {
+            return 0;
+        }
+        match nat {
+            NatStatus::OpenNat => 1,
+            // The home router and the one above it
+            NatStatus::DoubleNat | NatStatus::Cgnat => 2,
+            // No gateway to ask, we have a CGNAT address ourselves
+            // e.g. on a mobile network, or at least one NAT
+            NatStatus::Unknown => 1,
+        }
+    });
+    let ipv6 = public.iter().chain(&local).any(|ip| ip.is_ipv6() && ip.is_public());
+    NetworkSummary {
+        cgnat_suspected: nat == NatStatus::Cgnat || local_cgnat,
+        local: local,
+        gateway: gateway,
+        public: public,
+        nat: nat,
+        behind_nat: behind_nat,
+        nat_layers: nat_layers,
+        ipv6: ipv6,
+    }
+}
+
+#[test]
+fn test_nat_status() {
+    let public = MyIp::V4(Ipv4Addr::new(192, 0, 2, 1));
+    let external = [MyIp::V4(Ipv4Addr::new(192, 0, 2, 1))];
+    assert_eq!(nat_status(Some(&public), &external), NatStatus::OpenNat);
+    assert_eq!(nat_status(Some(&MyIp::V4(Ipv4Addr::new(100, 72, 0, 1))), &external),
+               NatStatus::Cgnat);
+    assert_eq!(nat_status(Some(&MyIp::V4(Ipv4Addr::new(192, 168, 0, 2))), &external),
+               NatStatus::DoubleNat);
+    assert_eq!(nat_status(Some(&MyIp::V4(Ipv4Addr::new(198, 51, 100, 1))), &external),
+               NatStatus::DoubleNat);
+    assert_eq!(nat_status(None, &external), NatStatus::Unknown);
+}
+
+#[test]
+fn test_summarize() {
+    use std::str::FromStr;
+    let ip = |s| MyIp::from_str(s).unwrap();
+    let public = vec![ip("8.8.4.4"), ip("2606:4700::1")];
+    let home = summarize(vec![ip("192.168.1.10")], Some(ip("8.8.4.4")), public.clone());
+    assert_eq!(home.behind_nat, Some(true));
+    assert_eq!(home.nat_layers, Some(1));
+    assert!(!home.cgnat_suspected);
+    assert!(home.ipv6);
+    let cgnat = summarize(vec![ip("192.168.1.10")], Some(ip("100.64.1.2")), vec![ip("8.8.4.4")]);
+    assert_eq!(cgnat.nat_layers, Some(2));
+    assert!(cgnat.cgnat_suspected);
+    assert!(!cgnat.ipv6);
+    let server = summarize(vec![ip("8.8.4.4")], None, public);
+    assert_eq!(server.behind_nat, Some(false));
+    assert_eq!(server.nat_layers, Some(0));
+}
@@ -0,0 +1,146 @@
+//!
+//! Wait for address and route changes with rtnetlink (Linux)
+//!
+
+use std::io;
+use std::mem;
+use std::time::{Duration, Instant};
+use libc;
+
+/// Size of the netlink message header
+const NLMSG_HDRLEN: usize = 16;
+
+/// An rtnetlink socket subscribed to link, address and route changes
+struct Socket(libc::c_int);
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.0);
+        }
+    }
+}
+
+impl Socket {
+    fn open() -> io::Result<Socket> {
+        let fd = unsafe {
+            libc::socket(libc::AF_NETLINK, libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                         libc::NETLINK_ROUTE)
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let sock = Socket(fd);
+
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = (libc::RTMGRP_LINK | libc::RTMGRP_IPV4_IFADDR | libc::RTMGRP_IPV4_ROUTE
+                          | libc::RTMGRP_IPV6_IFADDR | libc::RTMGRP_IPV6_ROUTE) as u32;
+        let res = unsafe {
+            libc::bind(fd, &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                       mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t)
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(sock)
+    }
+
+    fn set_timeout(&self, timeout: Duration) -> io::Result<()> {
+        let tv = libc::timeval {
+            tv_sec: timeout.as_secs() as libc::time_t,
+            tv_usec: timeout.subsec_micros() as libc::suseconds_t,
+        };
+        let res = unsafe {
+            libc::setsockopt(self.0, libc::SOL_SOCKET, libc::SO_RCVTIMEO,
+                             &tv as *const libc::timeval as *const libc::c_void,
+                             mem::size_of::<libc::timeval>() as libc::socklen_t)
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Read a batch of messages, true if one of them is a change
+    fn recv_change(&self, buf: &mut [u8]) -> io::Result<bool> {
+        let len = unsafe {
+            libc::recv(self.0, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0)
+        };
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(has_change(&buf[..len as usize]))
+    }
+}
+
+/// True if the netlink messages in `buf` include a link,
+/// address or route change
+fn has_change(buf: &[u8]) -> bool {
+    let mut pos = 0;
+    while pos + NLMSG_HDRLEN <= buf.len() {
+        let msg_len = (buf[pos] as usize) | (buf[pos+1] as usize) << 8
+                        | (buf[pos+2] as usize) << 16 | (buf[pos+3] as usize) << 24;
+        let msg_type = (buf[pos+4] as u16) | (buf[pos+5] as u16) << 8;
+        match msg_type {
+            libc::RTM_NEWLINK | libc::RTM_DELLINK | libc::RTM_NEWADDR | libc::RTM_DELADDR
+                | libc::RTM_NEWROUTE | libc::RTM_DELROUTE => return true,
+            _ => (),
+        }
+        if msg_len < NLMSG_HDRLEN {
+            break;
+        }
+        // Messages are 4 byte aligned
+        pos += (msg_len + 3) & !3;
+    }
+    false
+}
+
+fn is_timeout(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut
+}
+
+/// Block until a link, address or route changes or `timeout` passes,
+/// returns true on a change. Any interface counts, not only the one
+/// facing the internet.
+pub fn wait_for_change(timeout: Duration) -> io::Result<bool> {
+    let sock = try!(Socket::open());
+    let deadline = Instant::now() + timeout;
+    let mut buf = [0u8; 8192];
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(false);
+        }
+        try!(sock.set_timeout(deadline - now));
+        match sock.recv_change(&mut buf) {
+            Ok(true) => break,
+            Ok(false) => (),
+            Err(ref err) if is_timeout(err) => return Ok(false),
+            Err(err) => return Err(err),
+        }
+    }
+
+    // A change comes with a burst of messages (e.g. link, address
+    // and routes), wait for it to settle before checking
+    try!(sock.set_timeout(Duration::from_millis(500)));
+    loop {
+        match sock.recv_change(&mut buf) {
+            Ok(_) => (),
+            Err(ref err) if is_timeout(err) => return Ok(true),
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+#[test]
+fn test_has_change() {
+    // RTM_NEWADDR header, little endian
+    let mut msg = [0u8; 24];
+    msg[0] = 24;
+    msg[4] = libc::RTM_NEWADDR as u8;
+    assert!(has_change(&msg));
+    // NLMSG_DONE
+    msg[4] = 3;
+    assert!(!has_change(&msg));
+}
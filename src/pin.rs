@@ -0,0 +1,176 @@
+//!
+//! Certificate pinning for HTTPS providers, see `HttpProvider::pin()`
+//!
+//! A provider that answers through a captive portal or a TLS
+//! intercepting proxy gives the address of the wrong network, a
+//! pinned certificate turns that into an error.
+//!
+
+#[cfg(feature = "http")]
+use std::io;
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use rustc_serialize::base64::FromBase64;
+use rustc_serialize::hex::FromHex;
+#[cfg(all(feature = "openssl-tls", not(feature = "rustls-tls")))]
+use hyper::net::HttpStream;
+#[cfg(all(feature = "openssl-tls", not(feature = "rustls-tls")))]
+use openssl::ssl::SslStream;
+
+/// A certificate the server must present, by the SHA-256 hash of
+/// the whole certificate or of its public key (SPKI). A public key
+/// pin survives renewals that keep the same key.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pin {
+    Certificate(Vec<u8>),
+    PublicKey(Vec<u8>),
+}
+
+impl Pin {
+    /// The SHA-256 fingerprint of the certificate in hex, with or
+    /// without colons, e.g. from `openssl x509 -noout -fingerprint -sha256`
+    pub fn certificate(fingerprint: &str) -> Option<Pin> {
+        let hex: String = fingerprint.chars().filter(|&c| c != ':').collect();
+        match hex.from_hex() {
+            Ok(hash) if hash.len() == 32 => Some(Pin::Certificate(hash)),
+            _ => None,
+        }
+    }
+
+    /// The SHA-256 hash of the public key in base64, as in curl's
+    /// `--pinnedpubkey sha256//...`, the prefix is optional
+    pub fn public_key(hash: &str) -> Option<Pin> {
+        match hash.trim_left_matches("sha256//").from_base64() {
+            Ok(hash) if hash.len() == 32 => Some(Pin::PublicKey(hash)),
+            _ => None,
+        }
+    }
+
+    /// True if the DER encoded certificate `cert` matches
+    pub fn matches(&self, cert: &[u8]) -> bool {
+        match *self {
+            Pin::Certificate(ref hash) => sha256(cert) == *hash,
+            Pin::PublicKey(ref hash) => spki(cert).map_or(false, |key| sha256(key) == *hash),
+        }
+    }
+}
+
+/// TLS streams that can tell the server certificate
+#[cfg(feature = "http")]
+pub trait PeerCertificate {
+    /// The server certificate, DER encoded
+    fn peer_certificate(&self) -> Option<Vec<u8>>;
+}
+
+#[cfg(all(feature = "openssl-tls", not(feature = "rustls-tls")))]
+impl PeerCertificate for SslStream<HttpStream> {
+    fn peer_certificate(&self) -> Option<Vec<u8>> {
+        let cert = match self.ssl().peer_certificate() {
+            Some(cert) => cert,
+            None => return None,
+        };
+        let mut pem = Vec::new();
+        if cert.write_pem(&mut pem).is_err() {
+            return None;
+        }
+        // The base64 between the BEGIN and END lines
+        let b64: String = String::from_utf8_lossy(&pem).lines()
+                            .filter(|line| !line.starts_with("-----"))
+                            .collect();
+        b64.from_base64().ok()
+    }
+}
+
+/// Fails unless the server certificate of `stream` matches one
+/// of `pins`, no pins means any certificate
+#[cfg(feature = "http")]
+pub fn check<S: PeerCertificate>(pins: &[Pin], stream: &S, host: &str) -> io::Result<()> {
+    if pins.is_empty() {
+        return Ok(());
+    }
+    match stream.peer_certificate() {
+        Some(ref cert) if pins.iter().any(|pin| pin.matches(cert)) => Ok(()),
+        Some(_) => {
+            info!("Certificate of {} does not match its pins", host);
+            Err(io::Error::new(io::ErrorKind::Other,
+                               format!("Certificate of {} does not match its pins", host)))
+        },
+        None => Err(io::Error::new(io::ErrorKind::Other,
+                                   format!("No certificate from {}", host))),
+    }
+}
+
+fn sha256(data: &[u8]) -> Vec<u8> {
+    let mut sha = Sha256::new();
+    sha.input(data);
+    let mut hash = vec![0u8; 32];
+    sha.result(&mut hash);
+    hash
+}
+
+/// Splits the DER element at the start of `der` into its
+/// contents and the bytes after it
+fn der_element(der: &[u8]) -> Option<(&[u8], &[u8])> {
+    if der.len() < 2 {
+        return None;
+    }
+    let (len, start) = if der[1] < 0x80 {
+        (der[1] as usize, 2)
+    } else {
+        let n = (der[1] & 0x7f) as usize;
+        if n == 0 || n > 4 || der.len() < 2 + n {
+            return None;
+        }
+        (der[2..2 + n].iter().fold(0, |len, &b| (len << 8) | b as usize), 2 + n)
+    };
+    if der.len() - start < len {
+        return None;
+    }
+    Some((&der[start..start + len], &der[start + len..]))
+}
+
+/// The SubjectPublicKeyInfo of a certificate, including its
+/// tag and length since the hash covers the whole element
+fn spki(cert: &[u8]) -> Option<&[u8]> {
+    let tbs = match der_element(cert).and_then(|(cert, _)| der_element(cert)) {
+        Some((tbs, _)) => tbs,
+        None => return None,
+    };
+    // Skip the version (explicitly tagged and optional), serial,
+    // signature algorithm, issuer, validity and subject
+    let mut rest = tbs;
+    let skip = if rest.first() == Some(&0xa0) { 6 } else { 5 };
+    for _ in 0..skip {
+        rest = match der_element(rest) {
+            Some((_, after)) => after,
+            None => return None,
+        };
+    }
+    match der_element(rest) {
+        Some((_, after)) => Some(&rest[..rest.len() - after.len()]),
+        None => None,
+    }
+}
+
+#[test]
+fn test_pin() {
+    use rustc_serialize::base64::{ToBase64, STANDARD};
+    use rustc_serialize::hex::ToHex;
+
+    // The structure of a certificate, with empty fields
+    let key = [0x30, 0x02, 0x05, 0x00];
+    let mut tbs = vec![0xa0, 0x03, 0x02, 0x01, 0x02, 0x02, 0x01, 0x01,
+                       0x30, 0x00, 0x30, 0x00, 0x30, 0x00, 0x30, 0x00];
+    tbs.extend_from_slice(&key);
+    let mut cert = vec![0x30, tbs.len() as u8 + 7, 0x30, tbs.len() as u8];
+    cert.extend_from_slice(&tbs);
+    cert.extend_from_slice(&[0x30, 0x00, 0x03, 0x01, 0x00]);
+    assert_eq!(spki(&cert), Some(&key[..]));
+
+    let fingerprint = sha256(&cert).to_hex();
+    assert!(Pin::certificate(&fingerprint).unwrap().matches(&cert));
+    let pin = format!("sha256//{}", sha256(&key).to_base64(STANDARD));
+    assert!(Pin::public_key(&pin).unwrap().matches(&cert));
+    assert!(!Pin::public_key(&pin).unwrap().matches(&key));
+    assert_eq!(Pin::certificate("00:11"), None);
+}
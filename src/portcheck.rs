@@ -0,0 +1,120 @@
+//!
+//! Check if a port is reachable from the internet
+//!
+//! ```no_run
+//! use whatsmyip::PortCheck;
+//! let open = PortCheck::new(8080)
+//!                 .service(whatsmyip::portcheck::IFCONFIG_PORT_URL)
+//!                 .check().unwrap();
+//! ```
+//!
+
+use std::io::Read;
+use std::time::Duration;
+use rustc_serialize::json::Json;
+use {MyIp, Error, whatsmyip};
+use client::{self, ClientConfig};
+use tcp;
+
+/// ifconfig.co connects back to the port and answers with JSON
+pub const IFCONFIG_PORT_URL: &'static str = "https://ifconfig.co/port/{port}";
+
+/// Checks if a TCP port is reachable from the internet
+pub struct PortCheck {
+    port: u16,
+    ip: Option<MyIp>,
+    service: Option<String>,
+    timeout: Duration,
+}
+
+/// Parse the `reachable` field from the service response
+fn reachable_from_json(body: &str) -> Result<bool, Error> {
+    let json = try!(Json::from_str(body)
+                    .map_err(Error::from));
+    match json.find("reachable").and_then(|val| val.as_boolean()) {
+        Some(reachable) => Ok(reachable),
+        None => Err(Error::Parse("No field reachable in JSON response".to_owned())),
+    }
+}
+
+impl PortCheck {
+    pub fn new(port: u16) -> PortCheck {
+        PortCheck {
+            port: port,
+            ip: None,
+            service: None,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// The external address to connect to, if not set it is
+    /// found with `whatsmyip()`. Ignored when using a service.
+    pub fn ip(&mut self, ip: MyIp) -> &mut Self {
+        self.ip = Some(ip);
+        self
+    }
+
+    /// Ask a cooperating service to connect back to us, `{port}` in
+    /// the URL is replaced with the port and the response must be JSON
+    /// with a boolean `reachable` field (see `IFCONFIG_PORT_URL`).
+    ///
+    /// Without a service we connect to our own external address,
+    /// this only works if the router supports hairpinning.
+    /// (defaults to **None**)
+    pub fn service(&mut self, url: &str) -> &mut Self {
+        self.service = Some(url.to_owned());
+        self
+    }
+
+    /// Timeout for the connection or the service request
+    /// (defaults to **5 seconds**)
+    pub fn timeout(&mut self, t: Duration) -> &mut Self {
+        self.timeout = t;
+        self
+    }
+
+    fn check_service(&self, url: &str) -> Result<bool, Error> {
+        let url = url.replace("{port}", &self.port.to_string());
+        let cli = client::new_client(&ClientConfig::new(Some(self.timeout)));
+        let mut res = try!(cli.get(&url)
+                        .send()
+                        .map_err(Error::from));
+        if !res.status.is_success() {
+            return Err(Error::Http { status: res.status.to_u16() });
+        }
+        let mut s = String::new();
+        try!(res.read_to_string(&mut s)
+            .map_err(Error::from));
+        debug!("{} => {}", &url, &s);
+        reachable_from_json(&s)
+    }
+
+    fn check_loopback(&self) -> Result<bool, Error> {
+        let ip = match self.ip {
+            Some(ref ip) => ip.to_string(),
+            None => try!(whatsmyip()).to_string(),
+        };
+        match tcp::connect(&ip, self.port, self.timeout) {
+            Ok(_) => Ok(true),
+            Err(err) => {
+                info!("{}:{} => {}", ip, self.port, err);
+                Ok(false)
+            },
+        }
+    }
+
+    /// Returns true if the port is reachable
+    pub fn check(&self) -> Result<bool, Error> {
+        match self.service {
+            Some(ref url) => self.check_service(url),
+            None => self.check_loopback(),
+        }
+    }
+}
+
+#[test]
+fn test_reachable_from_json() {
+    let body = r#"{"ip":"192.0.2.1","port":8080,"reachable":true}"#;
+    assert!(reachable_from_json(body).unwrap());
+    assert!(reachable_from_json(r#"{"ip":"192.0.2.1"}"#).is_err());
+}
@@ -0,0 +1,421 @@
+//!
+//! Pluggable address providers
+//!
+//! Implement `Provider` to add your own endpoints or protocols
+//! with `WhatsMyIp::provider()`.
+//!
+
+use std::cmp::min;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use {MyIp, Error, Pin, WhatsMyIp};
+#[cfg(feature = "http")]
+use http::{http_ip_txt, http_ip_json, http_ip_header, http_ip_regex, http_ips_txt, http_ips_json};
+
+/// Address families a provider can report
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum IpFamily {
+    V4,
+    V6,
+    Both,
+}
+
+impl IpFamily {
+    /// True if `ip` belongs to this family
+    pub fn includes(&self, ip: &MyIp) -> bool {
+        matches!((*self, ip), (IpFamily::Both, _) | (IpFamily::V4, &MyIp::V4(_))
+                              | (IpFamily::V6, &MyIp::V6(_)))
+    }
+
+    /// True if both families have addresses in common
+    pub fn overlaps(&self, other: IpFamily) -> bool {
+        *self == IpFamily::Both || other == IpFamily::Both || *self == other
+    }
+}
+
+/// How `WhatsMyIp::find()` orders providers with the same priority
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Strategy {
+    /// Random order, a higher weight makes a provider more likely
+    /// to be tried first
+    RandomShuffle,
+    /// The order providers were added in
+    FixedOrder,
+    /// Start with the next provider on each call, spreads the
+    /// load when calling `find()` repeatedly
+    RoundRobin,
+    /// Fastest provider in previous calls first
+    LowestLatencyFirst,
+}
+
+/// What a provider gets to work with
+pub struct Context<'a> {
+    /// The settings for this search
+    pub opts: &'a WhatsMyIp,
+    /// Timeout for this provider, its override or `http_timeout()`
+    pub timeout: Option<Duration>,
+    /// Certificates HTTPS servers must present, see `HttpProvider::pin()`
+    pub pins: &'a [Pin],
+}
+
+/// Health of a provider across calls to `WhatsMyIp::find()`
+#[derive(Clone, Debug)]
+pub struct ProviderStats {
+    pub name: String,
+    pub successes: u32,
+    pub failures: u32,
+    /// Failures since the last success
+    pub consecutive_failures: u32,
+    /// Latency of the last successful query
+    pub latency: Option<Duration>,
+    /// The circuit breaker is open, the provider is skipped until then
+    pub skip_until: Option<Instant>,
+}
+
+impl ProviderStats {
+    pub fn new(name: &str) -> ProviderStats {
+        ProviderStats {
+            name: name.to_owned(),
+            successes: 0,
+            failures: 0,
+            consecutive_failures: 0,
+            latency: None,
+            skip_until: None,
+        }
+    }
+}
+
+/// One provider in `WhatsMyIp::benchmark()`
+pub struct BenchmarkResult {
+    pub name: String,
+    /// Time to get the answer (or the error)
+    pub latency: Duration,
+    pub result: Result<MyIp, Error>,
+}
+
+/// A source for the external address
+pub trait Provider: Send + Sync {
+    /// Name used in logs and to select providers
+    fn name(&self) -> &str;
+
+    /// Query the provider, `ctx` holds the timeout and other settings
+    fn fetch(&self, ctx: &Context) -> Result<MyIp, Error>;
+
+    /// Query the provider for all the addresses it reports, for
+    /// services that return e.g. both IPv4 and IPv6 in one response
+    /// (defaults to the address from `fetch()`)
+    fn fetch_all(&self, ctx: &Context) -> Result<Vec<MyIp>, Error> {
+        self.fetch(ctx).map(|ip| vec![ip])
+    }
+
+    /// Address families this provider can report
+    /// (defaults to **Both**)
+    fn families(&self) -> IpFamily {
+        IpFamily::Both
+    }
+}
+
+#[cfg(feature = "http")]
+/// An HTTP service, the address is parsed from the response
+pub struct HttpProvider {
+    name: String,
+    url: String,
+    parser: Parser,
+    // Parser argument, e.g. the JSON field or header name
+    arg: String,
+    family: IpFamily,
+    pins: Vec<Pin>,
+}
+
+#[cfg(feature = "http")]
+/// Parses the response into one address, or all addresses in it
+enum Parser {
+    One(fn(&Context, &str, &str) -> Result<MyIp, Error>),
+    All(fn(&Context, &str, &str) -> Result<Vec<MyIp>, Error>),
+}
+
+#[cfg(feature = "http")]
+impl HttpProvider {
+    fn new(name: &str, url: &str, parser: Parser, arg: &str) -> HttpProvider {
+        HttpProvider {
+            name: name.to_owned(),
+            url: url.to_owned(),
+            parser: parser,
+            arg: arg.to_owned(),
+            family: IpFamily::Both,
+            pins: Vec::new(),
+        }
+    }
+
+    /// The service only answers over IPv4 or IPv6, e.g. because
+    /// it only has A or AAAA records
+    /// (defaults to **Both**)
+    pub fn family(mut self, family: IpFamily) -> HttpProvider {
+        self.family = family;
+        self
+    }
+
+    /// Only accept this certificate from the server, can be called
+    /// more than once to allow e.g. the current and the next one.
+    /// Use this with your own HTTPS service.
+    pub fn pin(mut self, pin: Pin) -> HttpProvider {
+        self.pins.push(pin);
+        self
+    }
+
+    /// The response body is the address as plain text
+    pub fn text(name: &str, url: &str) -> HttpProvider {
+        HttpProvider::new(name, url, Parser::One(http_ip_txt), "")
+    }
+
+    /// The response is a JSON object with the address in `field`
+    pub fn json(name: &str, url: &str, field: &str) -> HttpProvider {
+        HttpProvider::new(name, url, Parser::One(http_ip_json), field)
+    }
+
+    /// The address is in the response header `header`
+    pub fn header(name: &str, url: &str, header: &str) -> HttpProvider {
+        HttpProvider::new(name, url, Parser::One(http_ip_header), header)
+    }
+
+    /// The address is the first match of `pattern` in the body
+    pub fn regex(name: &str, url: &str, pattern: &str) -> HttpProvider {
+        HttpProvider::new(name, url, Parser::One(http_ip_regex), pattern)
+    }
+
+    /// The response body holds one or more addresses as plain
+    /// text, separated by whitespace
+    pub fn text_all(name: &str, url: &str) -> HttpProvider {
+        HttpProvider::new(name, url, Parser::All(http_ips_txt), "")
+    }
+
+    /// The response is a JSON object with addresses in each of
+    /// `fields`, e.g. `&["ipv4", "ipv6"]`, missing fields are ignored
+    pub fn json_all(name: &str, url: &str, fields: &[&str]) -> HttpProvider {
+        HttpProvider::new(name, url, Parser::All(http_ips_json), &fields.join(","))
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// `ctx` with the pins of this provider
+    fn context<'a>(&'a self, ctx: &Context<'a>) -> Context<'a> {
+        Context {
+            opts: ctx.opts,
+            timeout: ctx.timeout,
+            pins: &self.pins,
+        }
+    }
+}
+
+#[cfg(feature = "http")]
+impl Provider for HttpProvider {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn fetch(&self, ctx: &Context) -> Result<MyIp, Error> {
+        let ctx = &self.context(ctx);
+        match self.parser {
+            Parser::One(parser) => parser(ctx, &self.url, &self.arg),
+            Parser::All(parser) => parser(ctx, &self.url, &self.arg).map(|mut ips| ips.remove(0)),
+        }
+    }
+
+    fn families(&self) -> IpFamily {
+        self.family
+    }
+
+    fn fetch_all(&self, ctx: &Context) -> Result<Vec<MyIp>, Error> {
+        let ctx = &self.context(ctx);
+        match self.parser {
+            Parser::One(parser) => parser(ctx, &self.url, &self.arg).map(|ip| vec![ip]),
+            Parser::All(parser) => parser(ctx, &self.url, &self.arg),
+        }
+    }
+}
+
+/// One canned answer of a `MockProvider`
+#[derive(Clone, Debug)]
+enum MockAnswer {
+    Ips(Vec<MyIp>),
+    Http(u16),
+    Timeout,
+    Network,
+    Protocol(String),
+}
+
+/// A provider with canned answers, to test code that uses `WhatsMyIp`
+/// (e.g. IP change handling) without network access, see
+/// `WhatsMyIp::with_providers()`. Each query takes the next answer,
+/// the last one repeats.
+///
+/// ```
+/// use std::str::FromStr;
+/// use whatsmyip::{MockProvider, MyIp, WhatsMyIp};
+/// let old = MyIp::from_str("8.8.4.4").unwrap();
+/// let new = MyIp::from_str("8.8.8.8").unwrap();
+/// let opts = WhatsMyIp::with_providers(vec![
+///     Box::new(MockProvider::new("mock").ip(old).fail_timeout().ip(new)),
+/// ]);
+/// assert_eq!(opts.find().unwrap(), vec![old]);
+/// assert!(opts.find().is_err());
+/// assert_eq!(opts.find().unwrap(), vec![new]);
+/// ```
+pub struct MockProvider {
+    name: String,
+    answers: Vec<MockAnswer>,
+    next: AtomicUsize,
+    delay: Duration,
+    family: IpFamily,
+}
+
+impl MockProvider {
+    pub fn new(name: &str) -> MockProvider {
+        MockProvider {
+            name: name.to_owned(),
+            answers: Vec::new(),
+            next: AtomicUsize::new(0),
+            delay: Duration::from_secs(0),
+            family: IpFamily::Both,
+        }
+    }
+
+    /// Answer with `ip`
+    pub fn ip(self, ip: MyIp) -> MockProvider {
+        self.ips(&[ip])
+    }
+
+    /// Answer with all of `ips`, like services that report
+    /// both families at once
+    pub fn ips(mut self, ips: &[MyIp]) -> MockProvider {
+        self.answers.push(MockAnswer::Ips(ips.to_vec()));
+        self
+    }
+
+    /// Fail with `Error::Http`
+    pub fn fail_http(mut self, status: u16) -> MockProvider {
+        self.answers.push(MockAnswer::Http(status));
+        self
+    }
+
+    /// Fail with `Error::Timeout`
+    pub fn fail_timeout(mut self) -> MockProvider {
+        self.answers.push(MockAnswer::Timeout);
+        self
+    }
+
+    /// Fail with `Error::Network`, as if the connection was refused
+    pub fn fail_network(mut self) -> MockProvider {
+        self.answers.push(MockAnswer::Network);
+        self
+    }
+
+    /// Fail with `Error::Protocol`
+    pub fn fail(mut self, msg: &str) -> MockProvider {
+        self.answers.push(MockAnswer::Protocol(msg.to_owned()));
+        self
+    }
+
+    /// Wait before each answer, a longer delay than the provider
+    /// timeout fails with `Error::Timeout`
+    /// (defaults to **0**)
+    pub fn delay(mut self, delay: Duration) -> MockProvider {
+        self.delay = delay;
+        self
+    }
+
+    /// (defaults to **Both**)
+    pub fn family(mut self, family: IpFamily) -> MockProvider {
+        self.family = family;
+        self
+    }
+}
+
+impl Provider for MockProvider {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn fetch(&self, ctx: &Context) -> Result<MyIp, Error> {
+        self.fetch_all(ctx).map(|mut ips| ips.remove(0))
+    }
+
+    fn families(&self) -> IpFamily {
+        self.family
+    }
+
+    fn fetch_all(&self, ctx: &Context) -> Result<Vec<MyIp>, Error> {
+        match ctx.timeout {
+            Some(timeout) if timeout < self.delay => {
+                thread::sleep(timeout);
+                return Err(Error::Timeout);
+            },
+            _ => thread::sleep(self.delay),
+        }
+        if self.answers.is_empty() {
+            return Err(Error::Protocol(format!("No answers for {}", self.name)));
+        }
+        let idx = min(self.next.fetch_add(1, Ordering::SeqCst), self.answers.len() - 1);
+        match self.answers[idx].clone() {
+            MockAnswer::Ips(ref ips) if ips.is_empty() =>
+                Err(Error::Parse(format!("No address from {}", self.name))),
+            MockAnswer::Ips(ips) => Ok(ips),
+            MockAnswer::Http(status) => Err(Error::Http { status: status }),
+            MockAnswer::Timeout => Err(Error::Timeout),
+            MockAnswer::Network => Err(Error::Network(io::Error::new(io::ErrorKind::ConnectionRefused,
+                                                                     "Mock connection refused"))),
+            MockAnswer::Protocol(msg) => Err(Error::Protocol(msg)),
+        }
+    }
+}
+
+#[cfg(feature = "http")]
+/// The default providers, public HTTP services
+pub fn http_providers() -> Vec<Box<dyn Provider>> {
+    vec![
+        Box::new(HttpProvider::text("icanhazip", "http://icanhazip.com")),
+        Box::new(HttpProvider::text("icanhazip4", "http://ipv4.icanhazip.com")
+                    .family(IpFamily::V4)),
+        Box::new(HttpProvider::text("icanhazip6", "http://ipv6.icanhazip.com")
+                    .family(IpFamily::V6)),
+        Box::new(HttpProvider::text("dnsomatic", "http://myip.dnsomatic.com")
+                    .family(IpFamily::V4)),
+        Box::new(HttpProvider::text("whatismyipaddress", "http://bot.whatismyipaddress.com/")),
+        Box::new(HttpProvider::text("ipify", "https://api.ipify.org?format=text")
+                    .family(IpFamily::V4)),
+        Box::new(HttpProvider::text("ipify6", "https://api6.ipify.org?format=text")
+                    .family(IpFamily::V6)),
+        Box::new(HttpProvider::json("ip-api", "http://ip-api.com/json", "query")
+                    .family(IpFamily::V4)),
+        Box::new(HttpProvider::json("ipinfo", "https://ipinfo.io/json", "ip")),
+        Box::new(HttpProvider::json("ifconfig.co", "https://ifconfig.co/json", "ip")),
+    ]
+}
+
+/// The providers of `WhatsMyIp::new()`
+#[cfg(feature = "http")]
+pub(crate) fn default_providers() -> Vec<Box<dyn Provider>> {
+    http_providers()
+}
+
+/// No providers without the `http` feature, add your own
+#[cfg(not(feature = "http"))]
+pub(crate) fn default_providers() -> Vec<Box<dyn Provider>> {
+    Vec::new()
+}
+
+#[test]
+fn test_ip_family() {
+    use std::net::Ipv4Addr;
+    let ip = MyIp::V4(Ipv4Addr::new(192, 0, 2, 1));
+    assert!(IpFamily::V4.includes(&ip));
+    assert!(!IpFamily::V6.includes(&ip));
+    assert!(IpFamily::Both.includes(&ip));
+    assert!(IpFamily::V6.overlaps(IpFamily::Both));
+    assert!(!IpFamily::V6.overlaps(IpFamily::V4));
+}
@@ -0,0 +1,95 @@
+//!
+//! Python bindings, with the python feature
+//!
+//! Build the extension module with
+//! `cargo rustc --release --lib --features python --crate-type cdylib`
+//! and copy `target/release/libwhatsmyip.so` to `whatsmyip.so`
+//! (`whatsmyip.pyd` on Windows) somewhere on the Python path. On
+//! macOS also set `RUSTFLAGS="-C link-arg=-undefined -C link-arg=dynamic_lookup"`.
+//!
+//! ```python
+//! import whatsmyip
+//! print(whatsmyip.find())                  # ['192.0.2.1', '2001:db8::1']
+//! print(whatsmyip.find("v4", timeout=5))   # ['192.0.2.1']
+//! whatsmyip.monitor(lambda ips: print("New address", ips), interval=60)
+//! ```
+//!
+
+use std::cmp::min;
+use std::thread;
+use std::time::{Duration, Instant};
+use pyo3::prelude::*;
+use pyo3::exceptions::{PyException, PyValueError};
+use {WhatsMyIp, IpMonitor, IpFamily, MyIp};
+
+create_exception!(whatsmyip, WhatsMyIpError, PyException);
+
+fn family_from_str(family: &str) -> PyResult<IpFamily> {
+    match family {
+        "both" => Ok(IpFamily::Both),
+        "v4" => Ok(IpFamily::V4),
+        "v6" => Ok(IpFamily::V6),
+        _ => Err(PyValueError::new_err(format!("Invalid address family {}, use both, v4 or v6",
+                                                family))),
+    }
+}
+
+fn options(family: &str, timeout: Option<f64>) -> PyResult<WhatsMyIp> {
+    let mut opts = WhatsMyIp::new();
+    opts.family(try!(family_from_str(family)));
+    if let Some(timeout) = timeout {
+        opts.deadline(Some(Duration::from_millis((timeout * 1000.0) as u64)));
+    }
+    Ok(opts)
+}
+
+fn to_strings(ips: &[MyIp]) -> Vec<String> {
+    ips.iter().map(|ip| ip.to_string()).collect()
+}
+
+/// The external addresses as strings, family is "both", "v4" or "v6"
+/// and timeout the most seconds to wait for the sources. Raises
+/// WhatsMyIpError if no source answered.
+#[pyfunction]
+#[pyo3(signature = (family = "both", timeout = None))]
+fn find(py: Python, family: &str, timeout: Option<f64>) -> PyResult<Vec<String>> {
+    let opts = try!(options(family, timeout));
+    // Other Python threads run while the sources are queried
+    py.allow_threads(move || opts.find())
+        .map(|ips| to_strings(&ips))
+        .map_err(|err| WhatsMyIpError::new_err(err.to_string()))
+}
+
+/// Check the external addresses every interval seconds, forever,
+/// calling callback with the list of addresses on the first check and
+/// every time they change. Returns when callback raises an exception
+/// or on KeyboardInterrupt.
+#[pyfunction]
+#[pyo3(signature = (callback, interval = 300, family = "both"))]
+fn monitor(py: Python, callback: PyObject, interval: u64, family: &str) -> PyResult<()> {
+    let interval = Duration::from_secs(interval);
+    let mut monitor = IpMonitor::new(try!(options(family, None)));
+    loop {
+        match py.allow_threads(|| monitor.check()) {
+            Ok(Some(ips)) => {
+                try!(callback.call1(py, (to_strings(&ips),)));
+            },
+            Ok(None) => (),
+            Err(err) => info!("{}", err),
+        }
+        // Wake up every second for Ctrl-C
+        let start = Instant::now();
+        while let Some(left) = interval.checked_sub(start.elapsed()) {
+            try!(py.check_signals());
+            py.allow_threads(|| thread::sleep(min(left, Duration::from_secs(1))));
+        }
+    }
+}
+
+#[pymodule]
+fn whatsmyip(py: Python, m: &PyModule) -> PyResult<()> {
+    try!(m.add("WhatsMyIpError", py.get_type::<WhatsMyIpError>()));
+    try!(m.add_function(try!(wrap_pyfunction!(find, m))));
+    try!(m.add_function(try!(wrap_pyfunction!(monitor, m))));
+    Ok(())
+}
@@ -0,0 +1,117 @@
+//!
+//! Name resolution for HTTP providers, see `WhatsMyIp::resolver()`
+//!
+
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Resolves host names when connecting to HTTP providers, e.g.
+/// to use a specific DNS server when the system one is broken
+pub trait Resolver: Send + Sync {
+    /// The addresses of `host`, in the order to try them
+    fn resolve(&self, host: &str) -> io::Result<Vec<IpAddr>>;
+}
+
+/// The system resolver
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn resolve(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+        Ok(try!((host, 0).to_socket_addrs())
+            .map(|addr| addr.ip())
+            .collect())
+    }
+}
+
+/// Fixed addresses for some hosts, then the custom resolver if
+/// any, then the system one
+#[derive(Clone)]
+pub struct Hosts {
+    fixed: Vec<(String, IpAddr)>,
+    resolver: Option<Arc<dyn Resolver>>,
+}
+
+impl Hosts {
+    pub fn new() -> Hosts {
+        Hosts {
+            fixed: Vec::new(),
+            resolver: None,
+        }
+    }
+
+    pub fn insert(&mut self, host: &str, ip: IpAddr) {
+        self.fixed.push((host.to_lowercase(), ip));
+    }
+
+    pub fn set_resolver(&mut self, resolver: Option<Arc<dyn Resolver>>) {
+        self.resolver = resolver;
+    }
+
+    /// The socket addresses for `host`
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    pub fn lookup(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        let host = host.trim_left_matches('[').trim_right_matches(']');
+        if let Ok(ip) = IpAddr::from_str(host) {
+            return Ok(vec![SocketAddr::new(ip, port)]);
+        }
+
+        let fixed: Vec<SocketAddr> = self.fixed.iter()
+                                        .filter(|&&(ref name, _)| name.eq_ignore_ascii_case(host))
+                                        .map(|&(_, ip)| SocketAddr::new(ip, port))
+                                        .collect();
+        if !fixed.is_empty() {
+            return Ok(fixed);
+        }
+
+        let ips = match self.resolver {
+            Some(ref resolver) => try!(resolver.resolve(host)),
+            None => try!(SystemResolver.resolve(host)),
+        };
+        debug!("Resolved {} => {:?}", host, ips);
+        if ips.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::Other,
+                                      format!("Unable to resolve {}", host)));
+        }
+        Ok(ips.into_iter().map(|ip| SocketAddr::new(ip, port)).collect())
+    }
+}
+
+impl PartialEq for Hosts {
+    fn eq(&self, other: &Hosts) -> bool {
+        let same_resolver = match (&self.resolver, &other.resolver) {
+            (&Some(ref a), &Some(ref b)) => Arc::ptr_eq(a, b),
+            (&None, &None) => true,
+            _ => false,
+        };
+        self.fixed == other.fixed && same_resolver
+    }
+}
+
+impl fmt::Debug for Hosts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Hosts {{ fixed: {:?}, resolver: {} }}", self.fixed,
+               if self.resolver.is_some() { "custom" } else { "system" })
+    }
+}
+
+#[test]
+fn test_hosts_lookup() {
+    let mut hosts = Hosts::new();
+    hosts.insert("icanhazip.com", IpAddr::from_str("192.0.2.1").unwrap());
+    assert_eq!(hosts.lookup("ICANHAZIP.com", 80).unwrap(),
+               vec![SocketAddr::from_str("192.0.2.1:80").unwrap()]);
+    assert_eq!(hosts.lookup("[2001:db8::1]", 443).unwrap(),
+               vec![SocketAddr::from_str("[2001:db8::1]:443").unwrap()]);
+
+    struct Nowhere;
+    impl Resolver for Nowhere {
+        fn resolve(&self, _: &str) -> io::Result<Vec<IpAddr>> {
+            Ok(Vec::new())
+        }
+    }
+    hosts.set_resolver(Some(Arc::new(Nowhere)));
+    assert!(hosts.lookup("example.com", 80).is_err());
+}
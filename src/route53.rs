@@ -0,0 +1,233 @@
+//!
+//! Update AWS Route 53 records, signed with AWS Signature Version 4
+//!
+
+use std::io::Read;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crypto::digest::Digest;
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha2::Sha256;
+use hyper::header::Headers;
+use {MyIp, Error, Updater};
+use client::{self, ClientConfig};
+
+const ROUTE53_HOST: &'static str = "route53.amazonaws.com";
+// Route 53 is a global service, signed for us-east-1
+const ROUTE53_REGION: &'static str = "us-east-1";
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    let mut sha = Sha256::new();
+    sha.input(data);
+    sha.result_str()
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut hmac = Hmac::new(Sha256::new(), key);
+    hmac.input(data);
+    hmac.result().code().to_vec()
+}
+
+/// The time as `YYYYMMDDTHHMMSSZ`
+fn amz_date(t: SystemTime) -> String {
+    let secs = t.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+    // Civil date from days since the epoch, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}{:02}{:02}T{:02}{:02}{:02}Z", year, month, day,
+            rem / 3600, rem % 3600 / 60, rem % 60)
+}
+
+/// AWS credentials
+struct Credentials {
+    access_key: String,
+    secret_key: String,
+    session_token: Option<String>,
+}
+
+/// Sign a request, returns the headers to send along with `Host`.
+/// `headers` are extra headers to sign, lowercase and sorted.
+#[allow(clippy::too_many_arguments)]
+fn sign(creds: &Credentials, region: &str, service: &str, method: &str, host: &str,
+        path: &str, query: &str, headers: &[(&str, &str)], payload: &[u8],
+        date: &str) -> Vec<(String, String)> {
+    let mut signed = vec![("host".to_owned(), host.to_owned()),
+                          ("x-amz-date".to_owned(), date.to_owned())];
+    if let Some(ref token) = creds.session_token {
+        signed.push(("x-amz-security-token".to_owned(), token.clone()));
+    }
+    for &(name, value) in headers {
+        signed.push((name.to_owned(), value.to_owned()));
+    }
+    signed.sort();
+    let names: Vec<&str> = signed.iter().map(|&(ref name, _)| &name[..]).collect();
+    let signed_headers = names.join(";");
+
+    let mut canonical = format!("{}\n{}\n{}\n", method, path, query);
+    for &(ref name, ref value) in &signed {
+        canonical.push_str(&format!("{}:{}\n", name, value.trim()));
+    }
+    canonical.push_str(&format!("\n{}\n{}", signed_headers, sha256_hex(payload)));
+
+    let scope = format!("{}/{}/{}/aws4_request", &date[..8], region, service);
+    let to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", date, scope,
+                          sha256_hex(canonical.as_bytes()));
+    let mut key = hmac_sha256(format!("AWS4{}", creds.secret_key).as_bytes(), &date.as_bytes()[..8]);
+    key = hmac_sha256(&key, region.as_bytes());
+    key = hmac_sha256(&key, service.as_bytes());
+    key = hmac_sha256(&key, b"aws4_request");
+    let signature = hex(&hmac_sha256(&key, to_sign.as_bytes()));
+
+    let mut out: Vec<(String, String)> = signed.into_iter()
+                                            .filter(|&(ref name, _)| name != "host")
+                                            .collect();
+    out.push(("authorization".to_owned(),
+              format!("AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                      creds.access_key, scope, signed_headers, signature)));
+    out
+}
+
+/// Upserts the A and AAAA records of a name in a Route 53 hosted zone.
+/// Needs the `route53` feature.
+pub struct Route53 {
+    creds: Credentials,
+    zone_id: String,
+    record: String,
+    ttl: u32,
+    timeout: Duration,
+}
+
+impl Route53 {
+    /// `zone_id` is the hosted zone id (e.g. `Z1D633PJN98FT9`) and
+    /// `record` the full name to update
+    pub fn new(access_key: &str, secret_key: &str, zone_id: &str, record: &str) -> Route53 {
+        Route53 {
+            creds: Credentials {
+                access_key: access_key.to_owned(),
+                secret_key: secret_key.to_owned(),
+                session_token: None,
+            },
+            zone_id: zone_id.trim_left_matches("/hostedzone/").to_owned(),
+            record: record.to_owned(),
+            ttl: 300,
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Session token for temporary credentials
+    /// (defaults to **None**)
+    pub fn session_token(mut self, token: &str) -> Route53 {
+        self.creds.session_token = Some(token.to_owned());
+        self
+    }
+
+    /// TTL of the records in seconds
+    /// (defaults to **300**)
+    pub fn ttl(mut self, ttl: u32) -> Route53 {
+        self.ttl = ttl;
+        self
+    }
+
+    /// (defaults to **10 seconds**)
+    pub fn timeout(mut self, timeout: Duration) -> Route53 {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The ChangeResourceRecordSets request body, one UPSERT per
+    /// family using the first address of each
+    fn change_batch(&self, ips: &[MyIp]) -> String {
+        let mut changes = String::new();
+        let v4 = ips.iter().find(|ip| ip.is_ipv4());
+        let v6 = ips.iter().find(|ip| ip.is_ipv6());
+        for &(rtype, ip) in &[("A", v4), ("AAAA", v6)] {
+            if let Some(ip) = ip {
+                changes.push_str(&format!(
+                    "<Change><Action>UPSERT</Action><ResourceRecordSet>\
+                     <Name>{}</Name><Type>{}</Type><TTL>{}</TTL>\
+                     <ResourceRecords><ResourceRecord><Value>{}</Value></ResourceRecord></ResourceRecords>\
+                     </ResourceRecordSet></Change>",
+                    self.record, rtype, self.ttl, ip));
+            }
+        }
+        format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+                 <ChangeResourceRecordSetsRequest xmlns=\"https://route53.amazonaws.com/doc/2013-04-01/\">\
+                 <ChangeBatch><Changes>{}</Changes></ChangeBatch>\
+                 </ChangeResourceRecordSetsRequest>", changes)
+    }
+
+}
+
+impl Updater for Route53 {
+    fn name(&self) -> &str {
+        &self.record
+    }
+
+    fn update(&self, ip: &MyIp) -> Result<(), Error> {
+        self.update_all(&[*ip])
+    }
+
+    /// Point the record to `ips`
+    fn update_all(&self, ips: &[MyIp]) -> Result<(), Error> {
+        let path = format!("/2013-04-01/hostedzone/{}/rrset/", self.zone_id);
+        let body = self.change_batch(ips);
+        let date = amz_date(SystemTime::now());
+        let mut headers = Headers::new();
+        headers.set_raw("Content-Type", vec![b"text/xml".to_vec()]);
+        for (name, value) in sign(&self.creds, ROUTE53_REGION, "route53", "POST", ROUTE53_HOST,
+                                  &path, "", &[], body.as_bytes(), &date) {
+            headers.set_raw(name, vec![value.into_bytes()]);
+        }
+
+        let cli = client::new_client(&ClientConfig::new(Some(self.timeout)));
+        let mut res = try!(cli.post(&format!("https://{}{}", ROUTE53_HOST, path))
+                            .headers(headers)
+                            .body(&body)
+                            .send()
+                            .map_err(Error::from));
+        let mut s = String::new();
+        try!(res.read_to_string(&mut s)
+            .map_err(Error::from));
+        debug!("Route 53 {} => {} {}", self.record, res.status, s);
+        if !res.status.is_success() {
+            return Err(Error::Http { status: res.status.to_u16() });
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_amz_date() {
+    assert_eq!(amz_date(UNIX_EPOCH + Duration::from_secs(1440938160)), "20150830T123600Z");
+    assert_eq!(amz_date(UNIX_EPOCH + Duration::from_secs(951782400)), "20000229T000000Z");
+}
+
+#[test]
+fn test_sign() {
+    // get-vanilla from the AWS Signature Version 4 test suite
+    let creds = Credentials {
+        access_key: "AKIDEXAMPLE".to_owned(),
+        secret_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_owned(),
+        session_token: None,
+    };
+    let headers = sign(&creds, "us-east-1", "service", "GET", "example.amazonaws.com",
+                       "/", "", &[], b"", "20150830T123600Z");
+    assert_eq!(headers.last().unwrap().1,
+               "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+                SignedHeaders=host;x-amz-date, \
+                Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31");
+}
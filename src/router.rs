@@ -0,0 +1,70 @@
+//!
+//! Scrape the WAN address from the router status page
+//!
+
+use std::io::Read;
+use std::time::Duration;
+use hyper::header::{Authorization, Basic};
+use {MyIp, Error};
+use client::{self, ClientConfig};
+use http::ip_from_regex;
+
+/// A router status page with the WAN address
+///
+/// ```no_run
+/// use whatsmyip::{WhatsMyIp, RouterScrape};
+/// let mut router = RouterScrape::new("http://192.168.1.1/status.html",
+///                                    r"WAN IP.*?(\d+\.\d+\.\d+\.\d+)");
+/// router.credentials("admin", "secret");
+/// let addrs = WhatsMyIp::new()
+///                 .router_scrape(&router)
+///                 .find().unwrap();
+/// ```
+#[derive(Clone)]
+pub struct RouterScrape {
+    url: String,
+    pattern: String,
+    credentials: Option<(String, String)>,
+}
+
+impl RouterScrape {
+    /// The address is the first match of `pattern` in the page
+    /// at `url` (or its first capture group)
+    pub fn new(url: &str, pattern: &str) -> RouterScrape {
+        RouterScrape {
+            url: url.to_owned(),
+            pattern: pattern.to_owned(),
+            credentials: None,
+        }
+    }
+
+    /// Use HTTP Basic authentication
+    pub fn credentials(&mut self, username: &str, password: &str) -> &mut Self {
+        self.credentials = Some((username.to_owned(), password.to_owned()));
+        self
+    }
+
+    /// Fetch the status page and extract the address
+    pub fn fetch(&self, timeout: Option<Duration>) -> Result<MyIp, Error> {
+        let cli = client::new_client(&ClientConfig::new(timeout));
+        let mut req = cli.get(&self.url);
+        if let Some((ref username, ref password)) = self.credentials {
+            req = req.header(Authorization(Basic {
+                username: username.clone(),
+                password: Some(password.clone()),
+            }));
+        }
+        let mut res = try!(req.send()
+                        .map_err(Error::from));
+        if !res.status.is_success() {
+            return Err(Error::Http { status: res.status.to_u16() });
+        }
+
+        let mut s = String::new();
+        try!(res.read_to_string(&mut s)
+            .map_err(Error::from));
+        let ip = try!(ip_from_regex(&s, &self.pattern));
+        debug!("{} => {}", &self.url, ip);
+        Ok(ip)
+    }
+}
@@ -0,0 +1,150 @@
+//!
+//! SIP servers add the address they see us with to the Via header
+//! of their responses, when asked with `rport` (RFC 3581)
+//!
+
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::time::Duration;
+use rand::{thread_rng, Rng};
+use {MyIp, Error, ip_from_str};
+use bind::Bind;
+use error;
+
+fn random_token() -> String {
+    thread_rng().gen_ascii_chars().take(16).collect()
+}
+
+/// An OPTIONS request, `local` is the address we send from
+fn options_request(host: &str, local: &SocketAddr, branch: &str) -> String {
+    let local_host = match *local {
+        SocketAddr::V4(addr) => format!("{}", addr.ip()),
+        SocketAddr::V6(addr) => format!("[{}]", addr.ip()),
+    };
+    format!("OPTIONS sip:{host} SIP/2.0\r\n\
+             Via: SIP/2.0/UDP {local}:{port};rport;branch=z9hG4bK{branch}\r\n\
+             Max-Forwards: 70\r\n\
+             From: <sip:whatsmyip@{local}>;tag={tag}\r\n\
+             To: <sip:{host}>\r\n\
+             Call-ID: {callid}@{local}\r\n\
+             CSeq: 1 OPTIONS\r\n\
+             Accept: application/sdp\r\n\
+             Content-Length: 0\r\n\r\n",
+            host=host, local=local_host, port=local.port(), branch=branch,
+            tag=random_token(), callid=random_token())
+}
+
+/// Returns the address and port from the `received` and `rport`
+/// parameters of our Via header
+fn parse_via(response: &str, branch: &str) -> Result<(MyIp, Option<u16>), Error> {
+    if !response.starts_with("SIP/2.0 ") {
+        return Err(Error::Parse("Invalid SIP response".to_owned()));
+    }
+    for line in response.lines() {
+        let mut parts = line.splitn(2, ':');
+        let name = parts.next().unwrap_or("").trim().to_lowercase();
+        // v is the compact form
+        if name != "via" && name != "v" {
+            continue;
+        }
+        let value = parts.next().unwrap_or("");
+        if !value.contains(branch) {
+            continue;
+        }
+
+        let mut params = value.split(';');
+        // SIP/2.0/UDP host:port
+        let sent_by = params.next().unwrap_or("").trim()
+                        .split_once(' ').map_or("", |(_, sent_by)| sent_by).trim().to_owned();
+        let mut received = None;
+        let mut rport = None;
+        for param in params {
+            let mut kv = param.trim().splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                (Some("received"), Some(val)) => received = Some(val.to_owned()),
+                (Some("rport"), Some(val)) => rport = val.parse::<u16>().ok(),
+                _ => (),
+            }
+        }
+
+        // Without received the server saw us at the sent-by address
+        let host = match received {
+            Some(host) => host,
+            None => match sent_by.rfind(':') {
+                Some(pos) if !sent_by[pos..].contains(']') => sent_by[..pos].to_owned(),
+                _ => sent_by,
+            },
+        };
+        let ip = try!(ip_from_str(host.trim_matches(|c| c == '[' || c == ']')));
+        return Ok((ip, rport));
+    }
+    Err(Error::Parse("No Via header in SIP response".to_owned()))
+}
+
+fn sip_request(host: &str, addr: &SocketAddr, bind: Bind,
+               timeout: Option<Duration>) -> Result<MyIp, Error> {
+    let socket = try!(bind.udp(addr)
+                        .map_err(Error::from));
+    try!(socket.set_read_timeout(timeout)
+            .map_err(Error::from));
+    // Connect to learn the local address for the Via header
+    try!(socket.connect(addr)
+            .map_err(Error::from));
+    let local = try!(socket.local_addr()
+                        .map_err(Error::from));
+
+    let branch = random_token();
+    try!(socket.send(options_request(host, &local, &branch).as_bytes())
+            .map_err(Error::from));
+
+    let mut buf = [0u8; 4096];
+    loop {
+        let len = try!(socket.recv(&mut buf)
+                        .map_err(Error::from));
+        let response = String::from_utf8_lossy(&buf[..len]);
+        // Skip provisional responses
+        if response.starts_with("SIP/2.0 1") {
+            continue;
+        }
+        let (ip, rport) = try!(parse_via(&response, &branch));
+        if let Some(port) = rport {
+            debug!("SIP {} => rport {}", host, port);
+        }
+        return Ok(ip);
+    }
+}
+
+/// Send an OPTIONS request to the SIP `server` (host:port) from `bind`
+pub fn sip_ip(server: &str, bind: Bind, timeout: Option<Duration>) -> Result<MyIp, Error> {
+    let host = match server.rfind(':') {
+        Some(pos) if !server[pos..].contains(']') => &server[..pos],
+        _ => server,
+    };
+    let addrs = try!(server.to_socket_addrs()
+                        .map_err(Error::from));
+    let mut last_err = error::unresolved(server);
+    for addr in addrs {
+        match sip_request(host, &addr, bind, timeout) {
+            Ok(ip) => {
+                debug!("SIP {} => {}", server, ip);
+                return Ok(ip);
+            },
+            Err(err) => last_err = err,
+        }
+    }
+    Err(last_err)
+}
+
+#[test]
+fn test_parse_via() {
+    let res = "SIP/2.0 200 OK\r\n\
+               Via: SIP/2.0/UDP 10.0.0.2:5060;rport=40123;branch=z9hG4bKabc;received=192.0.2.1\r\n\
+               CSeq: 1 OPTIONS\r\n\r\n";
+    let (ip, rport) = parse_via(res, "abc").unwrap();
+    assert!(ip == MyIp::V4("192.0.2.1".parse().unwrap()));
+    assert_eq!(rport, Some(40123));
+
+    let res = "SIP/2.0 200 OK\r\nv: SIP/2.0/UDP 192.0.2.7:5060;branch=z9hG4bKabc\r\n\r\n";
+    let (ip, rport) = parse_via(res, "abc").unwrap();
+    assert!(ip == MyIp::V4("192.0.2.7".parse().unwrap()));
+    assert_eq!(rport, None);
+}
@@ -0,0 +1,100 @@
+//!
+//! The last known address, saved to disk
+//!
+//! The file holds the time of the check in seconds since the epoch
+//! followed by one address per line.
+//!
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use {MyIp, Error};
+
+/// The addresses from the last confirmed check
+#[derive(Clone, Debug, PartialEq)]
+pub struct State {
+    pub ips: Vec<MyIp>,
+    /// When the addresses were confirmed
+    pub timestamp: SystemTime,
+}
+
+impl State {
+    pub fn new(ips: Vec<MyIp>) -> State {
+        State {
+            ips: ips,
+            timestamp: SystemTime::now(),
+        }
+    }
+
+    fn parse(s: &str) -> Result<State, Error> {
+        let mut lines = s.lines().map(str::trim).filter(|line| !line.is_empty());
+        let secs = match lines.next() {
+            Some(line) => try!(u64::from_str(line).map_err(Error::from)),
+            None => return Err(Error::Parse("Empty state file".to_owned())),
+        };
+        let mut ips = Vec::new();
+        for line in lines {
+            ips.push(try!(MyIp::from_str(line)));
+        }
+        Ok(State {
+            ips: ips,
+            timestamp: UNIX_EPOCH + Duration::from_secs(secs),
+        })
+    }
+
+    fn contents(&self) -> String {
+        let secs = self.timestamp.duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0);
+        let mut s = format!("{}\n", secs);
+        for ip in &self.ips {
+            s.push_str(&format!("{}\n", ip));
+        }
+        s
+    }
+
+    /// Read the state file, None if it does not exist yet
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Option<State>, Error> {
+        let mut f = match File::open(path.as_ref()) {
+            Ok(f) => f,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(Error::from(err)),
+        };
+        let mut s = String::new();
+        try!(f.read_to_string(&mut s)
+            .map_err(Error::from));
+        State::parse(&s).map(Some)
+    }
+
+    /// Write the state file, a temporary file is renamed over the
+    /// old one so readers never see a partial write
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        {
+            let mut f = try!(File::create(&tmp)
+                            .map_err(Error::from));
+            try!(f.write_all(self.contents().as_bytes())
+                .map_err(Error::from));
+        }
+        fs::rename(&tmp, path)
+            .map_err(Error::from)
+    }
+}
+
+#[test]
+fn test_state_file() {
+    use std::env;
+    use std::net::Ipv4Addr;
+    let path = env::temp_dir().join(format!("whatsmyip-state-{}", ::std::process::id()));
+    assert!(State::load(&path).unwrap().is_none());
+    let state = State {
+        ips: vec![MyIp::V4(Ipv4Addr::new(192, 0, 2, 1)), MyIp::from_str("2001:db8::1").unwrap()],
+        timestamp: UNIX_EPOCH + Duration::from_secs(1500000000),
+    };
+    state.save(&path).unwrap();
+    assert_eq!(State::load(&path).unwrap(), Some(state));
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,58 @@
+//!
+//! Answers as they arrive, see `WhatsMyIp::find_stream()`
+//!
+
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use {WhatsMyIp, IpReport};
+
+/// Yields each answer as soon as a source responds, ends once
+/// all sources were tried (or `fast()` found an address)
+pub struct FindStream {
+    rx: Receiver<IpReport>,
+}
+
+/// Start the search in a new thread
+pub fn spawn(opts: WhatsMyIp) -> FindStream {
+    let (tx, rx) = channel();
+    thread::spawn(move || {
+        let mut found = opts.found();
+        found.tx = Some(tx);
+        opts.collect_into(found, opts.igd, false);
+    });
+    FindStream {
+        rx: rx,
+    }
+}
+
+impl Iterator for FindStream {
+    type Item = IpReport;
+
+    fn next(&mut self) -> Option<IpReport> {
+        self.rx.recv().ok()
+    }
+}
+
+#[test]
+fn test_find_stream() {
+    use std::net::Ipv4Addr;
+    use {MyIp, Error, Provider, Context, Source};
+
+    struct Fixed;
+    impl Provider for Fixed {
+        fn name(&self) -> &str {
+            "fixed"
+        }
+
+        fn fetch(&self, _: &Context) -> Result<MyIp, Error> {
+            Ok(MyIp::V4(Ipv4Addr::new(192, 0, 2, 1)))
+        }
+    }
+
+    let mut opts = WhatsMyIp::new();
+    opts.igd(false).stun(false).dns(false).public_only(false).clear_providers().provider(Box::new(Fixed));
+    let reports: Vec<IpReport> = opts.find_stream().collect();
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].ip, MyIp::V4(Ipv4Addr::new(192, 0, 2, 1)));
+    assert_eq!(reports[0].source, Source::Provider("fixed".to_owned()));
+}
@@ -0,0 +1,372 @@
+//!
+//! Minimal STUN client (RFC 5389), only Binding requests are supported,
+//! and just enough TURN (RFC 5766) to Allocate and learn the
+//! reflexive address
+//!
+
+use std::net::{UdpSocket, SocketAddr, ToSocketAddrs, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+use std::cmp::min;
+use rand::{thread_rng, Rng};
+use crypto::digest::Digest;
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::md5::Md5;
+use crypto::sha1::Sha1;
+use {MyIp, Error};
+use bind::Bind;
+use error;
+
+const MAGIC_COOKIE: u32 = 0x2112A442;
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_RESPONSE: u16 = 0x0101;
+const ALLOCATE_REQUEST: u16 = 0x0003;
+const ALLOCATE_RESPONSE: u16 = 0x0103;
+const ALLOCATE_ERROR: u16 = 0x0113;
+const REFRESH_REQUEST: u16 = 0x0004;
+const ATTR_MAPPED_ADDRESS: u16 = 0x0001;
+const ATTR_USERNAME: u16 = 0x0006;
+const ATTR_MESSAGE_INTEGRITY: u16 = 0x0008;
+const ATTR_ERROR_CODE: u16 = 0x0009;
+const ATTR_LIFETIME: u16 = 0x000d;
+const ATTR_REALM: u16 = 0x0014;
+const ATTR_NONCE: u16 = 0x0015;
+const ATTR_REQUESTED_TRANSPORT: u16 = 0x0019;
+const ATTR_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+/// Protocol number for UDP
+const TRANSPORT_UDP: u8 = 17;
+
+/// Public STUN servers, used by default
+pub const STUN_SERVERS: &'static [&'static str] = &[
+    "stun.l.google.com:19302",
+    "stun1.l.google.com:19302",
+    "stun2.l.google.com:19302",
+    "stun.stunprotocol.org:3478",
+    ];
+
+fn read_u16(buf: &[u8], pos: usize) -> u16 {
+    (buf[pos] as u16) << 8 | buf[pos+1] as u16
+}
+
+fn read_u32(buf: &[u8], pos: usize) -> u32 {
+    (read_u16(buf, pos) as u32) << 16 | read_u16(buf, pos+2) as u32
+}
+
+fn push_u16(buf: &mut Vec<u8>, val: u16) {
+    buf.push((val >> 8) as u8);
+    buf.push(val as u8);
+}
+
+fn set_length(msg: &mut [u8], len: usize) {
+    msg[2] = (len >> 8) as u8;
+    msg[3] = len as u8;
+}
+
+/// Build a message, if `key` is given a MESSAGE-INTEGRITY
+/// attribute is appended
+fn message(msg_type: u16, tid: &[u8; 12], attrs: &[(u16, &[u8])], key: Option<&[u8]>) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(20);
+    push_u16(&mut msg, msg_type);
+    // Length is set below
+    push_u16(&mut msg, 0);
+    for i in 0..4 {
+        msg.push((MAGIC_COOKIE >> (24 - 8*i)) as u8);
+    }
+    msg.extend_from_slice(tid);
+
+    for &(attr, val) in attrs {
+        push_u16(&mut msg, attr);
+        push_u16(&mut msg, val.len() as u16);
+        msg.extend_from_slice(val);
+        // Attributes are padded to 4 bytes
+        while msg.len() % 4 != 0 {
+            msg.push(0);
+        }
+    }
+
+    if let Some(key) = key {
+        // The length must already account for the integrity attribute
+        let len = msg.len() - 20 + 24;
+        set_length(&mut msg, len);
+        let mut hmac = Hmac::new(Sha1::new(), key);
+        hmac.input(&msg);
+        push_u16(&mut msg, ATTR_MESSAGE_INTEGRITY);
+        push_u16(&mut msg, 20);
+        msg.extend_from_slice(hmac.result().code());
+    }
+    let len = msg.len() - 20;
+    set_length(&mut msg, len);
+    msg
+}
+
+fn binding_request(tid: &[u8; 12]) -> Vec<u8> {
+    message(BINDING_REQUEST, tid, &[], None)
+}
+
+/// Parse an (optionally XOR'ed) address attribute value
+fn parse_address(val: &[u8], tid: &[u8; 12], xor: bool) -> Result<MyIp, Error> {
+    if val.len() < 4 {
+        return Err(Error::Parse("Truncated STUN address attribute".to_owned()));
+    }
+    let mut cookie_tid = Vec::with_capacity(16);
+    for i in 0..4 {
+        cookie_tid.push((MAGIC_COOKIE >> (24 - 8*i)) as u8);
+    }
+    cookie_tid.extend_from_slice(tid);
+
+    match val[1] {
+        0x01 if val.len() >= 8 => {
+            let mut octets = [0u8; 4];
+            for i in 0..4 {
+                octets[i] = if xor { val[4+i] ^ cookie_tid[i] } else { val[4+i] };
+            }
+            Ok(MyIp::V4(Ipv4Addr::new(octets[0], octets[1], octets[2], octets[3])))
+        },
+        0x02 if val.len() >= 20 => {
+            let mut segments = [0u16; 8];
+            for i in 0..8 {
+                let hi = if xor { val[4+2*i] ^ cookie_tid[2*i] } else { val[4+2*i] };
+                let lo = if xor { val[5+2*i] ^ cookie_tid[2*i+1] } else { val[5+2*i] };
+                segments[i] = (hi as u16) << 8 | lo as u16;
+            }
+            Ok(MyIp::V6(Ipv6Addr::new(segments[0], segments[1], segments[2], segments[3],
+                                      segments[4], segments[5], segments[6], segments[7])))
+        },
+        family => Err(Error::Parse(format!("Invalid STUN address family {}", family))),
+    }
+}
+
+/// Attribute types and values
+type Attributes<'a> = Vec<(u16, &'a [u8])>;
+
+/// Check the header and split a message into its attributes,
+/// returns the message type and the attributes
+fn parse_message<'a>(buf: &'a [u8], tid: &[u8; 12]) -> Result<(u16, Attributes<'a>), Error> {
+    if buf.len() < 20 {
+        return Err(Error::Parse("Truncated STUN response".to_owned()));
+    }
+    if read_u32(buf, 4) != MAGIC_COOKIE || &buf[8..20] != tid {
+        return Err(Error::Parse("STUN transaction mismatch".to_owned()));
+    }
+
+    let end = min(buf.len(), 20 + read_u16(buf, 2) as usize);
+    let mut pos = 20;
+    let mut attrs = Vec::new();
+    while pos + 4 <= end {
+        let attr = read_u16(buf, pos);
+        let len = read_u16(buf, pos+2) as usize;
+        if pos + 4 + len > end {
+            break;
+        }
+        attrs.push((attr, &buf[pos+4..pos+4+len]));
+        // Attributes are padded to 4 bytes
+        pos += 4 + len.div_ceil(4) * 4;
+    }
+    Ok((read_u16(buf, 0), attrs))
+}
+
+/// Find the (XOR-)MAPPED-ADDRESS in the attributes
+fn mapped_address(attrs: &[(u16, &[u8])], tid: &[u8; 12]) -> Result<MyIp, Error> {
+    let mut mapped = None;
+    for &(attr, val) in attrs {
+        match attr {
+            // XOR-MAPPED-ADDRESS always takes precedence
+            ATTR_XOR_MAPPED_ADDRESS => return parse_address(val, tid, true),
+            ATTR_MAPPED_ADDRESS => mapped = Some(parse_address(val, tid, false)),
+            _ => (),
+        }
+    }
+    mapped.unwrap_or(Err(Error::Parse("No mapped address in STUN response".to_owned())))
+}
+
+/// Extract the mapped address from a Binding response
+fn parse_response(buf: &[u8], tid: &[u8; 12]) -> Result<MyIp, Error> {
+    let (msg_type, attrs) = try!(parse_message(buf, tid));
+    if msg_type != BINDING_RESPONSE {
+        return Err(Error::Parse(format!("Unexpected STUN message type {:#x}", msg_type)));
+    }
+    mapped_address(&attrs, tid)
+}
+
+fn bind_socket(addr: &SocketAddr, bind: Bind, timeout: Option<Duration>) -> Result<UdpSocket, Error> {
+    let socket = try!(bind.udp(addr)
+                        .map_err(Error::from));
+    try!(socket.set_read_timeout(timeout)
+            .map_err(Error::from));
+    Ok(socket)
+}
+
+fn stun_request(addr: &SocketAddr, bind: Bind, timeout: Option<Duration>) -> Result<MyIp, Error> {
+    let socket = try!(bind_socket(addr, bind, timeout));
+
+    let mut tid = [0u8; 12];
+    thread_rng().fill_bytes(&mut tid);
+    try!(socket.send_to(&binding_request(&tid), addr)
+            .map_err(Error::from));
+
+    let mut buf = [0u8; 512];
+    let (len, _) = try!(socket.recv_from(&mut buf)
+                        .map_err(Error::from));
+    parse_response(&buf[..len], &tid)
+}
+
+/// Send a STUN Binding request to `server` (host:port) from `bind`
+/// and return the reflexive address
+pub fn stun_ip(server: &str, bind: Bind, timeout: Option<Duration>) -> Result<MyIp, Error> {
+    let addrs = try!(server.to_socket_addrs()
+                        .map_err(Error::from));
+    let mut last_err = error::unresolved(server);
+    for addr in addrs {
+        match stun_request(&addr, bind, timeout) {
+            Ok(ip) => {
+                debug!("STUN {} => {}", server, ip);
+                return Ok(ip);
+            },
+            Err(err) => last_err = err,
+        }
+    }
+    Err(last_err)
+}
+
+/// A TURN server and its long-term credentials
+#[derive(Clone)]
+pub struct TurnServer {
+    /// Server address (host:port)
+    pub server: String,
+    pub username: String,
+    pub password: String,
+}
+
+fn transaction(socket: &UdpSocket, addr: &SocketAddr, msg: &[u8],
+               buf: &mut [u8]) -> Result<usize, Error> {
+    try!(socket.send_to(msg, addr)
+            .map_err(Error::from));
+    let (len, _) = try!(socket.recv_from(buf)
+                        .map_err(Error::from));
+    Ok(len)
+}
+
+fn find_attr<'a>(attrs: &[(u16, &'a [u8])], attr: u16) -> Option<&'a [u8]> {
+    attrs.iter().find(|&&(a, _)| a == attr).map(|&(_, val)| val)
+}
+
+fn turn_request(turn: &TurnServer, addr: &SocketAddr, bind: Bind,
+                timeout: Option<Duration>) -> Result<MyIp, Error> {
+    let socket = try!(bind_socket(addr, bind, timeout));
+    let transport = [TRANSPORT_UDP, 0, 0, 0];
+    let mut buf = [0u8; 1024];
+
+    // The first Allocate is unauthenticated, the server answers with
+    // 401 and the realm/nonce to use
+    let mut tid = [0u8; 12];
+    thread_rng().fill_bytes(&mut tid);
+    let req = message(ALLOCATE_REQUEST, &tid,
+                      &[(ATTR_REQUESTED_TRANSPORT, &transport)], None);
+    let len = try!(transaction(&socket, addr, &req, &mut buf));
+    let (msg_type, attrs) = try!(parse_message(&buf[..len], &tid));
+    if msg_type == ALLOCATE_RESPONSE {
+        // No authentication required
+        return mapped_address(&attrs, &tid);
+    }
+    if msg_type != ALLOCATE_ERROR {
+        return Err(Error::Parse(format!("Unexpected TURN message type {:#x}", msg_type)));
+    }
+    let realm = try!(find_attr(&attrs, ATTR_REALM)
+                        .ok_or(Error::Parse("No REALM in TURN response".to_owned()))).to_vec();
+    let nonce = try!(find_attr(&attrs, ATTR_NONCE)
+                        .ok_or(Error::Parse("No NONCE in TURN response".to_owned()))).to_vec();
+
+    // Long-term credential key is MD5(username:realm:password)
+    let mut md5 = Md5::new();
+    md5.input(turn.username.as_bytes());
+    md5.input(b":");
+    md5.input(&realm);
+    md5.input(b":");
+    md5.input(turn.password.as_bytes());
+    let mut key = [0u8; 16];
+    md5.result(&mut key);
+
+    thread_rng().fill_bytes(&mut tid);
+    let req = message(ALLOCATE_REQUEST, &tid,
+                      &[(ATTR_REQUESTED_TRANSPORT, &transport),
+                        (ATTR_USERNAME, turn.username.as_bytes()),
+                        (ATTR_REALM, &realm),
+                        (ATTR_NONCE, &nonce)],
+                      Some(&key));
+    let len = try!(transaction(&socket, addr, &req, &mut buf));
+    let (msg_type, attrs) = try!(parse_message(&buf[..len], &tid));
+    if msg_type != ALLOCATE_RESPONSE {
+        let code = match find_attr(&attrs, ATTR_ERROR_CODE) {
+            Some(val) if val.len() >= 4 => val[2] as u16 * 100 + val[3] as u16,
+            _ => 0,
+        };
+        return Err(Error::Protocol(format!("TURN Allocate failed with error {}", code)));
+    }
+    let ip = mapped_address(&attrs, &tid);
+
+    // Release the allocation, we only wanted the reflexive address
+    thread_rng().fill_bytes(&mut tid);
+    let req = message(REFRESH_REQUEST, &tid,
+                      &[(ATTR_LIFETIME, &[0, 0, 0, 0]),
+                        (ATTR_USERNAME, turn.username.as_bytes()),
+                        (ATTR_REALM, &realm),
+                        (ATTR_NONCE, &nonce)],
+                      Some(&key));
+    if let Err(err) = transaction(&socket, addr, &req, &mut buf) {
+        info!("Unable to release TURN allocation: {}", err);
+    }
+    ip
+}
+
+/// Allocate a relay in the TURN server from `bind` and return the
+/// reflexive address from the Allocate response
+pub fn turn_ip(turn: &TurnServer, bind: Bind, timeout: Option<Duration>) -> Result<MyIp, Error> {
+    let addrs = try!(turn.server.to_socket_addrs()
+                        .map_err(Error::from));
+    let mut last_err = error::unresolved(&turn.server);
+    for addr in addrs {
+        match turn_request(turn, &addr, bind, timeout) {
+            Ok(ip) => {
+                debug!("TURN {} => {}", turn.server, ip);
+                return Ok(ip);
+            },
+            Err(err) => last_err = err,
+        }
+    }
+    Err(last_err)
+}
+
+#[test]
+fn test_parse_xor_mapped_address() {
+    let tid = [1u8, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+    let mut res = vec![0x01, 0x01, 0x00, 0x0c, 0x21, 0x12, 0xa4, 0x42];
+    res.extend_from_slice(&tid);
+    // XOR-MAPPED-ADDRESS 192.0.2.1:32853
+    res.extend_from_slice(&[0x00, 0x20, 0x00, 0x08,
+                            0x00, 0x01, 0xa1, 0x47, 0xe1, 0x12, 0xa6, 0x43]);
+    assert!(parse_response(&res, &tid).unwrap() == MyIp::V4(Ipv4Addr::new(192, 0, 2, 1)));
+}
+
+#[test]
+fn test_message_integrity() {
+    let tid = [0u8; 12];
+    let msg = message(ALLOCATE_REQUEST, &tid, &[(ATTR_USERNAME, b"user")], Some(b"key"));
+    // Header + padded USERNAME + MESSAGE-INTEGRITY
+    assert_eq!(msg.len(), 20 + 8 + 24);
+    assert_eq!(read_u16(&msg, 2) as usize, msg.len() - 20);
+    let (msg_type, attrs) = parse_message(&msg, &tid).unwrap();
+    assert_eq!(msg_type, ALLOCATE_REQUEST);
+    assert_eq!(find_attr(&attrs, ATTR_USERNAME), Some(&b"user"[..]));
+    assert_eq!(find_attr(&attrs, ATTR_MESSAGE_INTEGRITY).map(|val| val.len()), Some(20));
+}
+
+#[test]
+fn test_stun_bind_family() {
+    use std::io;
+    use IpFamily;
+    // An IPv4 server with only IPv6 allowed, nothing is sent
+    match stun_ip("192.0.2.1:3478", Bind::Any.only(IpFamily::V6), Some(Duration::from_millis(10))) {
+        Err(Error::Network(ref err)) if err.kind() == io::ErrorKind::AddrNotAvailable => (),
+        res => panic!("{:?}", res),
+    }
+}
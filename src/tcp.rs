@@ -0,0 +1,56 @@
+//!
+//! Services that write the client address on a plain TCP
+//! connection and close it
+//!
+
+use std::io;
+use std::io::Read;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+use {MyIp, Error, ip_from_str};
+
+/// Connect to the first address of `host` that answers within `timeout`
+pub fn connect(host: &str, port: u16, timeout: Duration) -> io::Result<TcpStream> {
+    let mut last_err = io::Error::new(io::ErrorKind::Other,
+                                      format!("Unable to resolve {}", host));
+    for addr in try!((host, port).to_socket_addrs()) {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = err,
+        }
+    }
+    Err(last_err)
+}
+
+/// A service that writes the address as plain text and closes
+/// the connection
+#[derive(Clone)]
+pub struct TcpTextProvider {
+    pub host: String,
+    pub port: u16,
+}
+
+impl TcpTextProvider {
+    pub fn new(host: &str, port: u16) -> TcpTextProvider {
+        TcpTextProvider {
+            host: host.to_owned(),
+            port: port,
+        }
+    }
+
+    /// Connect and read the address, `timeout` applies to
+    /// both the connection and the read
+    pub fn fetch(&self, timeout: Duration) -> Result<MyIp, Error> {
+        let stream = try!(connect(&self.host, self.port, timeout)
+                            .map_err(Error::from));
+        try!(stream.set_read_timeout(Some(timeout))
+                .map_err(Error::from));
+
+        // An address is never this long
+        let mut s = String::new();
+        try!(stream.take(256).read_to_string(&mut s)
+                .map_err(Error::from));
+        debug!("{}:{} => {}", &self.host, self.port, &s);
+        ip_from_str(&s)
+    }
+}
@@ -0,0 +1,119 @@
+//!
+//! rustls backend for hyper, with the `rustls-tls` feature
+//!
+
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use hyper;
+use hyper::net::{HttpStream, NetworkStream, Ssl};
+use pin::PeerCertificate;
+use rustls::{self, ClientConfig, ClientSession, Session};
+use webpki::DNSNameRef;
+use webpki_roots;
+
+/// Wraps client connections with rustls, trusting the Mozilla
+/// root certificates
+#[derive(Clone)]
+pub struct Rustls {
+    config: Arc<ClientConfig>,
+}
+
+impl Default for Rustls {
+    fn default() -> Rustls {
+        let mut config = ClientConfig::new();
+        config.root_store.add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+        Rustls {
+            config: Arc::new(config),
+        }
+    }
+}
+
+/// A TLS connection, hyper needs its streams to be `Clone`
+#[derive(Clone)]
+pub struct TlsStream {
+    inner: Arc<Mutex<(ClientSession, HttpStream)>>,
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut inner = self.inner.lock().unwrap();
+        let (ref mut session, ref mut stream) = *inner;
+        match rustls::Stream::new(session, stream).read(buf) {
+            // The server closed without close_notify, most
+            // servers do this after the response
+            Err(ref err) if err.kind() == io::ErrorKind::ConnectionAborted => Ok(0),
+            res => res,
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut inner = self.inner.lock().unwrap();
+        let (ref mut session, ref mut stream) = *inner;
+        rustls::Stream::new(session, stream).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let (ref mut session, ref mut stream) = *inner;
+        rustls::Stream::new(session, stream).flush()
+    }
+}
+
+impl NetworkStream for TlsStream {
+    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+        self.inner.lock().unwrap().1.peer_addr()
+    }
+
+    fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        self.inner.lock().unwrap().1.set_read_timeout(dur)
+    }
+
+    fn set_write_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        self.inner.lock().unwrap().1.set_write_timeout(dur)
+    }
+
+    fn close(&mut self, how: Shutdown) -> io::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.0.send_close_notify();
+        let (ref mut session, ref mut stream) = *inner;
+        let _ = session.write_tls(stream);
+        stream.close(how)
+    }
+}
+
+impl PeerCertificate for TlsStream {
+    fn peer_certificate(&self) -> Option<Vec<u8>> {
+        let mut inner = self.inner.lock().unwrap();
+        let (ref mut session, ref mut stream) = *inner;
+        // The handshake only happens on the first read or write
+        while session.is_handshaking() {
+            if session.complete_io(stream).is_err() {
+                return None;
+            }
+        }
+        session.get_peer_certificates()
+            .and_then(|certs| certs.into_iter().next())
+            .map(|cert| cert.0)
+    }
+}
+
+impl Ssl for Rustls {
+    type Stream = TlsStream;
+
+    fn wrap_client(&self, stream: HttpStream, host: &str) -> hyper::Result<TlsStream> {
+        let name = try!(DNSNameRef::try_from_ascii_str(host)
+                        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput,
+                                                    format!("Invalid TLS name {}", host))));
+        Ok(TlsStream {
+            inner: Arc::new(Mutex::new((ClientSession::new(&self.config, name), stream))),
+        })
+    }
+
+    fn wrap_server(&self, _: HttpStream) -> hyper::Result<TlsStream> {
+        Err(hyper::Error::from(io::Error::new(io::ErrorKind::Other, "Client only")))
+    }
+}
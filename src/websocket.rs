@@ -0,0 +1,192 @@
+//!
+//! WebSocket echo services, the first message holds the client address
+//!
+//! Only plain `ws://` URLs are supported.
+//!
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+use rand::{thread_rng, Rng};
+use rustc_serialize::base64::{ToBase64, STANDARD};
+use crypto::digest::Digest;
+use crypto::sha1::Sha1;
+use {MyIp, Error, ip_from_str};
+use tcp;
+
+const WEBSOCKET_GUID: &'static str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_CLOSE: u8 = 0x8;
+/// Longest message we are willing to read
+const MAX_PAYLOAD: u64 = 1024;
+
+/// A WebSocket endpoint that sends the client address as its first message
+#[derive(Clone)]
+pub struct WebSocketProvider {
+    pub url: String,
+}
+
+/// Split a ws:// URL into host, port and path
+fn parse_url(url: &str) -> Result<(String, u16, String), Error> {
+    if !url.starts_with("ws://") {
+        return Err(Error::Parse(format!("Unsupported WebSocket URL {}", url)));
+    }
+    let rest = &url["ws://".len()..];
+    let (hostport, path) = match rest.find('/') {
+        Some(pos) => (&rest[..pos], &rest[pos..]),
+        None => (rest, "/"),
+    };
+    // Careful with [::1]:80
+    match hostport.rfind(':') {
+        Some(pos) if !hostport[pos..].contains(']') => {
+            let port = try!(hostport[pos+1..].parse::<u16>()
+                            .map_err(|err| Error::Parse(format!("{}: {}", url, err))));
+            Ok((hostport[..pos].to_owned(), port, path.to_owned()))
+        },
+        _ => Ok((hostport.to_owned(), 80, path.to_owned())),
+    }
+}
+
+fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.input_str(key);
+    sha1.input_str(WEBSOCKET_GUID);
+    let mut digest = [0u8; 20];
+    sha1.result(&mut digest);
+    digest.to_base64(STANDARD)
+}
+
+fn read_exact(stream: &mut TcpStream, buf: &mut [u8]) -> Result<(), Error> {
+    stream.read_exact(buf).map_err(Error::from)
+}
+
+/// Read the handshake response up to the empty line
+fn read_handshake(stream: &mut TcpStream) -> Result<String, Error> {
+    let mut response = Vec::new();
+    let mut byte = [0u8; 1];
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() > 4096 {
+            return Err(Error::Parse("WebSocket handshake response too long".to_owned()));
+        }
+        try!(read_exact(stream, &mut byte));
+        response.push(byte[0]);
+    }
+    Ok(String::from_utf8_lossy(&response).into_owned())
+}
+
+/// Read one frame, returns the opcode and the payload
+fn read_frame(stream: &mut TcpStream) -> Result<(u8, Vec<u8>), Error> {
+    let mut header = [0u8; 2];
+    try!(read_exact(stream, &mut header));
+    let opcode = header[0] & 0x0f;
+    let masked = header[1] & 0x80 != 0;
+    let mut len = (header[1] & 0x7f) as u64;
+    if len == 126 {
+        let mut ext = [0u8; 2];
+        try!(read_exact(stream, &mut ext));
+        len = (ext[0] as u64) << 8 | ext[1] as u64;
+    } else if len == 127 {
+        let mut ext = [0u8; 8];
+        try!(read_exact(stream, &mut ext));
+        len = ext.iter().fold(0, |acc, &b| acc << 8 | b as u64);
+    }
+    if len > MAX_PAYLOAD {
+        return Err(Error::Parse(format!("WebSocket message too long ({} bytes)", len)));
+    }
+
+    let mut mask = [0u8; 4];
+    if masked {
+        try!(read_exact(stream, &mut mask));
+    }
+    let mut payload = vec![0u8; len as usize];
+    try!(read_exact(stream, &mut payload));
+    if masked {
+        for (i, b) in payload.iter_mut().enumerate() {
+            *b ^= mask[i % 4];
+        }
+    }
+    Ok((opcode, payload))
+}
+
+/// Skip control frames until the first text message
+fn read_text(stream: &mut TcpStream) -> Result<String, Error> {
+    loop {
+        let (opcode, payload) = try!(read_frame(stream));
+        match opcode {
+            OPCODE_TEXT => return Ok(String::from_utf8_lossy(&payload).into_owned()),
+            OPCODE_CLOSE => return Err(Error::Parse("WebSocket closed before any message".to_owned())),
+            // Ignore pings and binary frames
+            _ => continue,
+        }
+    }
+}
+
+impl WebSocketProvider {
+    pub fn new(url: &str) -> WebSocketProvider {
+        WebSocketProvider {
+            url: url.to_owned(),
+        }
+    }
+
+    /// Connect, read the first text message and close, `timeout`
+    /// applies to the connection and each read
+    pub fn fetch(&self, timeout: Duration) -> Result<MyIp, Error> {
+        let (host, port, path) = try!(parse_url(&self.url));
+        let mut stream = try!(tcp::connect(host.trim_matches(|c| c == '[' || c == ']'),
+                                           port, timeout)
+                                .map_err(Error::from));
+        try!(stream.set_read_timeout(Some(timeout))
+                .map_err(Error::from));
+
+        let mut nonce = [0u8; 16];
+        thread_rng().fill_bytes(&mut nonce);
+        let key = nonce.to_base64(STANDARD);
+        let req = format!("GET {} HTTP/1.1\r\n\
+                           Host: {}:{}\r\n\
+                           Upgrade: websocket\r\n\
+                           Connection: Upgrade\r\n\
+                           Sec-WebSocket-Key: {}\r\n\
+                           Sec-WebSocket-Version: 13\r\n\r\n", path, host, port, key);
+        try!(stream.write_all(req.as_bytes())
+                .map_err(Error::from));
+
+        let response = try!(read_handshake(&mut stream));
+        if !response.starts_with("HTTP/1.1 101") {
+            return Err(Error::Parse(format!("WebSocket handshake failed: {}",
+                               response.lines().next().unwrap_or(""))));
+        }
+        let accept = accept_key(&key);
+        let accepted = response.lines().any(|line| {
+            let mut parts = line.splitn(2, ':');
+            let name = parts.next().unwrap_or("").trim().to_lowercase();
+            name == "sec-websocket-accept" && parts.next().map(|v| v.trim()) == Some(&accept[..])
+        });
+        if !accepted {
+            return Err(Error::Parse("Invalid Sec-WebSocket-Accept".to_owned()));
+        }
+
+        let res = read_text(&mut stream).and_then(|msg| ip_from_str(&msg));
+
+        // Client frames must be masked, a zero mask will do
+        let _ = stream.write_all(&[0x80 | OPCODE_CLOSE, 0x80, 0, 0, 0, 0]);
+        if let Ok(ref ip) = res {
+            debug!("{} => {}", &self.url, ip);
+        }
+        res
+    }
+}
+
+#[test]
+fn test_accept_key() {
+    // Example from RFC 6455
+    assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+}
+
+#[test]
+fn test_parse_url() {
+    assert_eq!(parse_url("ws://example.com/ip").unwrap(),
+               ("example.com".to_owned(), 80, "/ip".to_owned()));
+    assert_eq!(parse_url("ws://[::1]:8080").unwrap(),
+               ("[::1]".to_owned(), 8080, "/".to_owned()));
+    assert!(parse_url("wss://example.com/").is_err());
+}